@@ -0,0 +1,4 @@
+// Uniform of a camera, the view projection of the scene camera or of a shadow cascade.
+struct CameraProjection {
+    proj: mat4x4<f32>
+}
@@ -0,0 +1,74 @@
+#include "camera.wgsl"
+
+@group(0) @binding(0)
+var<uniform> camera: CameraProjection;
+
+struct VertexIn {
+    @location(0) pos: vec3<f32>,
+    @location(1) color: vec4<f32>,
+#ifdef BARYCENTRIC
+    @location(2) barycentric: vec3<f32>,
+#endif
+}
+
+struct VertexOut {
+    @builtin(position) pos: vec4<f32>,
+    @location(0) color: vec4<f32>,
+    @location(1) world_pos: vec3<f32>,
+    @location(2) view_depth: f32,
+#ifdef BARYCENTRIC
+    @location(3) barycentric: vec3<f32>,
+#endif
+}
+
+@vertex
+fn vs_main(input: VertexIn) -> VertexOut {
+    var v_out: VertexOut;
+    v_out.color = input.color;
+    v_out.pos = camera.proj * vec4<f32>(input.pos, 1.0);
+    v_out.world_pos = input.pos;
+    v_out.view_depth = v_out.pos.w;
+#ifdef BARYCENTRIC
+    v_out.barycentric = input.barycentric;
+#endif
+    return v_out;
+}
+
+// Unlit vertex color, used by the chunk borders.
+@fragment
+fn fs_color(input: VertexOut) -> @location(0) vec4<f32> {
+    return vec4<f32>(input.color.rgb, 1.0);
+}
+
+@fragment
+fn fs_wireframe(input: VertexOut) -> @location(0) vec4<f32> {
+#ifdef BARYCENTRIC
+    // Keeps the fragments within about one pixel of an edge.
+    let width = fwidth(input.barycentric);
+    let edge = smoothstep(vec3<f32>(0.0), width * 1.5, input.barycentric);
+    if (min(edge.x, min(edge.y, edge.z)) > 0.99) {
+        discard;
+    }
+#endif
+    return vec4<f32>(input.color.rgb, 1.0);
+}
+
+// Flat normals from the screen space derivatives, the vertices don't have normals.
+@fragment
+fn fs_normals(input: VertexOut) -> @location(0) vec4<f32> {
+    let normal = normalize(cross(dpdx(input.world_pos), dpdy(input.world_pos)));
+    return vec4<f32>(normal * 0.5 + vec3<f32>(0.5), 1.0);
+}
+
+// Additively blended, each layer of fragments gets brighter from red to yellow to white.
+@fragment
+fn fs_overdraw(input: VertexOut) -> @location(0) vec4<f32> {
+    return vec4<f32>(0.1, 0.04, 0.01, 1.0);
+}
+
+// Linear view depth, white at the camera and black at the far plane.
+@fragment
+fn fs_depth(input: VertexOut) -> @location(0) vec4<f32> {
+    let depth = clamp(input.view_depth / FAR_PLANE, 0.0, 1.0);
+    return vec4<f32>(vec3<f32>(1.0 - depth), 1.0);
+}
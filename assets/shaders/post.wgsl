@@ -0,0 +1,142 @@
+struct PostProcess {
+    exposure: f32,
+    bloom_threshold: f32,
+    bloom_intensity: f32,
+    tone_mapping: u32,
+    gamma: f32,
+    encode_gamma: u32,
+    fxaa: u32,
+}
+
+@group(0) @binding(0)
+var input_a: texture_2d<f32>;
+@group(0) @binding(1)
+var input_b: texture_2d<f32>;
+@group(0) @binding(2)
+var input_sampler: sampler;
+@group(0) @binding(3)
+var<uniform> post: PostProcess;
+
+struct VertexOut {
+    @builtin(position) pos: vec4<f32>,
+    @location(0) uv: vec2<f32>
+}
+
+// A single triangle covering the whole screen.
+@vertex
+fn vs_fullscreen(@builtin(vertex_index) index: u32) -> VertexOut {
+    let uv = vec2<f32>(f32((index << 1u) & 2u), f32(index & 2u));
+    var v_out: VertexOut;
+    v_out.pos = vec4<f32>(uv * vec2<f32>(2.0, -2.0) + vec2<f32>(-1.0, 1.0), 0.0, 1.0);
+    v_out.uv = uv;
+    return v_out;
+}
+
+fn luma(color: vec3<f32>) -> f32 {
+    return dot(color, vec3<f32>(0.299, 0.587, 0.114));
+}
+
+fn sample_a(uv: vec2<f32>) -> vec3<f32> {
+    return textureSampleLevel(input_a, input_sampler, uv, 0.0).rgb;
+}
+
+// Keeps the parts of the image brighter than the bloom threshold.
+@fragment
+fn fs_bright(input: VertexOut) -> @location(0) vec4<f32> {
+    let color = sample_a(input.uv) * post.exposure;
+    let brightness = luma(color);
+    let factor = max(brightness - post.bloom_threshold, 0.0) / max(brightness, 0.0001);
+    return vec4<f32>(color * factor, 1.0);
+}
+
+fn blur(uv: vec2<f32>, direction: vec2<f32>) -> vec4<f32> {
+    var weights = array<f32, 5>(0.227027, 0.1945946, 0.1216216, 0.054054, 0.016216);
+    let texel = direction / vec2<f32>(textureDimensions(input_a));
+    var color = sample_a(uv) * weights[0];
+    for (var i = 1; i < 5; i += 1) {
+        let offset = texel * f32(i);
+        color += sample_a(uv + offset) * weights[i];
+        color += sample_a(uv - offset) * weights[i];
+    }
+    return vec4<f32>(color, 1.0);
+}
+
+@fragment
+fn fs_blur_horizontal(input: VertexOut) -> @location(0) vec4<f32> {
+    return blur(input.uv, vec2<f32>(1.0, 0.0));
+}
+
+@fragment
+fn fs_blur_vertical(input: VertexOut) -> @location(0) vec4<f32> {
+    return blur(input.uv, vec2<f32>(0.0, 1.0));
+}
+
+fn reinhard(color: vec3<f32>) -> vec3<f32> {
+    return color / (vec3<f32>(1.0) + color);
+}
+
+// Krzysztof Narkowicz's fit of the ACES filmic curve.
+fn aces(color: vec3<f32>) -> vec3<f32> {
+    let a = 2.51;
+    let b = 0.03;
+    let c = 2.43;
+    let d = 0.59;
+    let e = 0.14;
+    return clamp((color * (a * color + b)) / (color * (c * color + d) + e), vec3<f32>(0.0), vec3<f32>(1.0));
+}
+
+// input_a is the HDR scene, input_b the blurred bloom.
+@fragment
+fn fs_tonemap(input: VertexOut) -> @location(0) vec4<f32> {
+    let bloom = textureSampleLevel(input_b, input_sampler, input.uv, 0.0).rgb;
+    var color = sample_a(input.uv) * post.exposure + bloom * post.bloom_intensity;
+    switch post.tone_mapping {
+        case 1u: {
+            color = reinhard(color);
+        }
+        case 2u: {
+            color = aces(color);
+        }
+        default: {
+            color = clamp(color, vec3<f32>(0.0), vec3<f32>(1.0));
+        }
+    }
+    if (post.encode_gamma != 0u) {
+        color = pow(color, vec3<f32>(1.0 / post.gamma));
+    }
+    return vec4<f32>(color, 1.0);
+}
+
+// Timothy Lottes' FXAA, console version.
+@fragment
+fn fs_fxaa(input: VertexOut) -> @location(0) vec4<f32> {
+    let uv = input.uv;
+    let color = sample_a(uv);
+    if (post.fxaa == 0u) {
+        return vec4<f32>(color, 1.0);
+    }
+    let texel = 1.0 / vec2<f32>(textureDimensions(input_a));
+    let luma_nw = luma(sample_a(uv + vec2<f32>(-1.0, -1.0) * texel));
+    let luma_ne = luma(sample_a(uv + vec2<f32>(1.0, -1.0) * texel));
+    let luma_sw = luma(sample_a(uv + vec2<f32>(-1.0, 1.0) * texel));
+    let luma_se = luma(sample_a(uv + vec2<f32>(1.0, 1.0) * texel));
+    let luma_m = luma(color);
+    let luma_min = min(luma_m, min(min(luma_nw, luma_ne), min(luma_sw, luma_se)));
+    let luma_max = max(luma_m, max(max(luma_nw, luma_ne), max(luma_sw, luma_se)));
+
+    var dir = vec2<f32>(
+        -((luma_nw + luma_ne) - (luma_sw + luma_se)),
+        (luma_nw + luma_sw) - (luma_ne + luma_se)
+    );
+    let dir_reduce = max((luma_nw + luma_ne + luma_sw + luma_se) * (0.25 / 8.0), 1.0 / 128.0);
+    let rcp_dir_min = 1.0 / (min(abs(dir.x), abs(dir.y)) + dir_reduce);
+    dir = clamp(dir * rcp_dir_min, vec2<f32>(-8.0), vec2<f32>(8.0)) * texel;
+
+    let rgb_a = 0.5 * (sample_a(uv + dir * (1.0 / 3.0 - 0.5)) + sample_a(uv + dir * (2.0 / 3.0 - 0.5)));
+    let rgb_b = rgb_a * 0.5 + 0.25 * (sample_a(uv - dir * 0.5) + sample_a(uv + dir * 0.5));
+    let luma_b = luma(rgb_b);
+    if (luma_b < luma_min || luma_b > luma_max) {
+        return vec4<f32>(rgb_a, 1.0);
+    }
+    return vec4<f32>(rgb_b, 1.0);
+}
@@ -1,18 +1,34 @@
-struct CameraProjection {
-    proj: mat4x4<f32>
-}
+#include "camera.wgsl"
 
 @group(0) @binding(0)
 var<uniform> camera: CameraProjection;
 
+struct Shadow {
+    light_view_proj: array<mat4x4<f32>, MAX_SHADOW_CASCADES>,
+    cascade_splits: vec4<f32>,
+    sun_direction: vec4<f32>,
+    bias: f32,
+    cascade_count: u32,
+    texel_size: f32,
+}
+
+@group(1) @binding(0)
+var<uniform> shadow: Shadow;
+@group(1) @binding(1)
+var shadow_map: texture_depth_2d_array;
+@group(1) @binding(2)
+var shadow_sampler: sampler_comparison;
+
 struct VertexIn {
     @location(0) pos: vec3<f32>,
-    @location(1) color: vec3<f32>
+    @location(1) color: vec4<f32>
 }
 
 struct VertexOut {
     @builtin(position) pos: vec4<f32>,
-    @location(0) color: vec3<f32>
+    @location(0) color: vec4<f32>,
+    @location(1) world_pos: vec3<f32>,
+    @location(2) view_depth: f32
 }
 
 @vertex 
@@ -20,10 +36,52 @@ fn vs_main(input: VertexIn) -> VertexOut {
     var v_out: VertexOut;
     v_out.color = input.color;
     v_out.pos = camera.proj * vec4<f32>(input.pos, 1.0);
+    v_out.world_pos = input.pos;
+    v_out.view_depth = v_out.pos.w;
     return v_out;
 }
 
+// Percentage closer filtering over a 3x3 texel kernel.
+fn sample_shadow(world_pos: vec3<f32>, view_depth: f32) -> f32 {
+    var cascade = 0u;
+    for (var i = 0u; i + 1u < shadow.cascade_count; i += 1u) {
+        if (view_depth > shadow.cascade_splits[i]) {
+            cascade = i + 1u;
+        }
+    }
+    let light_pos = shadow.light_view_proj[cascade] * vec4<f32>(world_pos, 1.0);
+    let ndc = light_pos.xyz / light_pos.w;
+    if (ndc.z > 1.0) {
+        return 1.0;
+    }
+    let uv = ndc.xy * vec2<f32>(0.5, -0.5) + vec2<f32>(0.5, 0.5);
+    let depth = ndc.z - shadow.bias;
+
+    var visibility = 0.0;
+    for (var x = -1; x <= 1; x += 1) {
+        for (var y = -1; y <= 1; y += 1) {
+            let offset = vec2<f32>(f32(x), f32(y)) * shadow.texel_size;
+            visibility += textureSampleCompareLevel(shadow_map, shadow_sampler, uv + offset, i32(cascade), depth);
+        }
+    }
+    return visibility / 9.0;
+}
+
+fn shade(input: VertexOut) -> vec3<f32> {
+    let ambient = 0.3;
+    let visibility = sample_shadow(input.world_pos, input.view_depth);
+    return input.color.rgb * (ambient + (1.0 - ambient) * visibility);
+}
+
 @fragment
 fn fs_main(input: VertexOut) ->  @location(0) vec4<f32> {
-    return vec4<f32>(input.color, 1.0);
-}
\ No newline at end of file
+#ifdef ALPHA_TEST
+    // Foliage is either fully opaque or discarded.
+    if (input.color.a < 0.5) {
+        discard;
+    }
+    return vec4<f32>(shade(input), 1.0);
+#else
+    return vec4<f32>(shade(input), input.color.a);
+#endif
+}
@@ -0,0 +1,31 @@
+#include "camera.wgsl"
+
+// The cascade is rendered like a camera looking from the sun.
+@group(0) @binding(0)
+var<uniform> cascade: CameraProjection;
+
+struct VertexIn {
+    @location(0) pos: vec3<f32>,
+    @location(1) color: vec4<f32>
+}
+
+struct VertexOut {
+    @builtin(position) pos: vec4<f32>,
+    @location(0) alpha: f32
+}
+
+@vertex
+fn vs_main(input: VertexIn) -> VertexOut {
+    var v_out: VertexOut;
+    v_out.pos = cascade.proj * vec4<f32>(input.pos, 1.0);
+    v_out.alpha = input.color.a;
+    return v_out;
+}
+
+// Cutout casters, with the cutoff of the cutout color pass so only the visible texels cast shadows.
+@fragment
+fn fs_alpha_test(input: VertexOut) {
+    if (input.alpha < 0.5) {
+        discard;
+    }
+}
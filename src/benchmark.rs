@@ -0,0 +1,320 @@
+//! Benchmarks started with `--benchmark`: the camera flies along a [CameraPath] with a fixed
+//! timestep, and a report of the frame statistics is written once the path is finished.
+//!
+//! The report is JSON, with the statistics and every frame, or CSV, with the statistics only,
+//! depending on its extension. With `--headless` no window is opened, see [run_headless].
+
+use std::{
+    collections::BTreeMap,
+    fmt::Write,
+    fs,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use serde::Serialize;
+use tracing::{info, span, Level};
+use vek::Vec2;
+
+use crate::{
+    camera::Camera,
+    camera_path::CameraPath,
+    error::{Error, RendererError},
+    profiler::Profiler,
+    renderer::{self, Renderer},
+    settings::Settings,
+};
+
+/// Path time advanced by each frame, so that the same frames are rendered on every machine.
+pub const TIMESTEP: Duration = Duration::from_micros(16_667);
+/// Seed of the world when `--seed` isn't given, so that the runs can be compared.
+pub const DEFAULT_SEED: u64 = 0;
+pub const DEFAULT_REPORT: &str = "benchmark.json";
+/// Size of the headless frames when the settings don't have a window size.
+pub const DEFAULT_SIZE: Vec2<u32> = Vec2::new(1280, 720);
+
+/// Statistics of a frame, the times are in milliseconds.
+#[derive(Debug, Clone, Serialize)]
+pub struct FrameSample {
+    pub frame: u32,
+    /// Time since the previous frame.
+    pub frame_time: f64,
+    /// Time spent recording and submitting the frame.
+    pub cpu_time: f64,
+    /// `None` without timestamp queries, and for the last frames that weren't read back.
+    pub gpu_time: Option<f64>,
+    pub draw_calls: u32,
+    #[serde(skip)]
+    profiler_index: u64,
+}
+
+/// Statistics of a metric over the frames, the percentiles use the nearest rank.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Stats {
+    pub mean: f64,
+    pub min: f64,
+    pub p50: f64,
+    pub p90: f64,
+    pub p95: f64,
+    pub p99: f64,
+    pub max: f64,
+}
+
+impl Stats {
+    /// `None` without values.
+    pub fn new(mut values: Vec<f64>) -> Option<Self> {
+        if values.is_empty() {
+            return None;
+        }
+        values.sort_by(f64::total_cmp);
+        let len = values.len();
+        let percentile = |p: f64| values[((p / 100.0 * len as f64).ceil() as usize).max(1) - 1];
+        Some(Self {
+            mean: values.iter().sum::<f64>() / len as f64,
+            min: values[0],
+            p50: percentile(50.0),
+            p90: percentile(90.0),
+            p95: percentile(95.0),
+            p99: percentile(99.0),
+            max: values[len - 1],
+        })
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct Report {
+    pub adapter: String,
+    pub backend: String,
+    pub resolution: [u32; 2],
+    pub seed: u64,
+    pub camera_path: PathBuf,
+    pub timestep_ms: f64,
+    pub frames: usize,
+    /// Statistics of the metrics of [FrameSample] that have values.
+    pub stats: BTreeMap<&'static str, Stats>,
+    pub samples: Vec<FrameSample>,
+}
+
+impl Report {
+    /// Writes the report as CSV if the extension of `path` is `csv`, as JSON otherwise.
+    pub fn write(&self, path: &Path) -> Result<(), Error> {
+        let contents = if path.extension().is_some_and(|extension| extension == "csv") {
+            self.csv()
+        } else {
+            serde_json::to_string_pretty(self).expect("The report is valid JSON")
+        };
+        fs::write(path, contents).map_err(|source| Error::BenchmarkReport {
+            path: path.to_path_buf(),
+            source,
+        })
+    }
+
+    /// One line per metric.
+    fn csv(&self) -> String {
+        let mut csv = "metric,mean,min,p50,p90,p95,p99,max\n".to_string();
+        for (metric, stats) in &self.stats {
+            let Stats {
+                mean,
+                min,
+                p50,
+                p90,
+                p95,
+                p99,
+                max,
+            } = stats;
+            writeln!(
+                csv,
+                "{},{},{},{},{},{},{},{}",
+                metric, mean, min, p50, p90, p95, p99, max
+            )
+            .unwrap();
+        }
+        csv
+    }
+}
+
+/// A benchmark in progress.
+pub struct Benchmark {
+    path: CameraPath,
+    path_file: PathBuf,
+    report_file: PathBuf,
+    seed: u64,
+    /// Frames started so far.
+    frame: u32,
+    samples: Vec<FrameSample>,
+}
+
+impl Benchmark {
+    pub fn new(path_file: &Path, report_file: &Path, seed: u64) -> Result<Self, Error> {
+        let path = CameraPath::load(path_file)?;
+        info!(path = ?path_file, seed, "Starting the benchmark");
+        Ok(Self {
+            path,
+            path_file: path_file.to_path_buf(),
+            report_file: report_file.to_path_buf(),
+            seed,
+            frame: 0,
+            samples: Vec::new(),
+        })
+    }
+
+    /// Number of frames rendered along the path.
+    pub fn frame_count(&self) -> u32 {
+        ((self.path.end() - self.path.start()) / TIMESTEP.as_secs_f32()).round() as u32 + 1
+    }
+
+    /// Moves the camera to the next frame of the path, returns `false` once it's finished.
+    pub fn update_camera(&mut self, camera: &mut Camera) -> bool {
+        if self.frame >= self.frame_count() {
+            return false;
+        }
+        let time = self.path.start() + self.frame as f32 * TIMESTEP.as_secs_f32();
+        self.path.sample(time).apply(camera);
+        self.frame += 1;
+        true
+    }
+
+    /// Records the statistics of the last frame of the profiler, called after each frame.
+    pub fn record_frame(&mut self, profiler: &Profiler) {
+        let Some(last) = profiler.frames().back() else {
+            return;
+        };
+        // The profiler doesn't record frames while it's paused.
+        if self
+            .samples
+            .last()
+            .is_some_and(|sample| sample.profiler_index >= last.index)
+        {
+            return;
+        }
+        let milliseconds = |duration: Duration| duration.as_secs_f64() * 1000.0;
+        self.samples.push(FrameSample {
+            frame: self.frame - 1,
+            frame_time: milliseconds(last.duration),
+            cpu_time: milliseconds(last.cpu_time),
+            gpu_time: None,
+            draw_calls: last.draw_calls,
+            profiler_index: last.index,
+        });
+        self.update_gpu_times(profiler);
+    }
+
+    /// Sets the GPU times of the frames read back since the last call, a few frames after they
+    /// were recorded.
+    fn update_gpu_times(&mut self, profiler: &Profiler) {
+        let milliseconds = |duration: Duration| duration.as_secs_f64() * 1000.0;
+        for frame in profiler.frames() {
+            let Some(gpu_time) = frame.gpu_time() else {
+                continue;
+            };
+            if let Ok(index) = self
+                .samples
+                .binary_search_by_key(&frame.index, |sample| sample.profiler_index)
+            {
+                self.samples[index].gpu_time = Some(milliseconds(gpu_time));
+            }
+        }
+    }
+
+    /// Writes the report, call [Profiler::wait_for_gpu] first for the GPU times of the last frames.
+    pub fn finish(&mut self, renderer: &Renderer) -> Result<(), Error> {
+        self.update_gpu_times(&renderer.profiler);
+        let metric = |value: fn(&FrameSample) -> Option<f64>| {
+            Stats::new(self.samples.iter().filter_map(value).collect())
+        };
+        let stats = [
+            ("frame_time", metric(|sample| Some(sample.frame_time))),
+            ("cpu_time", metric(|sample| Some(sample.cpu_time))),
+            ("gpu_time", metric(|sample| sample.gpu_time)),
+            (
+                "draw_calls",
+                metric(|sample| Some(sample.draw_calls as f64)),
+            ),
+        ]
+        .into_iter()
+        .filter_map(|(name, stats)| Some((name, stats?)))
+        .collect::<BTreeMap<_, _>>();
+        let adapter = renderer.adapter_info();
+        let report = Report {
+            adapter: adapter.name.clone(),
+            backend: format!("{:?}", adapter.backend),
+            resolution: renderer.resolution.into_array(),
+            seed: self.seed,
+            camera_path: self.path_file.clone(),
+            timestep_ms: TIMESTEP.as_secs_f64() * 1000.0,
+            frames: self.samples.len(),
+            stats,
+            samples: self.samples.clone(),
+        };
+        report.write(&self.report_file)?;
+        info!(
+            path = ?self.report_file,
+            frames = report.frames,
+            frame_time = ?report.stats.get("frame_time"),
+            "Benchmark finished"
+        );
+        Ok(())
+    }
+}
+
+/// Runs the benchmark without a window, rendering to an offscreen texture.
+pub fn run_headless(mut benchmark: Benchmark, settings: &Settings) -> Result<(), Error> {
+    let size = settings.window.size.map_or(DEFAULT_SIZE, Vec2::from);
+    let mut renderer = Renderer::headless(size, settings.graphics.clone())?;
+    renderer.camera.fov = settings.camera.fov;
+    renderer.apply_settings(&settings.renderer);
+    while benchmark.update_camera(&mut renderer.camera) {
+        renderer.upload_camera();
+        let span = span!(Level::INFO, "Render");
+        let _guard = span.enter();
+        if !renderer::catch_device_loss(|| render_frame(&mut renderer))?? {
+            continue;
+        }
+        if renderer.device_lost() {
+            return Err(RendererError::DeviceLost.into());
+        }
+        benchmark.record_frame(&renderer.profiler);
+    }
+    // The timestamps of the last frames are still being read back.
+    renderer::catch_device_loss(|| renderer.profiler.wait_for_gpu(&renderer.device))?;
+    benchmark.finish(&renderer)
+}
+
+/// Renders a frame without UI and waits for it, returns whether it was rendered.
+fn render_frame(renderer: &mut Renderer) -> Result<bool, RendererError> {
+    let Some(frame) = renderer.acquire_frame()? else {
+        return Ok(false);
+    };
+    let mut encoder = renderer
+        .device
+        .create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("Encoder: Frame Main"),
+        });
+    renderer.start_frame(&mut encoder, &frame, None);
+    renderer.queue.submit(std::iter::once(encoder.finish()));
+    frame.present();
+    renderer.finish_frame();
+    // Waits for the GPU like presenting with vsync does, instead of queuing every frame.
+    renderer.device.poll(wgpu::Maintain::Wait);
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn computes_nearest_rank_percentiles() {
+        let stats = Stats::new((1..=100).map(f64::from).rev().collect()).unwrap();
+        assert_eq!(stats.min, 1.0);
+        assert_eq!(stats.p50, 50.0);
+        assert_eq!(stats.p90, 90.0);
+        assert_eq!(stats.p99, 99.0);
+        assert_eq!(stats.max, 100.0);
+        assert_eq!(stats.mean, 50.5);
+
+        let stats = Stats::new(vec![4.0]).unwrap();
+        assert_eq!((stats.p50, stats.p99), (4.0, 4.0));
+        assert_eq!(Stats::new(Vec::new()), None);
+    }
+}
@@ -52,7 +52,6 @@ impl<T: Pod> Buffer<T> {
     /// Returns the debug label of this Buffer
     fn label() -> &'static str {
         //TODO: Possibly find a better name for this
-        let type_name = std::any::type_name::<T>();
-        type_name
+        std::any::type_name::<T>()
     }
 }
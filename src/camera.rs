@@ -1,9 +1,15 @@
-use vek::{Mat4, Vec3};
-use winit::event::VirtualKeyCode;
+use vek::{Mat4, Vec3, Vec4};
 
 pub const DEFAULT_VERTICAL_FOV: f32 = 45.0;
+/// Distance moved per key press or mouse motion unit.
+pub const DEFAULT_SPEED: f32 = 0.1;
+/// Distance to the near clipping plane.
+pub const NEAR_PLANE: f32 = 0.1;
+/// Distance to the far clipping plane, also used as the view distance.
+pub const FAR_PLANE: f32 = 100.0;
 
 /// Fly style camera that allows to freely move around in a 3D scene.
+#[derive(Clone)]
 pub struct Camera {
     /// Field Of View in radians
     pub fov: f32,
@@ -19,36 +25,51 @@ impl Camera {
             fov: DEFAULT_VERTICAL_FOV,
             eye,
             target,
-            speed: 0.1,
+            speed: DEFAULT_SPEED,
             up: Vec3::unit_y(),
         }
     }
     pub fn build_mvp(&self, width: f32, height: f32) -> Mat4<f32> {
         let model = Mat4::translation_3d(Vec3::new(0.0, 0.0, 0.0));
-        let projection: Mat4<f32> =
-            Mat4::perspective_fov_lh_zo(self.fov.to_radians(), width, height, 0.1, 100.0);
-        let view: Mat4<f32> = Mat4::look_at_lh(self.eye, self.target, self.up);
-        return projection * view * model;
+        self.projection(width, height, NEAR_PLANE, FAR_PLANE) * self.view() * model
     }
 
-    pub fn on_key_pressed(&mut self, keycode: &VirtualKeyCode) {
+    pub fn view(&self) -> Mat4<f32> {
+        Mat4::look_at_lh(self.eye, self.target, self.up)
+    }
+
+    pub fn projection(&self, width: f32, height: f32, near: f32, far: f32) -> Mat4<f32> {
+        Mat4::perspective_fov_lh_zo(self.fov.to_radians(), width, height, near, far)
+    }
+
+    /// Returns the world space corners of the view frustum slice between `near` and `far`.
+    pub fn frustum_corners(&self, width: f32, height: f32, near: f32, far: f32) -> [Vec3<f32>; 8] {
+        let inverse = (self.projection(width, height, near, far) * self.view()).inverted();
+        let mut corners = [Vec3::zero(); 8];
+        for (i, corner) in corners.iter_mut().enumerate() {
+            let x = if i & 1 == 0 { -1.0 } else { 1.0 };
+            let y = if i & 2 == 0 { -1.0 } else { 1.0 };
+            let z = if i & 4 == 0 { 0.0 } else { 1.0 };
+            let world = inverse * Vec4::new(x, y, z, 1.0);
+            *corner = world.xyz() / world.w;
+        }
+        corners
+    }
+
+    pub fn on_movement(&mut self, movement: Movement) {
+        match movement {
+            Movement::Forward => self.move_by(0.0, 1.0),
+            Movement::Backward => self.move_by(0.0, -1.0),
+            Movement::Right => self.move_by(1.0, 0.0),
+            Movement::Left => self.move_by(-1.0, 0.0),
+        }
+    }
+
+    /// Moves by `right` and `forward` steps of [Camera::speed], e.g. from a gamepad stick.
+    pub fn move_by(&mut self, right: f32, forward: f32) {
         let forward_vec_normal = (self.target - self.eye).normalized();
         let right_vec_normal = self.up.cross(forward_vec_normal).normalized();
-        match keycode {
-            VirtualKeyCode::W | VirtualKeyCode::Up => {
-                self.eye += forward_vec_normal * self.speed;
-            }
-            VirtualKeyCode::S | VirtualKeyCode::Down => {
-                self.eye -= forward_vec_normal * self.speed;
-            }
-            VirtualKeyCode::D | VirtualKeyCode::Right => {
-                self.eye += right_vec_normal * self.speed;
-            }
-            VirtualKeyCode::A | VirtualKeyCode::Left => {
-                self.eye -= right_vec_normal * self.speed;
-            }
-            _ => (),
-        }
+        self.eye += (forward_vec_normal * forward + right_vec_normal * right) * self.speed;
     }
     pub fn on_mouse_input(&mut self, dx: f64, dy: f64) {
         let v: Vec3<f32> = Vec3::new(dx as f32 * self.speed, dy as f32 * self.speed, 0.0);
@@ -56,6 +77,15 @@ impl Camera {
     }
 }
 
+/// A step of the camera, bound to keys by [crate::settings::KeyBindings].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Movement {
+    Forward,
+    Backward,
+    Left,
+    Right,
+}
+
 // We need this for Rust to store our data correctly for the shaders
 #[repr(C)]
 // This is so we can store this in a buffer
@@ -0,0 +1,451 @@
+//! Camera paths, keyframes of the camera played back by benchmarks and edited from the
+//! "Camera Path" window, e.g.
+//!
+//! ```toml
+//! [[keyframes]]
+//! time = 0.0
+//! eye = [0.0, 1.0, -5.0]
+//! target = [0.0, 0.0, 0.0]
+//!
+//! [[keyframes]]
+//! time = 4.0
+//! eye = [5.0, 2.0, 0.0]
+//! target = [0.0, 0.0, 0.0]
+//! fov = 60.0
+//! ```
+//!
+//! The eye follows a Catmull-Rom spline, the view direction is slerped between the keyframes,
+//! and the distance to the target and the field of view are interpolated linearly.
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    time::Instant,
+};
+
+use serde::{Deserialize, Serialize};
+use tracing::{info, warn};
+use vek::{Quaternion, Rgba, Vec3};
+
+use crate::{
+    camera::{Camera, DEFAULT_VERTICAL_FOV},
+    debug_draw,
+    error::{self, Error},
+};
+
+/// File of the "Camera Path" window until another one is loaded.
+pub const DEFAULT_FILE: &str = "camera_path.toml";
+/// Time the playhead moves forward after adding a keyframe, so the next one is added after it.
+pub const KEYFRAME_SPACING: f32 = 2.0;
+/// Segments of the path drawn per second while the editor is open.
+const PREVIEW_SEGMENTS: f32 = 10.0;
+
+#[derive(Debug, Clone, Default, PartialEq, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct CameraPath {
+    /// Sorted by time, there is at least one in the loaded paths.
+    pub keyframes: Vec<Keyframe>,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct Keyframe {
+    /// Seconds since the start of the path.
+    pub time: f32,
+    pub eye: [f32; 3],
+    pub target: [f32; 3],
+    /// Vertical field of view in degrees.
+    #[serde(default = "default_fov")]
+    pub fov: f32,
+}
+
+fn default_fov() -> f32 {
+    DEFAULT_VERTICAL_FOV
+}
+
+impl Keyframe {
+    /// The current view of `camera`.
+    pub fn from_camera(time: f32, camera: &Camera) -> Self {
+        Self {
+            time,
+            eye: camera.eye.into_array(),
+            target: camera.target.into_array(),
+            fov: camera.fov,
+        }
+    }
+
+    fn pose(&self) -> Pose {
+        Pose {
+            eye: self.eye.into(),
+            target: self.target.into(),
+            fov: self.fov,
+        }
+    }
+
+    /// The unit direction from the eye to the target, and their distance.
+    fn direction(&self) -> (Vec3<f32>, f32) {
+        let offset = Vec3::from(self.target) - Vec3::from(self.eye);
+        let distance = offset.magnitude();
+        if distance > f32::EPSILON {
+            (offset / distance, distance)
+        } else {
+            (Vec3::unit_z(), 0.0)
+        }
+    }
+}
+
+/// The view of the camera at a point of a [CameraPath].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Pose {
+    pub eye: Vec3<f32>,
+    pub target: Vec3<f32>,
+    pub fov: f32,
+}
+
+impl Pose {
+    pub fn apply(&self, camera: &mut Camera) {
+        camera.eye = self.eye;
+        camera.target = self.target;
+        camera.fov = self.fov;
+    }
+}
+
+impl CameraPath {
+    pub fn load(path: &Path) -> Result<Self, Error> {
+        let error = |message: String| Error::CameraPath {
+            path: path.to_path_buf(),
+            message,
+        };
+        let source = fs::read_to_string(path).map_err(|e| error(e.to_string()))?;
+        let camera_path: Self = toml::from_str(&source).map_err(|e| error(e.to_string()))?;
+        camera_path.validate().map_err(error)?;
+        Ok(camera_path)
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), Error> {
+        let error = |message: String| Error::CameraPath {
+            path: path.to_path_buf(),
+            message,
+        };
+        self.validate().map_err(error)?;
+        let contents = toml::to_string_pretty(self).map_err(|e| error(e.to_string()))?;
+        fs::write(path, contents).map_err(|e| error(e.to_string()))
+    }
+
+    fn validate(&self) -> Result<(), String> {
+        if self.keyframes.is_empty() {
+            return Err("a camera path needs at least one keyframe".to_string());
+        }
+        if let Some(pair) = self
+            .keyframes
+            .windows(2)
+            .find(|pair| pair[0].time >= pair[1].time)
+        {
+            return Err(format!(
+                "the keyframe at {}s must come after the one at {}s",
+                pair[1].time, pair[0].time
+            ));
+        }
+        Ok(())
+    }
+
+    /// Time of the first keyframe.
+    pub fn start(&self) -> f32 {
+        self.keyframes[0].time
+    }
+
+    /// Time of the last keyframe.
+    pub fn end(&self) -> f32 {
+        self.keyframes[self.keyframes.len() - 1].time
+    }
+
+    /// Inserts `keyframe` in order, replacing the keyframe at the same time if there is one.
+    /// Returns its index.
+    pub fn insert(&mut self, keyframe: Keyframe) -> usize {
+        match self
+            .keyframes
+            .binary_search_by(|other| other.time.total_cmp(&keyframe.time))
+        {
+            Ok(index) => {
+                self.keyframes[index] = keyframe;
+                index
+            }
+            Err(index) => {
+                self.keyframes.insert(index, keyframe);
+                index
+            }
+        }
+    }
+
+    /// The view at `time`, clamped to the path. The path must have a keyframe.
+    pub fn sample(&self, time: f32) -> Pose {
+        let time = time.clamp(self.start(), self.end());
+        let next = self
+            .keyframes
+            .iter()
+            .position(|keyframe| keyframe.time > time)
+            .unwrap_or(self.keyframes.len() - 1);
+        if next == 0 {
+            // A single keyframe.
+            return self.keyframes[0].pose();
+        }
+        let (k1, k2) = (&self.keyframes[next - 1], &self.keyframes[next]);
+        // The missing neighbours of the first and last segments mirror their other end.
+        let k0 = self.keyframes.get(next.wrapping_sub(2)).unwrap_or(k1);
+        let k3 = self.keyframes.get(next + 1).unwrap_or(k2);
+        let segment = [k0, k1, k2, k3];
+        let eye = catmull_rom(
+            segment.map(|keyframe| Vec3::from(keyframe.eye)),
+            segment.map(|keyframe| keyframe.time),
+            time,
+        );
+        let s = (time - k1.time) / (k2.time - k1.time);
+        let ((d1, distance1), (d2, distance2)) = (k1.direction(), k2.direction());
+        let rotation = Quaternion::rotation_from_to_3d(d1, d2);
+        let direction = Quaternion::slerp(Quaternion::identity(), rotation, s) * d1;
+        Pose {
+            eye,
+            target: eye + direction * (distance1 + (distance2 - distance1) * s),
+            fov: k1.fov + (k2.fov - k1.fov) * s,
+        }
+    }
+}
+
+/// The path edited in the "Camera Path" window, and its playback.
+pub struct PathEditor {
+    pub open: bool,
+    /// Can be empty while editing.
+    pub path: CameraPath,
+    /// File the path is loaded from and saved to.
+    pub file: String,
+    /// Time of the playhead.
+    pub time: f32,
+    /// When the playback last moved the camera, `None` when paused.
+    playing: Option<Instant>,
+}
+
+impl PathEditor {
+    pub fn new() -> Self {
+        Self {
+            open: false,
+            path: CameraPath::default(),
+            file: DEFAULT_FILE.to_string(),
+            time: 0.0,
+            playing: None,
+        }
+    }
+
+    /// Time of the last keyframe, 0 without keyframes.
+    pub fn duration(&self) -> f32 {
+        self.path
+            .keyframes
+            .last()
+            .map_or(0.0, |keyframe| keyframe.time)
+    }
+
+    pub fn is_playing(&self) -> bool {
+        self.playing.is_some()
+    }
+
+    /// Plays from the playhead, or from the start once it reached the end.
+    pub fn play(&mut self) {
+        if self.path.keyframes.is_empty() {
+            return;
+        }
+        if self.time >= self.duration() {
+            self.time = self.path.start();
+        }
+        self.playing = Some(Instant::now());
+    }
+
+    pub fn pause(&mut self) {
+        self.playing = None;
+    }
+
+    /// Moves the playhead to `time` and the camera to the view at that time.
+    pub fn seek(&mut self, time: f32, camera: &mut Camera) {
+        self.time = time.max(0.0);
+        if !self.path.keyframes.is_empty() {
+            self.path.sample(self.time).apply(camera);
+        }
+    }
+
+    /// Adds the current view of `camera` at the playhead, then moves the playhead forward.
+    pub fn add_keyframe(&mut self, camera: &Camera) {
+        self.path.insert(Keyframe::from_camera(self.time, camera));
+        self.time += KEYFRAME_SPACING;
+    }
+
+    pub fn remove_keyframe(&mut self, index: usize) {
+        self.path.keyframes.remove(index);
+        if self.path.keyframes.is_empty() {
+            self.pause();
+        }
+    }
+
+    /// Advances the playback, moving `camera`. Returns whether the camera moved.
+    pub fn update(&mut self, camera: &mut Camera) -> bool {
+        let Some(last) = self.playing else {
+            return false;
+        };
+        let now = Instant::now();
+        let time = self.time + (now - last).as_secs_f32();
+        if time >= self.duration() {
+            self.pause();
+        } else {
+            self.playing = Some(now);
+        }
+        self.seek(time.min(self.duration()), camera);
+        true
+    }
+
+    /// Loads [Self::file].
+    pub fn load(&mut self) -> Result<(), Error> {
+        let file = PathBuf::from(&self.file);
+        let path = CameraPath::load(&file)?;
+        info!(path = ?file, keyframes = path.keyframes.len(), "Loaded the camera path");
+        self.set_path(&file, path);
+        Ok(())
+    }
+
+    /// Replaces the path with `path`, loaded from `file`, with the playhead at its start.
+    pub fn set_path(&mut self, file: &Path, path: CameraPath) {
+        self.file = file.display().to_string();
+        self.time = path.start();
+        self.path = path;
+        self.pause();
+    }
+
+    /// Saves to [Self::file], logging the result.
+    pub fn save(&self) {
+        let file = PathBuf::from(&self.file);
+        match self.path.save(&file) {
+            Ok(()) => info!(path = ?file, "Saved the camera path"),
+            Err(error) => warn!(
+                path = ?file,
+                error = error::report(&error),
+                "Failed to save the camera path"
+            ),
+        }
+    }
+
+    /// Draws the path and its keyframes in the scene.
+    pub fn draw_preview(&self) {
+        if self.path.keyframes.len() < 2 {
+            return;
+        }
+        let (start, end) = (self.path.start(), self.path.end());
+        let segments = ((end - start) * PREVIEW_SEGMENTS).ceil().max(1.0) as usize;
+        let point = |i: usize| {
+            let time = start + (end - start) * i as f32 / segments as f32;
+            self.path.sample(time).eye
+        };
+        for i in 0..segments {
+            debug_draw::line(point(i), point(i + 1), Rgba::yellow());
+        }
+        for keyframe in &self.path.keyframes {
+            let pose = keyframe.pose();
+            debug_draw::sphere(pose.eye, 0.1, Rgba::yellow());
+            let (direction, _) = keyframe.direction();
+            debug_draw::line(pose.eye, pose.eye + direction * 0.5, Rgba::magenta());
+        }
+    }
+}
+
+/// Interpolates between `points[1]` and `points[2]` with tangents from their neighbours,
+/// scaled by the keyframe times so that the speed is continuous across segments.
+fn catmull_rom(points: [Vec3<f32>; 4], times: [f32; 4], time: f32) -> Vec3<f32> {
+    let [p0, p1, p2, p3] = points;
+    let [t0, t1, t2, t3] = times;
+    let duration = t2 - t1;
+    let tangent = |before: Vec3<f32>, after: Vec3<f32>, span: f32| {
+        if span > 0.0 {
+            (after - before) / span * duration
+        } else {
+            after - before
+        }
+    };
+    let m1 = tangent(p0, p2, t2 - t0);
+    let m2 = tangent(p1, p3, t3 - t1);
+    let s = (time - t1) / duration;
+    let (s2, s3) = (s * s, s * s * s);
+    p1 * (2.0 * s3 - 3.0 * s2 + 1.0)
+        + m1 * (s3 - 2.0 * s2 + s)
+        + p2 * (-2.0 * s3 + 3.0 * s2)
+        + m2 * (s3 - s2)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn keyframe(time: f32, eye: [f32; 3]) -> Keyframe {
+        Keyframe {
+            time,
+            eye,
+            target: [0.0; 3],
+            fov: DEFAULT_VERTICAL_FOV,
+        }
+    }
+
+    #[test]
+    fn passes_through_the_keyframes() {
+        let path = CameraPath {
+            keyframes: vec![
+                keyframe(0.0, [0.0, 0.0, 0.0]),
+                keyframe(1.0, [1.0, 2.0, 0.0]),
+                keyframe(3.0, [4.0, 0.0, 1.0]),
+            ],
+        };
+        for keyframe in &path.keyframes {
+            let pose = path.sample(keyframe.time);
+            assert!((pose.eye - Vec3::from(keyframe.eye)).magnitude() < 1e-5);
+            assert!(pose.target.magnitude() < 1e-5, "{:?}", pose.target);
+        }
+        // Clamped outside of the path.
+        assert_eq!(path.sample(-1.0).eye, Vec3::zero());
+        assert_eq!(path.sample(10.0).eye, Vec3::new(4.0, 0.0, 1.0));
+    }
+
+    #[test]
+    fn moves_at_constant_speed_on_evenly_spaced_keyframes() {
+        let path = CameraPath {
+            keyframes: (0..4)
+                .map(|i| keyframe(i as f32, [i as f32 * 2.0, 0.0, 0.0]))
+                .collect(),
+        };
+        for time in [0.5, 1.25, 2.75] {
+            let eye = path.sample(time).eye;
+            assert!((eye.x - time * 2.0).abs() < 1e-5, "{} at {}", eye.x, time);
+        }
+    }
+
+    #[test]
+    fn slerps_the_view_direction() {
+        let mut path = CameraPath::default();
+        for (time, target, fov) in [(0.0, [2.0, 0.0, 0.0], 40.0), (1.0, [0.0, 0.0, 4.0], 60.0)] {
+            path.insert(Keyframe {
+                target,
+                fov,
+                ..keyframe(time, [0.0; 3])
+            });
+        }
+        let pose = path.sample(0.5);
+        let expected = Vec3::new(1.0, 0.0, 1.0).normalized() * 3.0;
+        assert!(
+            (pose.target - expected).magnitude() < 1e-5,
+            "{:?}",
+            pose.target
+        );
+        assert_eq!(pose.fov, 50.0);
+    }
+
+    #[test]
+    fn rejects_unordered_keyframes() {
+        let path = CameraPath {
+            keyframes: vec![keyframe(1.0, [0.0; 3]), keyframe(1.0, [1.0; 3])],
+        };
+        assert!(path.validate().is_err());
+        assert!(CameraPath::default().validate().is_err());
+    }
+}
@@ -0,0 +1,365 @@
+//! Screenshots, taken with F2 or from the "Capture" window, and frame sequences for videos.
+//!
+//! A capture renders the frame again without the UI into a texture that can be copied, instead
+//! of copying the surface: wgpu 0.14 can't tell whether a surface supports `COPY_SRC`, and the
+//! GL and Metal surfaces don't. The texture is copied to a buffer read back with `map_async`, a
+//! few frames later, and the PNG files are encoded and written by a thread so that the frames
+//! aren't stalled.
+
+use std::{
+    fs::{self, File},
+    io::BufWriter,
+    num::NonZeroU32,
+    path::{Path, PathBuf},
+    sync::{
+        mpsc::{self, Receiver, SyncSender},
+        Arc, Mutex,
+    },
+    thread::{self, JoinHandle},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use tracing::{debug, info, span, warn, Level};
+use vek::Vec2;
+
+use crate::{
+    error::{self, Error},
+    renderer::Renderer,
+};
+
+/// Directory of the screenshots and of the frame sequences.
+pub const CAPTURE_DIR: &str = "screenshots";
+/// Largest multiple of the window size screenshots can be rendered at.
+pub const MAX_SCALE: u32 = 4;
+/// Frames read back but not written yet before the frames wait for the writer.
+const WRITE_QUEUE: usize = 8;
+
+/// Screenshots requested and frame sequences being recorded, and their readbacks.
+pub struct Capture {
+    pub open: bool,
+    /// Multiple of the window size screenshots are rendered at.
+    pub scale: u32,
+    screenshot_requested: bool,
+    sequence: Option<Sequence>,
+    /// Copies of the frames waiting to be mapped, oldest first.
+    readbacks: Vec<Readback>,
+    /// `None` once finished.
+    writer: Option<SyncSender<Image>>,
+    writer_thread: Option<JoinHandle<()>>,
+}
+
+/// Frames written to a directory, numbered from 0.
+struct Sequence {
+    dir: PathBuf,
+    frame: u32,
+}
+
+/// A frame copied to a buffer.
+struct Readback {
+    buffer: wgpu::Buffer,
+    size: Vec2<u32>,
+    padded_bytes_per_row: u32,
+    bgra: bool,
+    path: PathBuf,
+    /// Set once `map_async` completed.
+    mapped: Arc<Mutex<Option<Result<(), wgpu::BufferAsyncError>>>>,
+}
+
+/// Tightly packed RGBA pixels written to `path`.
+struct Image {
+    path: PathBuf,
+    size: Vec2<u32>,
+    pixels: Vec<u8>,
+}
+
+impl Capture {
+    pub fn new() -> Self {
+        let (writer, images) = mpsc::sync_channel(WRITE_QUEUE);
+        let writer_thread = thread::Builder::new()
+            .name("Capture Writer".to_string())
+            .spawn(move || write_images(images))
+            .map_err(|error| warn!(%error, "Failed to start the capture writer"))
+            .ok();
+        Self {
+            open: false,
+            scale: 1,
+            screenshot_requested: false,
+            sequence: None,
+            readbacks: Vec::new(),
+            writer: writer_thread.is_some().then_some(writer),
+            writer_thread,
+        }
+    }
+
+    /// Takes a screenshot at the end of the frame.
+    pub fn request_screenshot(&mut self) {
+        self.screenshot_requested = true;
+    }
+
+    pub fn is_recording(&self) -> bool {
+        self.sequence.is_some()
+    }
+
+    /// Frames recorded by the current sequence.
+    pub fn recorded_frames(&self) -> u32 {
+        self.sequence.as_ref().map_or(0, |sequence| sequence.frame)
+    }
+
+    /// Frames copied but not read back yet.
+    pub fn pending(&self) -> usize {
+        self.readbacks.len()
+    }
+
+    /// Records every frame to a new directory of [CAPTURE_DIR].
+    pub fn start_recording(&mut self) {
+        let dir = Path::new(CAPTURE_DIR).join(format!("sequence_{}", timestamp()));
+        if let Err(error) = fs::create_dir_all(&dir) {
+            warn!(?dir, %error, "Failed to create the frame sequence directory");
+            return;
+        }
+        info!(?dir, "Recording the frames");
+        self.sequence = Some(Sequence { dir, frame: 0 });
+    }
+
+    pub fn stop_recording(&mut self) {
+        if let Some(sequence) = self.sequence.take() {
+            info!(dir = ?sequence.dir, frames = sequence.frame, "Stopped recording the frames");
+        }
+    }
+
+    /// Captures the frame that was just rendered if requested, and writes the frames that were
+    /// read back. Called after each frame.
+    pub fn update(&mut self, renderer: &mut Renderer) {
+        let span = span!(Level::INFO, "Capture");
+        let _guard = span.enter();
+        if std::mem::take(&mut self.screenshot_requested) {
+            match fs::create_dir_all(CAPTURE_DIR) {
+                Ok(()) => {
+                    let path =
+                        Path::new(CAPTURE_DIR).join(format!("screenshot_{}.png", timestamp()));
+                    info!(?path, scale = self.scale, "Taking a screenshot");
+                    self.capture(renderer, self.scale, path);
+                }
+                Err(error) => {
+                    warn!(dir = CAPTURE_DIR, %error, "Failed to create the screenshot directory")
+                }
+            }
+        }
+        if let Some(sequence) = &mut self.sequence {
+            let path = sequence
+                .dir
+                .join(format!("frame_{:06}.png", sequence.frame));
+            sequence.frame += 1;
+            self.capture(renderer, 1, path);
+        }
+        renderer.device.poll(wgpu::Maintain::Poll);
+        self.write_mapped();
+    }
+
+    /// Waits for the frames being read back and writes them, e.g. before the device is dropped.
+    pub fn finish(&mut self, device: &wgpu::Device) {
+        if self.readbacks.is_empty() {
+            return;
+        }
+        device.poll(wgpu::Maintain::Wait);
+        self.write_mapped();
+    }
+
+    /// Renders the frame again at `scale` times the resolution and copies it to a buffer.
+    fn capture(&mut self, renderer: &mut Renderer, scale: u32, path: PathBuf) {
+        let format = renderer.surface_config.format;
+        let bgra = match format {
+            wgpu::TextureFormat::Bgra8Unorm | wgpu::TextureFormat::Bgra8UnormSrgb => true,
+            wgpu::TextureFormat::Rgba8Unorm | wgpu::TextureFormat::Rgba8UnormSrgb => false,
+            _ => {
+                warn!(
+                    ?format,
+                    "Frames can't be captured in the format of the surface"
+                );
+                return;
+            }
+        };
+        let config = &renderer.surface_config;
+        let window = Vec2::new(config.width, config.height);
+        // Scaled down to the largest size the device supports.
+        let max = renderer.device.limits().max_texture_dimension_2d;
+        let scale = scale.clamp(1, (max / window.reduce_max()).max(1));
+        let size = window * scale;
+
+        let mut encoder = renderer
+            .device
+            .create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                label: Some("Encoder: Capture"),
+            });
+        let texture = renderer.render_copyable(&mut encoder, size);
+        let padded_bytes_per_row = padded_bytes_per_row(size.x);
+        let buffer = renderer.device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Capture Readback"),
+            size: padded_bytes_per_row as u64 * size.y as u64,
+            usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        encoder.copy_texture_to_buffer(
+            texture.as_image_copy(),
+            wgpu::ImageCopyBuffer {
+                buffer: &buffer,
+                layout: wgpu::ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: NonZeroU32::new(padded_bytes_per_row),
+                    rows_per_image: None,
+                },
+            },
+            wgpu::Extent3d {
+                width: size.x,
+                height: size.y,
+                depth_or_array_layers: 1,
+            },
+        );
+        renderer.queue.submit(std::iter::once(encoder.finish()));
+
+        let mapped = Arc::new(Mutex::new(None));
+        let callback = Arc::clone(&mapped);
+        buffer
+            .slice(..)
+            .map_async(wgpu::MapMode::Read, move |result| {
+                *callback.lock().unwrap() = Some(result);
+            });
+        self.readbacks.push(Readback {
+            buffer,
+            size,
+            padded_bytes_per_row,
+            bgra,
+            path,
+            mapped,
+        });
+    }
+
+    /// Sends the readbacks that were mapped to the writer, in order.
+    fn write_mapped(&mut self) {
+        while let Some(readback) = self.readbacks.first() {
+            let Some(result) = readback.mapped.lock().unwrap().take() else {
+                break;
+            };
+            let readback = self.readbacks.remove(0);
+            if let Err(error) = result {
+                warn!(path = ?readback.path, %error, "Failed to read back the captured frame");
+                continue;
+            }
+            let pixels = unpad_rows(
+                &readback.buffer.slice(..).get_mapped_range(),
+                readback.size,
+                readback.padded_bytes_per_row,
+                readback.bgra,
+            );
+            readback.buffer.unmap();
+            let image = Image {
+                path: readback.path,
+                size: readback.size,
+                pixels,
+            };
+            let Some(writer) = &self.writer else {
+                warn!(path = ?image.path, "The capture writer isn't running");
+                continue;
+            };
+            if writer.send(image).is_err() {
+                warn!("The capture writer stopped");
+                self.writer = None;
+            }
+        }
+    }
+}
+
+impl Drop for Capture {
+    /// Waits for the images being written.
+    fn drop(&mut self) {
+        self.writer = None;
+        if let Some(thread) = self.writer_thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+/// Writes the images received until the [Capture] is dropped.
+fn write_images(images: Receiver<Image>) {
+    for image in images {
+        match write_png(&image) {
+            Ok(()) => debug!(path = ?image.path, "Saved the capture"),
+            Err(error) => warn!(error = error::report(&error), "Failed to save the capture"),
+        }
+    }
+}
+
+fn write_png(image: &Image) -> Result<(), Error> {
+    let error = |message: String| Error::Capture {
+        path: image.path.clone(),
+        message,
+    };
+    let file = File::create(&image.path).map_err(|e| error(e.to_string()))?;
+    let mut encoder = png::Encoder::new(BufWriter::new(file), image.size.x, image.size.y);
+    encoder.set_color(png::ColorType::Rgba);
+    encoder.set_depth(png::BitDepth::Eight);
+    let mut writer = encoder.write_header().map_err(|e| error(e.to_string()))?;
+    writer
+        .write_image_data(&image.pixels)
+        .map_err(|e| error(e.to_string()))?;
+    writer.finish().map_err(|e| error(e.to_string()))
+}
+
+/// Rows of a texture copied to a buffer are aligned to [wgpu::COPY_BYTES_PER_ROW_ALIGNMENT].
+fn padded_bytes_per_row(width: u32) -> u32 {
+    (width * 4).div_ceil(wgpu::COPY_BYTES_PER_ROW_ALIGNMENT) * wgpu::COPY_BYTES_PER_ROW_ALIGNMENT
+}
+
+/// Packs the rows of a readback into RGBA pixels, the alpha is made opaque since the surface
+/// alpha isn't meaningful.
+fn unpad_rows(data: &[u8], size: Vec2<u32>, padded_bytes_per_row: u32, bgra: bool) -> Vec<u8> {
+    let row_len = size.x as usize * 4;
+    let mut pixels = Vec::with_capacity(row_len * size.y as usize);
+    for row in data
+        .chunks(padded_bytes_per_row as usize)
+        .take(size.y as usize)
+    {
+        for pixel in row[..row_len].chunks_exact(4) {
+            let [r, g, b] = if bgra {
+                [pixel[2], pixel[1], pixel[0]]
+            } else {
+                [pixel[0], pixel[1], pixel[2]]
+            };
+            pixels.extend_from_slice(&[r, g, b, u8::MAX]);
+        }
+    }
+    pixels
+}
+
+/// Milliseconds since the Unix epoch, naming the captures.
+fn timestamp() -> u128 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn removes_the_row_padding() {
+        let size = Vec2::new(3, 2);
+        let padded = padded_bytes_per_row(size.x);
+        assert_eq!(padded, wgpu::COPY_BYTES_PER_ROW_ALIGNMENT);
+        let mut data = vec![0xAA; (padded * size.y) as usize];
+        for y in 0..2u8 {
+            for x in 0..3u8 {
+                let offset = y as usize * padded as usize + x as usize * 4;
+                data[offset..offset + 4].copy_from_slice(&[x, y, 10, 0]);
+            }
+        }
+        let pixels = unpad_rows(&data, size, padded, true);
+        assert_eq!(pixels.len(), 3 * 2 * 4);
+        assert_eq!(&pixels[..8], &[10, 0, 0, 255, 10, 0, 1, 255]);
+        assert_eq!(&pixels[20..], &[10, 1, 2, 255]);
+        let pixels = unpad_rows(&data, size, padded, false);
+        assert_eq!(&pixels[20..], &[2, 1, 10, 255]);
+    }
+}
@@ -0,0 +1,166 @@
+//! Command-line options, validated before the window and the renderer are created.
+
+use std::path::PathBuf;
+
+use clap::Parser;
+use tracing_subscriber::EnvFilter;
+use vek::Vec2;
+
+use crate::{
+    benchmark,
+    error::Error,
+    graphics::{self, AdapterSelection, Backend, PowerPreference, PresentMode},
+    settings::Settings,
+    window::{self, WindowOptions},
+};
+
+#[derive(Debug, Parser)]
+#[command(version, about = "Rusty Sandbox, a voxel rendering sandbox")]
+pub struct Cli {
+    /// World to load, not supported yet.
+    #[arg(long, value_name = "PATH")]
+    pub world: Option<PathBuf>,
+    /// Seed of the world generated by --benchmark, recorded in the report.
+    #[arg(long)]
+    pub seed: Option<u64>,
+    /// Inner size of the window, e.g. 1280x720.
+    #[arg(long, value_name = "WIDTHxHEIGHT", value_parser = parse_size)]
+    pub size: Option<Vec2<u32>>,
+    /// Starts in fullscreen, borderless unless set otherwise in the settings.
+    #[arg(long)]
+    pub fullscreen: bool,
+    #[arg(long, value_parser = graphics::parse_choice::<Backend>)]
+    pub backend: Option<Backend>,
+    /// Index or part of the name of the adapter to use.
+    #[arg(long, value_parser = parse_adapter)]
+    pub adapter: Option<AdapterSelection>,
+    #[arg(long, value_parser = graphics::parse_choice::<PowerPreference>)]
+    pub power_preference: Option<PowerPreference>,
+    #[arg(long, value_parser = graphics::parse_choice::<PresentMode>)]
+    pub present_mode: Option<PresentMode>,
+    /// Maximum frames per second, 0 for unlimited.
+    #[arg(long, value_name = "FPS")]
+    pub frame_cap: Option<u32>,
+    /// Runs without showing a window, requires --benchmark.
+    #[arg(long)]
+    pub headless: bool,
+    /// Log level or directives, taking precedence over RUST_LOG, e.g. `debug,wgpu_core=warn`.
+    #[arg(long, value_name = "DIRECTIVES")]
+    pub log_level: Option<String>,
+    /// Renders the camera path and writes a report instead of running interactively.
+    #[arg(long)]
+    pub benchmark: bool,
+    /// Camera keyframes flown by --benchmark, or played back on launch otherwise.
+    #[arg(long, value_name = "PATH")]
+    pub camera_path: Option<PathBuf>,
+    /// Benchmark report, written as CSV with a `.csv` extension and as JSON otherwise.
+    #[arg(long, value_name = "PATH", default_value = benchmark::DEFAULT_REPORT)]
+    pub report: PathBuf,
+    /// Records the keyboard and mouse input to a file, to replay it with --replay.
+    #[arg(long, value_name = "PATH")]
+    pub record: Option<PathBuf>,
+    /// Replays recorded input with a fixed timestep instead of the live input, then exits.
+    #[arg(long, value_name = "PATH")]
+    pub replay: Option<PathBuf>,
+    /// Settings file to use instead of the one in the config directory.
+    #[arg(long, value_name = "PATH")]
+    pub settings: Option<PathBuf>,
+}
+
+impl Cli {
+    /// Checks the options that clap can't, e.g. that the files exist.
+    pub fn validate(&self) -> Result<(), Error> {
+        let invalid = |message: String| Err(Error::InvalidArguments(message));
+        if self.world.is_some() {
+            return invalid("--world is not supported yet, worlds can't be loaded".to_string());
+        }
+        for path in [&self.camera_path, &self.replay].into_iter().flatten() {
+            if !path.is_file() {
+                return invalid(format!("{} is not a file", path.display()));
+            }
+        }
+        if let Some(size) = self.size {
+            if size.x == 0 || size.y == 0 || size.x > window::MAX_SIZE || size.y > window::MAX_SIZE
+            {
+                return invalid(format!(
+                    "the window size must be between 1x1 and {0}x{0}",
+                    window::MAX_SIZE
+                ));
+            }
+        }
+        if self.headless && !self.benchmark {
+            return invalid("--headless requires --benchmark".to_string());
+        }
+        if self.headless && self.fullscreen {
+            return invalid("--headless and --fullscreen can't be combined".to_string());
+        }
+        if self.seed.is_some() && !self.benchmark {
+            return invalid("--seed requires --benchmark".to_string());
+        }
+        if self.benchmark && self.camera_path.is_none() {
+            return invalid("--benchmark requires --camera-path".to_string());
+        }
+        if self.benchmark && (self.record.is_some() || self.replay.is_some()) {
+            return invalid("--benchmark can't be combined with --record or --replay".to_string());
+        }
+        if self.record.is_some() && self.replay.is_some() {
+            return invalid("--record and --replay can't be combined".to_string());
+        }
+        if let Some(directives) = &self.log_level {
+            if let Err(error) = EnvFilter::try_new(directives) {
+                return invalid(format!("invalid log level {}: {}", directives, error));
+            }
+        }
+        Ok(())
+    }
+
+    /// Overrides the settings read from the settings file.
+    pub fn apply(&self, settings: &mut Settings) {
+        if let Some(size) = self.size {
+            settings.window.size = Some(size.into_array());
+        }
+        let graphics = &mut settings.graphics;
+        if let Some(backend) = self.backend {
+            graphics.backend = backend;
+        }
+        if let Some(adapter) = &self.adapter {
+            graphics.adapter = Some(adapter.clone());
+        }
+        if let Some(power_preference) = self.power_preference {
+            graphics.power_preference = power_preference;
+        }
+        if let Some(present_mode) = self.present_mode {
+            graphics.present_mode = present_mode;
+        }
+        if let Some(frame_cap) = self.frame_cap {
+            graphics.frame_cap = (frame_cap > 0).then_some(frame_cap);
+        }
+    }
+
+    pub fn window_options(&self, settings: &Settings) -> WindowOptions {
+        WindowOptions {
+            settings: settings.window.clone(),
+            fullscreen: self.fullscreen,
+        }
+    }
+}
+
+fn parse_size(value: &str) -> Result<Vec2<u32>, String> {
+    let (width, height) = value
+        .split_once('x')
+        .ok_or_else(|| "expected WIDTHxHEIGHT".to_string())?;
+    let parse = |value: &str| {
+        value
+            .trim()
+            .parse::<u32>()
+            .map_err(|error| error.to_string())
+    };
+    Ok(Vec2::new(parse(width)?, parse(height)?))
+}
+
+fn parse_adapter(value: &str) -> Result<AdapterSelection, String> {
+    Ok(match value.parse() {
+        Ok(index) => AdapterSelection::Index(index),
+        Err(_) => AdapterSelection::Name(value.to_string()),
+    })
+}
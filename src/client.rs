@@ -1,48 +1,175 @@
+use std::time::Instant;
+
 use egui_wgpu_backend::RenderPass;
-use tracing::{info, span, Level};
-use winit::event::{Event, KeyboardInput, WindowEvent};
+use tracing::{debug, info, span, warn, Level};
+use winit::event::{ElementState, Event, KeyboardInput, MouseButton, VirtualKeyCode, WindowEvent};
 
 use crate::{
-    egui_instance::EguiInstance, error::RendererError, renderer::Renderer, window::Window,
+    capture::Capture,
+    egui_instance::{EguiInstance, UiPass},
+    error::{self, Error, RendererError},
+    gamepad::{Gamepads, GilrsSource},
+    graphics::FrameLimiter,
+    renderer::{self, Renderer},
+    settings::{CameraSettings, Settings, SettingsFile},
+    window::Window,
 };
 
 pub struct Client {
     pub window: Window,
     pub renderer: Renderer,
     pub gui: EguiInstance,
+    pub settings: SettingsFile,
+    frame_limiter: FrameLimiter,
+    pub capture: Capture,
+    /// `None` if gilrs isn't supported on the platform.
+    gamepads: Option<Gamepads<GilrsSource>>,
+    last_gamepad_update: Instant,
 }
 
 impl Client {
-    pub fn init(window: Window, renderer: Renderer) -> Self {
+    pub fn init(
+        window: Window,
+        mut renderer: Renderer,
+        settings: SettingsFile,
+    ) -> Result<Self, RendererError> {
+        let gui = crate::egui_instance::EguiInstance::new(window.winit());
+        let launch = &settings.launch;
+        renderer.camera.fov = launch.camera.fov;
+        renderer.camera.speed = launch.camera.speed;
+        renderer.upload_camera();
+        renderer.apply_settings(&launch.renderer);
+        let gamepads = match GilrsSource::new() {
+            Ok(source) => Some(Gamepads::new(source)),
+            Err(error) => {
+                warn!(error, "Gamepads are disabled");
+                None
+            }
+        };
+        Self::with_gui(window, renderer, gui, settings, Capture::new(), gamepads)
+    }
+
+    fn with_gui(
+        window: Window,
+        mut renderer: Renderer,
+        gui: EguiInstance,
+        settings: SettingsFile,
+        capture: Capture,
+        gamepads: Option<Gamepads<GilrsSource>>,
+    ) -> Result<Self, RendererError> {
         // We use the egui_wgpu_backend crate as the render backend.
         let egui_renderpass = RenderPass::new(&renderer.device, renderer.surface_config.format, 1);
-        let gui = crate::egui_instance::EguiInstance::new(egui_renderpass, &window.winit());
+        renderer.add_pass(UiPass::new(egui_renderpass))?;
 
-        Self {
+        Ok(Self {
             window,
             renderer,
             gui,
+            settings,
+            frame_limiter: FrameLimiter::new(),
+            capture,
+            gamepads,
+            last_gamepad_update: Instant::now(),
+        })
+    }
+
+    /// Tears down the renderer and creates a new one, with the graphics settings waiting to be
+    /// applied if any, or the previous ones if those fail. The camera, the settings and the UI
+    /// are kept.
+    pub fn recreate_renderer(self) -> Result<Self, Error> {
+        let Self {
+            window,
+            renderer,
+            mut gui,
+            settings,
+            mut capture,
+            gamepads,
+            ..
+        } = self;
+        info!("Recreating the renderer");
+        // The frames being read back belong to the old device.
+        if renderer::catch_device_loss(|| capture.finish(&renderer.device)).is_err() {
+            warn!("The captures being read back were lost with the device");
         }
+        // The old surface is dropped first, a window can't be presented to by two swapchains.
+        let mut state = renderer.into_state();
+        let mut renderer = match Renderer::new(&window, state.graphics.clone()) {
+            Ok(renderer) => renderer,
+            Err(error) if state.graphics != state.previous_graphics => {
+                warn!(
+                    error = error::report(&error),
+                    "Failed to apply the graphics settings, restoring the previous ones"
+                );
+                state.graphics = state.previous_graphics.clone();
+                Renderer::new(&window, state.graphics.clone())?
+            }
+            Err(error) => return Err(error.into()),
+        };
+        renderer.restore(state);
+        gui.reset(window.winit());
+        Ok(Self::with_gui(
+            window, renderer, gui, settings, capture, gamepads,
+        )?)
     }
 
-    pub fn update_camera(&mut self) {
-        let w = self.window.resolution().x as f32;
-        let h = self.window.resolution().y as f32;
-        self
-            .renderer
-            .camera_projection
-            .set_mvp_from_mat(self.renderer.camera.build_mvp(h, w));
-            self.renderer.camera_buffer.update(
-            &self.renderer.queue,
-            &[self.renderer.camera_projection],
-            0,
-        );
+    /// Forwards an event to egui, except the pointer events while the cursor is grabbed.
+    pub fn handle_gui_event(&mut self, event: &Event<()>) {
+        if self.window.cursor_grabbed()
+            && matches!(
+                event,
+                Event::WindowEvent {
+                    event: WindowEvent::CursorMoved { .. }
+                        | WindowEvent::MouseInput { .. }
+                        | WindowEvent::MouseWheel { .. },
+                    ..
+                }
+            )
+        {
+            return;
+        }
+        self.gui.handle_event(event);
     }
 
     pub fn update(&mut self, event: &WindowEvent) {
         let span = span!(Level::INFO, "update");
         let _guard = span.enter();
+        // egui gets the input first, e.g. typing in a text field doesn't move the camera.
+        let grabbed = self.window.cursor_grabbed();
+        let gui_keyboard = !grabbed && self.gui.wants_keyboard_input();
+        let gui_pointer = !grabbed && self.gui.wants_pointer_input();
         match event {
+            WindowEvent::KeyboardInput {
+                input:
+                    KeyboardInput {
+                        state: ElementState::Pressed,
+                        virtual_keycode: Some(VirtualKeyCode::Escape),
+                        ..
+                    },
+                ..
+            } if !gui_keyboard => self.window.set_cursor_grab(!grabbed),
+            WindowEvent::KeyboardInput {
+                input:
+                    KeyboardInput {
+                        state: ElementState::Pressed,
+                        virtual_keycode: Some(VirtualKeyCode::F2),
+                        ..
+                    },
+                ..
+            } if !gui_keyboard => self.capture.request_screenshot(),
+            WindowEvent::KeyboardInput {
+                input:
+                    KeyboardInput {
+                        state: ElementState::Pressed,
+                        virtual_keycode: Some(VirtualKeyCode::F11),
+                        ..
+                    },
+                ..
+            } if !gui_keyboard => self.window.set_fullscreen(!self.window.is_fullscreen()),
+            WindowEvent::MouseInput {
+                state: ElementState::Pressed,
+                button: MouseButton::Left,
+                ..
+            } if !gui_pointer => self.window.set_cursor_grab(true),
             WindowEvent::KeyboardInput {
                 input:
                     KeyboardInput {
@@ -50,16 +177,97 @@ impl Client {
                         ..
                     },
                 ..
-            } => {
-                self.renderer.camera.on_key_pressed(keycode);
-                self.update_camera();
+            } if !gui_keyboard && !self.window.is_paused() => {
+                if let Some(movement) = self.settings.launch.keys.movement(*keycode) {
+                    self.renderer.camera.on_movement(movement);
+                    self.renderer.upload_camera();
+                }
+            }
+            _ => (),
+        }
+    }
+
+    /// Whether the raw mouse motion rotates the camera, while the cursor is grabbed and the
+    /// sandbox isn't paused.
+    pub fn camera_follows_mouse(&self) -> bool {
+        self.window.cursor_grabbed() && !self.window.is_paused()
+    }
+
+    /// Rotates the camera, see [Client::camera_follows_mouse].
+    pub fn on_mouse_motion(&mut self, delta: (f64, f64)) {
+        if !self.camera_follows_mouse() {
+            return;
+        }
+        self.renderer.camera.on_mouse_input(delta.0, delta.1);
+        self.renderer.upload_camera();
+    }
+
+    /// Stops reading the gamepads, e.g. while replaying recorded input.
+    pub fn disable_gamepads(&mut self) {
+        self.gamepads = None;
+    }
+
+    /// Moves and rotates the camera with the active gamepad, called once per frame while not
+    /// paused. The events received during a pause are processed on resume.
+    pub fn update_gamepads(&mut self) {
+        let Some(gamepads) = &mut self.gamepads else {
+            return;
+        };
+        let now = Instant::now();
+        // Clamped so that a long frame or a pause doesn't make the camera jump.
+        let dt = (now - self.last_gamepad_update).as_secs_f32().min(0.1);
+        self.last_gamepad_update = now;
+        let frame = gamepads.update(&self.settings.launch.gamepad, dt);
+        for action in frame.actions {
+            debug!(?action, "Block editing isn't implemented yet");
+        }
+        if frame.movement.is_approx_zero() && frame.look.is_approx_zero() {
+            return;
+        }
+        let camera = &mut self.renderer.camera;
+        camera.move_by(frame.movement.x, frame.movement.y);
+        camera.on_mouse_input(frame.look.x, frame.look.y);
+        self.renderer.upload_camera();
+    }
+
+    /// The settings in effect, including the changes made in the UI.
+    pub fn current_settings(&self) -> Settings {
+        let launch = &self.settings.launch;
+        Settings {
+            window: self.window.settings(),
+            camera: CameraSettings {
+                fov: self.renderer.camera.fov,
+                speed: self.renderer.camera.speed,
             },
+            keys: launch.keys.clone(),
+            gamepad: launch.gamepad.clone(),
+            graphics: self.renderer.graphics().clone(),
+            renderer: self.renderer.settings(),
+            ..Settings::default()
+        }
+    }
 
-            _ => (),
+    /// Writes the changed settings to the settings file.
+    pub fn save_settings(&mut self) {
+        let current = self.current_settings();
+        if let Err(error) = self.settings.save(&current) {
+            warn!(error = error::report(&error), "Failed to save the settings");
         }
     }
 
+    /// Renders a frame, frames are skipped while the surface can't be acquired.
+    ///
+    /// Only unrecoverable errors are returned.
     pub fn render(&mut self) -> Result<(), RendererError> {
+        self.frame_limiter.wait(self.renderer.graphics().frame_cap);
+        renderer::catch_device_loss(|| self.render_frame())?
+    }
+
+    fn render_frame(&mut self) -> Result<(), RendererError> {
+        // Acquired before building the UI, a skipped UI frame would lose its texture updates.
+        let Some(frame) = self.renderer.acquire_frame()? else {
+            return Ok(());
+        };
         let mut encoder =
             self.renderer
                 .device
@@ -67,18 +275,20 @@ impl Client {
                     label: Some("Encoder: Frame Main"),
                 });
 
-        let texture = self.renderer.start_frame(&mut encoder);
-
-        self.gui.draw(
+        let ui = self.gui.draw(
             &mut self.renderer,
+            &mut self.capture,
             self.window.winit().scale_factor() as f32,
-            &mut encoder,
-            &texture,
         );
+        self.renderer.start_frame(&mut encoder, &frame, Some(ui));
+
         self.renderer
             .queue
             .submit(std::iter::once(encoder.finish()));
-        texture.present();
+        frame.present();
+        self.renderer.finish_frame();
+        self.capture.update(&mut self.renderer);
+        self.window.on_frame();
         Ok(())
     }
 
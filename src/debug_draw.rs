@@ -0,0 +1,370 @@
+//! Immediate-mode debug shapes, any system can draw lines during a frame:
+//!
+//! ```ignore
+//! debug_draw::aabb(min, max, Rgba::red());
+//! debug_draw::without_depth_test(|| debug_draw::axes(Vec3::zero(), 1.0));
+//! ```
+//!
+//! Shapes are collected until the renderer uploads them when it starts rendering a frame,
+//! they are then drawn for that frame only by the [DebugDrawPass].
+
+use std::{
+    cell::Cell,
+    f32::consts::TAU,
+    sync::{Arc, Mutex},
+};
+
+use vek::{Mat4, Rgba, Vec3, Vec4};
+use wgpu::BufferUsages;
+
+use crate::{
+    buffer::Buffer,
+    debug_view,
+    passes::{self, DEPTH, MULTISAMPLED_HDR},
+    pipeline::{DepthState, PipelineCache, PipelineDesc},
+    post::{HDR, HDR_FORMAT},
+    profiler,
+    render_graph::{Pass, PassContext},
+    renderer::SCENE_LAYOUT,
+    shader_manager::ShaderManager,
+    texture::Texture,
+    vertex::Vertex,
+};
+
+/// Segments of the circles of a sphere.
+const SPHERE_SEGMENTS: usize = 24;
+
+/// Line vertices of the frame, drawn with and without depth testing.
+struct Lines {
+    depth_tested: Vec<Vertex>,
+    overlay: Vec<Vertex>,
+}
+
+static LINES: Mutex<Lines> = Mutex::new(Lines {
+    depth_tested: Vec::new(),
+    overlay: Vec::new(),
+});
+
+thread_local! {
+    static DEPTH_TEST: Cell<bool> = const { Cell::new(true) };
+}
+
+/// Draws the shapes of `draw` on top of the scene, even when they are hidden.
+pub fn without_depth_test(draw: impl FnOnce()) {
+    let previous = DEPTH_TEST.with(|depth_test| depth_test.replace(false));
+    draw();
+    DEPTH_TEST.with(|depth_test| depth_test.set(previous));
+}
+
+pub fn line(a: Vec3<f32>, b: Vec3<f32>, color: Rgba<f32>) {
+    lines(&[(a, b)], color);
+}
+
+/// Axis aligned box.
+pub fn aabb(min: Vec3<f32>, max: Vec3<f32>, color: Rgba<f32>) {
+    let corners = std::array::from_fn(|i| {
+        Vec3::new(
+            if i & 1 == 0 { min.x } else { max.x },
+            if i & 2 == 0 { min.y } else { max.y },
+            if i & 4 == 0 { min.z } else { max.z },
+        )
+    });
+    box_edges(&corners, color);
+}
+
+/// Three circles around the axes.
+pub fn sphere(center: Vec3<f32>, radius: f32, color: Rgba<f32>) {
+    let point = |i: usize| {
+        let angle = i as f32 / SPHERE_SEGMENTS as f32 * TAU;
+        (angle.cos() * radius, angle.sin() * radius)
+    };
+    let offset = |x, y, z| center + Vec3::new(x, y, z);
+    let mut segments = Vec::with_capacity(SPHERE_SEGMENTS * 3);
+    for i in 0..SPHERE_SEGMENTS {
+        let ((ax, ay), (bx, by)) = (point(i), point(i + 1));
+        segments.push((offset(ax, ay, 0.0), offset(bx, by, 0.0)));
+        segments.push((offset(0.0, ax, ay), offset(0.0, bx, by)));
+        segments.push((offset(ay, 0.0, ax), offset(by, 0.0, bx)));
+    }
+    lines(&segments, color);
+}
+
+/// X, Y and Z in red, green and blue.
+pub fn axes(origin: Vec3<f32>, size: f32) {
+    line(origin, origin + Vec3::unit_x() * size, Rgba::red());
+    line(origin, origin + Vec3::unit_y() * size, Rgba::green());
+    line(origin, origin + Vec3::unit_z() * size, Rgba::blue());
+}
+
+/// The volume a view projection matrix maps to clip space, e.g. a camera or a shadow cascade.
+pub fn frustum(view_projection: Mat4<f32>, color: Rgba<f32>) {
+    let inverse = view_projection.inverted();
+    let corners = std::array::from_fn(|i| {
+        let ndc = Vec4::new(
+            if i & 1 == 0 { -1.0 } else { 1.0 },
+            if i & 2 == 0 { -1.0 } else { 1.0 },
+            if i & 4 == 0 { 0.0 } else { 1.0 },
+            1.0,
+        );
+        let world = inverse * ndc;
+        world.xyz() / world.w
+    });
+    box_edges(&corners, color);
+}
+
+/// Draws the 12 edges between corners indexed by their x, y and z bits.
+fn box_edges(corners: &[Vec3<f32>; 8], color: Rgba<f32>) {
+    // Corners differing by a single bit share an edge.
+    let edges = (0..8)
+        .flat_map(|i| {
+            [1, 2, 4]
+                .into_iter()
+                .filter(move |bit| i & bit == 0)
+                .map(move |bit| (corners[i], corners[i | bit]))
+        })
+        .collect::<Vec<_>>();
+    lines(&edges, color);
+}
+
+fn lines(segments: &[(Vec3<f32>, Vec3<f32>)], color: Rgba<f32>) {
+    let color = color.into_array();
+    let vertices = segments.iter().flat_map(|&(a, b)| {
+        [
+            Vertex {
+                position: a.into_array(),
+                color,
+            },
+            Vertex {
+                position: b.into_array(),
+                color,
+            },
+        ]
+    });
+    let mut lines = LINES.lock().unwrap();
+    if DEPTH_TEST.with(Cell::get) {
+        lines.depth_tested.extend(vertices);
+    } else {
+        lines.overlay.extend(vertices);
+    }
+}
+
+/// GPU side of the debug shapes.
+pub struct DebugDraw {
+    buffer: Buffer<Vertex>,
+    depth_tested: u32,
+    overlay: u32,
+    depth_tested_pipeline: Arc<wgpu::RenderPipeline>,
+    overlay_pipeline: Arc<wgpu::RenderPipeline>,
+}
+
+impl DebugDraw {
+    pub fn new(
+        device: &wgpu::Device,
+        pipelines: &mut PipelineCache,
+        shaders: &mut ShaderManager,
+        sample_count: u32,
+    ) -> Self {
+        Self {
+            buffer: Self::create_buffer(device, 1024),
+            depth_tested: 0,
+            overlay: 0,
+            depth_tested_pipeline: pipelines.get(
+                device,
+                shaders,
+                &pipeline_desc(true, sample_count),
+            ),
+            overlay_pipeline: pipelines.get(device, shaders, &pipeline_desc(false, sample_count)),
+        }
+    }
+
+    /// Fetches the pipelines again, after a MSAA or shader change.
+    pub fn update_pipelines(
+        &mut self,
+        device: &wgpu::Device,
+        pipelines: &mut PipelineCache,
+        shaders: &mut ShaderManager,
+        sample_count: u32,
+    ) {
+        self.depth_tested_pipeline =
+            pipelines.get(device, shaders, &pipeline_desc(true, sample_count));
+        self.overlay_pipeline = pipelines.get(device, shaders, &pipeline_desc(false, sample_count));
+    }
+
+    /// Uploads the shapes drawn since the last call, growing the buffer if needed.
+    pub fn upload(&mut self, device: &wgpu::Device, queue: &wgpu::Queue) {
+        let mut lines = LINES.lock().unwrap();
+        let len = lines.depth_tested.len() + lines.overlay.len();
+        if len > self.buffer.len() {
+            self.buffer = Self::create_buffer(device, len.next_power_of_two());
+        }
+        self.buffer.update(queue, &lines.depth_tested, 0);
+        self.buffer
+            .update(queue, &lines.overlay, lines.depth_tested.len());
+        self.depth_tested = lines.depth_tested.len() as u32;
+        self.overlay = lines.overlay.len() as u32;
+        lines.depth_tested.clear();
+        lines.overlay.clear();
+    }
+
+    fn create_buffer(device: &wgpu::Device, len: usize) -> Buffer<Vertex> {
+        Buffer::new(
+            device,
+            &vec![bytemuck::Zeroable::zeroed(); len],
+            BufferUsages::VERTEX | BufferUsages::COPY_DST,
+        )
+    }
+}
+
+fn pipeline_desc(depth_test: bool, sample_count: u32) -> PipelineDesc {
+    let (label, compare) = if depth_test {
+        ("Debug Lines Pipeline", wgpu::CompareFunction::LessEqual)
+    } else {
+        (
+            "Debug Overlay Lines Pipeline",
+            wgpu::CompareFunction::Always,
+        )
+    };
+    PipelineDesc::new(label, SCENE_LAYOUT, "debug.wgsl")
+        .defines(debug_view::shader_defines())
+        .vertex_buffer(Vertex::layout())
+        .fragment("fs_color", HDR_FORMAT)
+        .topology(wgpu::PrimitiveTopology::LineList)
+        .depth(DepthState {
+            compare,
+            ..DepthState::new(Texture::DEPTH_FORMAT, false)
+        })
+        .sample_count(sample_count)
+}
+
+/// Draws the debug shapes over the scene and the debug views.
+pub struct DebugDrawPass;
+
+impl Pass for DebugDrawPass {
+    fn name(&self) -> &'static str {
+        "Debug Draw Pass"
+    }
+
+    fn reads(&self) -> &[&'static str] {
+        &[DEPTH]
+    }
+
+    fn writes(&self) -> &[&'static str] {
+        &[HDR, MULTISAMPLED_HDR]
+    }
+
+    fn execute(&mut self, ctx: &mut PassContext) {
+        let renderer = ctx.renderer;
+        let debug_draw = renderer.debug_draw();
+        if debug_draw.depth_tested + debug_draw.overlay == 0 {
+            return;
+        }
+        let (view, resolve_target) = passes::scene_color(ctx);
+        let mut render_pass = ctx.encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some(self.name()),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view,
+                resolve_target,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Load,
+                    store: true,
+                },
+            })],
+            depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                view: ctx.resources.view(DEPTH),
+                depth_ops: Some(wgpu::Operations {
+                    load: wgpu::LoadOp::Load,
+                    store: false,
+                }),
+                stencil_ops: None,
+            }),
+        });
+        render_pass.set_bind_group(0, renderer.camera_bind_group(), &[]);
+        render_pass.set_bind_group(1, renderer.shadow.bind_group(), &[]);
+        render_pass.set_vertex_buffer(0, debug_draw.buffer.data().slice(..));
+        render_pass.set_pipeline(&debug_draw.depth_tested_pipeline);
+        render_pass.draw(0..debug_draw.depth_tested, 0..1);
+        profiler::count_draw_call();
+        render_pass.set_pipeline(&debug_draw.overlay_pipeline);
+        let end = debug_draw.depth_tested + debug_draw.overlay;
+        render_pass.draw(debug_draw.depth_tested..end, 0..1);
+        profiler::count_draw_call();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use vek::Vec2;
+
+    use super::*;
+    use crate::{error::RendererError, graphics::GraphicsSettings, renderer::Renderer};
+
+    /// Renders a frame with the lines drawn so far and reads back its RGBA pixels.
+    fn render_frame(renderer: &mut Renderer) -> Vec<u8> {
+        let size = renderer.resolution;
+        let frame = renderer.acquire_frame().unwrap().unwrap();
+        let mut encoder = renderer
+            .device
+            .create_command_encoder(&wgpu::CommandEncoderDescriptor::default());
+        renderer.start_frame(&mut encoder, &frame, None);
+        let bytes_per_row = size.x * 4;
+        let buffer = renderer.device.create_buffer(&wgpu::BufferDescriptor {
+            label: None,
+            size: (bytes_per_row * size.y) as u64,
+            usage: BufferUsages::COPY_DST | BufferUsages::MAP_READ,
+            mapped_at_creation: false,
+        });
+        encoder.copy_texture_to_buffer(
+            frame.texture().as_image_copy(),
+            wgpu::ImageCopyBuffer {
+                buffer: &buffer,
+                layout: wgpu::ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: std::num::NonZeroU32::new(bytes_per_row),
+                    rows_per_image: None,
+                },
+            },
+            wgpu::Extent3d {
+                width: size.x,
+                height: size.y,
+                depth_or_array_layers: 1,
+            },
+        );
+        renderer.queue.submit(std::iter::once(encoder.finish()));
+        renderer.finish_frame();
+        buffer.slice(..).map_async(wgpu::MapMode::Read, |_| {});
+        renderer.device.poll(wgpu::Maintain::Wait);
+        let pixels = buffer.slice(..).get_mapped_range().to_vec();
+        pixels
+    }
+
+    #[test]
+    fn draws_the_depth_tested_lines_in_front_of_the_scene() {
+        // 64 pixels wide, so that the rows don't need padding.
+        let mut renderer = match Renderer::headless(Vec2::new(64, 64), GraphicsSettings::default())
+        {
+            Ok(renderer) => renderer,
+            Err(RendererError::AdapterNotFound) => {
+                eprintln!("No graphics adapter, skipping");
+                return;
+            }
+            Err(error) => panic!("{}", error),
+        };
+        let is_red = |pixel: &[u8]| pixel[0] > 200 && pixel[1] < 100 && pixel[2] < 100;
+        let red_pixels = |pixels: Vec<u8>| pixels.chunks_exact(4).filter(|p| is_red(p)).count();
+        let before = red_pixels(render_frame(&mut renderer));
+
+        // Between the camera, at z = -3, and the scene, over the sky.
+        for step in 0..8 {
+            let y = 0.2 + step as f32 * 0.01;
+            let color = Rgba::new(1.0, 0.0, 0.0, 1.0);
+            line(Vec3::new(-2.0, y, -2.0), Vec3::new(2.0, y, -2.0), color);
+        }
+        let after = red_pixels(render_frame(&mut renderer));
+        assert!(
+            after >= before + 64,
+            "{} red pixels before the lines and {} after",
+            before,
+            after
+        );
+    }
+}
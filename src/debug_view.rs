@@ -0,0 +1,218 @@
+use std::sync::Arc;
+
+use vek::{Rgba, Vec3};
+
+use crate::{
+    camera::FAR_PLANE,
+    debug_draw,
+    mesh::Mesh,
+    passes::{self, DEPTH, MULTISAMPLED_HDR},
+    pipeline::{DepthState, PipelineCache, PipelineDesc},
+    post::{HDR, HDR_FORMAT},
+    render_graph::{Pass, PassContext},
+    renderer::SCENE_LAYOUT,
+    shader_manager::ShaderManager,
+    shader_preprocessor::ShaderDefines,
+    texture::Texture,
+    vertex::{BarycentricVertex, Vertex},
+};
+
+/// Alternative views of the scene used to inspect the meshes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DebugMode {
+    /// The regular shaded scene.
+    None,
+    /// Triangle edges only.
+    Wireframe,
+    /// Face normals mapped to colors.
+    Normals,
+    /// The shaded scene with the bounds of every mesh chunk.
+    ChunkBorders,
+    /// How many times each pixel is drawn, from black to red, yellow and white.
+    Overdraw,
+    /// Linear view depth.
+    Depth,
+}
+
+impl DebugMode {
+    pub const ALL: [DebugMode; 6] = [
+        DebugMode::None,
+        DebugMode::Wireframe,
+        DebugMode::Normals,
+        DebugMode::ChunkBorders,
+        DebugMode::Overdraw,
+        DebugMode::Depth,
+    ];
+
+    /// Whether the mode draws the meshes itself instead of the shaded scene.
+    pub fn replaces_scene(self) -> bool {
+        !matches!(self, DebugMode::None | DebugMode::ChunkBorders)
+    }
+
+    fn pipeline_desc(self, line_polygon_mode: bool, sample_count: u32) -> Option<PipelineDesc> {
+        let defines = shader_defines();
+        let desc = |label, entry_point| {
+            PipelineDesc::new(label, SCENE_LAYOUT, "debug.wgsl")
+                .defines(defines.clone())
+                .vertex_buffer(Vertex::layout())
+                .fragment(entry_point, HDR_FORMAT)
+                .depth(DepthState::new(Texture::DEPTH_FORMAT, true))
+                .sample_count(sample_count)
+        };
+        let desc = match self {
+            DebugMode::None | DebugMode::ChunkBorders => return None,
+            DebugMode::Wireframe if line_polygon_mode => {
+                desc("Wireframe Pipeline", "fs_wireframe").polygon_mode(wgpu::PolygonMode::Line)
+            }
+            DebugMode::Wireframe => {
+                let mut desc = desc("Wireframe Pipeline", "fs_wireframe")
+                    .defines(defines.clone().define("BARYCENTRIC"));
+                desc.vertex_buffers = vec![BarycentricVertex::layout()];
+                desc
+            }
+            DebugMode::Normals => desc("Normals Pipeline", "fs_normals"),
+            // Every fragment is counted, hidden or not.
+            DebugMode::Overdraw => desc("Overdraw Pipeline", "fs_overdraw")
+                .blend(wgpu::BlendState {
+                    color: wgpu::BlendComponent {
+                        src_factor: wgpu::BlendFactor::One,
+                        dst_factor: wgpu::BlendFactor::One,
+                        operation: wgpu::BlendOperation::Add,
+                    },
+                    alpha: wgpu::BlendComponent::REPLACE,
+                })
+                .depth(DepthState {
+                    compare: wgpu::CompareFunction::Always,
+                    ..DepthState::new(Texture::DEPTH_FORMAT, false)
+                }),
+            DebugMode::Depth => desc("Depth Pipeline", "fs_depth"),
+        };
+        Some(desc)
+    }
+}
+
+/// Defines of `debug.wgsl`.
+pub fn shader_defines() -> ShaderDefines {
+    ShaderDefines::new().define_value("FAR_PLANE", format!("{:?}", FAR_PLANE))
+}
+
+/// Returns whether wireframes can be drawn with [wgpu::PolygonMode::Line],
+/// otherwise they are drawn by the fragment shader from barycentric coordinates.
+pub fn line_polygon_mode_supported(device: &wgpu::Device) -> bool {
+    device
+        .features()
+        .contains(wgpu::Features::POLYGON_MODE_LINE)
+}
+
+/// State of the selected [DebugMode].
+pub struct DebugView {
+    mode: DebugMode,
+    /// Draws the world axes at the origin.
+    pub show_axes: bool,
+    pipeline: Option<Arc<wgpu::RenderPipeline>>,
+}
+
+impl DebugView {
+    pub fn new() -> Self {
+        Self {
+            mode: DebugMode::None,
+            show_axes: false,
+            pipeline: None,
+        }
+    }
+
+    pub fn mode(&self) -> DebugMode {
+        self.mode
+    }
+
+    /// Changes the mode, the pipeline must then be fetched with [DebugView::update_pipeline].
+    pub fn set_mode(&mut self, mode: DebugMode) {
+        self.mode = mode;
+    }
+
+    /// Queues the debug shapes of the frame.
+    pub fn draw(&self, meshes: &[Mesh]) {
+        if self.mode == DebugMode::ChunkBorders {
+            for mesh in meshes {
+                debug_draw::aabb(mesh.min, mesh.max, Rgba::new(1.0, 0.85, 0.0, 1.0));
+            }
+        }
+        if self.show_axes {
+            debug_draw::without_depth_test(|| debug_draw::axes(Vec3::zero(), 1.0));
+        }
+    }
+
+    /// Fetches the pipeline of the current mode, after a mode, MSAA or shader change.
+    pub fn update_pipeline(
+        &mut self,
+        device: &wgpu::Device,
+        pipelines: &mut PipelineCache,
+        shaders: &mut ShaderManager,
+        sample_count: u32,
+    ) {
+        self.pipeline = self
+            .mode
+            .pipeline_desc(line_polygon_mode_supported(device), sample_count)
+            .map(|desc| pipelines.get(device, shaders, &desc));
+    }
+}
+
+/// Redraws the meshes with the selected [DebugMode] over the scene, then resolves it again with MSAA.
+pub struct DebugPass;
+
+impl Pass for DebugPass {
+    fn name(&self) -> &'static str {
+        "Debug Pass"
+    }
+
+    fn reads(&self) -> &[&'static str] {
+        &[DEPTH]
+    }
+
+    // The depth buffer is redrawn with the meshes.
+    fn writes(&self) -> &[&'static str] {
+        &[HDR, MULTISAMPLED_HDR, DEPTH]
+    }
+
+    fn execute(&mut self, ctx: &mut PassContext) {
+        let renderer = ctx.renderer;
+        let debug = renderer.debug();
+        let Some(pipeline) = &debug.pipeline else {
+            return;
+        };
+        let (view, resolve_target) = passes::scene_color(ctx);
+        let clear_color = match debug.mode {
+            DebugMode::Wireframe => renderer.clear_color(),
+            _ => wgpu::Color::BLACK,
+        };
+        let mut render_pass = ctx.encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some(self.name()),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view,
+                resolve_target,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(clear_color),
+                    store: true,
+                },
+            })],
+            depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                view: ctx.resources.view(DEPTH),
+                depth_ops: Some(wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(1.0),
+                    store: true,
+                }),
+                stencil_ops: None,
+            }),
+        });
+        render_pass.set_pipeline(pipeline);
+        render_pass.set_bind_group(0, renderer.camera_bind_group(), &[]);
+        render_pass.set_bind_group(1, renderer.shadow.bind_group(), &[]);
+        for mesh in renderer.meshes() {
+            if debug.mode == DebugMode::Wireframe {
+                mesh.draw_wireframe(&mut render_pass);
+            } else {
+                mesh.draw(&mut render_pass);
+            }
+        }
+    }
+}
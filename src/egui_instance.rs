@@ -1,25 +1,33 @@
-use std::ops::AddAssign;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
 use egui::FontDefinitions;
 use egui_wgpu_backend::ScreenDescriptor;
 use egui_winit_platform::{Platform, PlatformDescriptor};
-use tracing::{span, Level};
-use vek::{Mat4, Vec2, Vec3};
-use wgpu::{CommandEncoder, SurfaceTexture};
+use tracing::{info, span, warn, Level};
 
 use crate::{
-    camera::{CameraBufferData, DEFAULT_VERTICAL_FOV},
+    camera_path::{CameraPath, PathEditor},
+    capture::{self, Capture},
+    debug_view::DebugMode,
+    error,
+    graphics::{AdapterSelection, PowerPreference, PresentMode},
+    logging::LogConsole,
+    post::ToneMapping,
+    profiler::Profiler,
+    render_graph::{Pass, PassContext, SURFACE},
     renderer::Renderer,
-    vertex::Vertex,
+    shadow::{MAX_SHADOW_CASCADES, SHADOW_RESOLUTIONS},
 };
 
 pub struct EguiInstance {
     pub platform: Platform,
-    pub render_pass: egui_wgpu_backend::RenderPass,
+    log_console: LogConsole,
+    graphics_open: bool,
+    pub camera_path: PathEditor,
 }
 
 impl EguiInstance {
-    pub fn new(render_pass: egui_wgpu_backend::RenderPass, window: &winit::window::Window) -> Self {
+    pub fn new(window: &winit::window::Window) -> Self {
         let platform = Platform::new(PlatformDescriptor {
             physical_width: window.inner_size().width,
             physical_height: window.inner_size().height,
@@ -29,21 +37,41 @@ impl EguiInstance {
         });
         Self {
             platform,
-            render_pass,
+            log_console: LogConsole::new(),
+            graphics_open: false,
+            camera_path: PathEditor::new(),
         }
     }
 
+    /// Recreates the egui context so that every texture is uploaded again, e.g. to a new device.
+    /// The memory, with the window positions, is kept.
+    pub fn reset(&mut self, window: &winit::window::Window) {
+        let memory = self.platform.context().memory().clone();
+        self.platform = Self::new(window).platform;
+        *self.platform.context().memory() = memory;
+    }
+
     pub fn handle_event<T>(&mut self, winit_event: &winit::event::Event<T>) {
         self.platform.handle_event(winit_event);
     }
 
+    /// Whether the pointer is over a window or dragging something, as of the last frame.
+    pub fn wants_pointer_input(&self) -> bool {
+        self.platform.context().wants_pointer_input()
+    }
+
+    /// Whether a text field has the focus, as of the last frame.
+    pub fn wants_keyboard_input(&self) -> bool {
+        self.platform.context().wants_keyboard_input()
+    }
+
+    /// Builds the user interface, applying the changes made to the renderer settings.
     pub fn draw(
         &mut self,
         renderer: &mut Renderer,
+        capture: &mut Capture,
         scale_factor: f32,
-        encoder: &mut CommandEncoder,
-        texture: &SurfaceTexture,
-    ) {
+    ) -> UiFrame {
         let span = span!(Level::INFO, "Draw Egui");
         let _guard = span.enter();
         self.platform.begin_frame();
@@ -53,82 +81,625 @@ impl EguiInstance {
             .resizable(true)
             .title_bar(false)
             .show(&self.platform.context(), |ui| {
-                fn update_camera(renderer: &mut Renderer, w: f32, h: f32) {
-                    let mvp = renderer.camera.build_mvp(w, h);
-                    renderer.camera_projection.set_mvp_from_mat(mvp);
-                    renderer.camera_buffer.update(
-                        &renderer.queue,
-                        &[renderer.camera_projection],
-                        0,
-                    );
-                }
-                let w = renderer.resolution.x as f32;
-                let h = renderer.resolution.y as f32;
                 ui.label("Camera Settings");
 
                 ui.label("FOV");
                 let slider = ui.add(egui::Slider::new(&mut renderer.camera.fov, 1.0..=120.0));
                 if slider.changed() {
-                    update_camera(renderer, w, h);
+                    renderer.upload_camera();
                 }
                 ui.label("Camera X");
                 let slider = ui.add(egui::Slider::new(&mut renderer.camera.eye.x, 1.0..=100.0));
                 if slider.changed() {
-                    update_camera(renderer, w, h);
+                    renderer.upload_camera();
                 }
                 ui.label("Camera Y");
                 let slider = ui.add(egui::Slider::new(&mut renderer.camera.eye.y, 1.0..=100.0));
                 if slider.changed() {
-                    update_camera(renderer, w, h);
+                    renderer.upload_camera();
                 }
                 ui.label("Camera Z");
                 let slider = ui.add(egui::Slider::new(&mut renderer.camera.eye.z, 1.0..=100.0));
                 if slider.changed() {
-                    update_camera(renderer, w, h);
+                    renderer.upload_camera();
                 }
                 ui.label("Target X");
                 let slider = ui.add(egui::Slider::new(&mut renderer.camera.target.x, 0.0..=1.0));
                 if slider.changed() {
-                    update_camera(renderer, w, h);
+                    renderer.upload_camera();
+                }
+
+                ui.separator();
+                ui.label("Shadow Settings");
+                let mut settings = renderer.shadow.settings;
+                egui::ComboBox::from_label("Resolution")
+                    .selected_text(settings.resolution.to_string())
+                    .show_ui(ui, |ui| {
+                        for resolution in SHADOW_RESOLUTIONS {
+                            ui.selectable_value(
+                                &mut settings.resolution,
+                                resolution,
+                                resolution.to_string(),
+                            );
+                        }
+                    });
+                ui.label("Bias");
+                ui.add(egui::Slider::new(&mut settings.bias, 0.0..=0.01).logarithmic(true));
+                ui.label("Cascades");
+                ui.add(egui::Slider::new(
+                    &mut settings.cascade_count,
+                    1..=MAX_SHADOW_CASCADES as u32,
+                ));
+                if settings != renderer.shadow.settings {
+                    renderer.shadow.set_settings(&renderer.device, settings);
                 }
+                ui.checkbox(&mut renderer.shadow.show_cascades, "Show Cascades");
+
+                ui.separator();
+                ui.label("Post Processing");
+                let post = &mut renderer.post.settings;
+                egui::ComboBox::from_label("Tone Mapping")
+                    .selected_text(format!("{:?}", post.tone_mapping))
+                    .show_ui(ui, |ui| {
+                        for tone_mapping in ToneMapping::ALL {
+                            ui.selectable_value(
+                                &mut post.tone_mapping,
+                                tone_mapping,
+                                format!("{:?}", tone_mapping),
+                            );
+                        }
+                    });
+                ui.label("Exposure");
+                ui.add(egui::Slider::new(&mut post.exposure, 0.1..=8.0).logarithmic(true));
+                ui.checkbox(&mut post.gamma_correction, "Gamma Correction");
+                ui.add_enabled(
+                    post.gamma_correction,
+                    egui::Slider::new(&mut post.gamma, 1.0..=3.0),
+                );
+                ui.checkbox(&mut post.bloom, "Bloom");
+                ui.add_enabled_ui(post.bloom, |ui| {
+                    ui.label("Bloom Threshold");
+                    ui.add(egui::Slider::new(&mut post.bloom_threshold, 0.0..=4.0));
+                    ui.label("Bloom Intensity");
+                    ui.add(egui::Slider::new(&mut post.bloom_intensity, 0.0..=2.0));
+                });
+                ui.checkbox(&mut post.fxaa, "FXAA");
+
+                let mut sample_count = renderer.sample_count();
+                egui::ComboBox::from_label("MSAA")
+                    .selected_text(format!("{}x", sample_count))
+                    .show_ui(ui, |ui| {
+                        for &count in renderer.supported_sample_counts() {
+                            ui.selectable_value(&mut sample_count, count, format!("{}x", count));
+                        }
+                    });
+                renderer.set_sample_count(sample_count);
+
+                ui.separator();
+                let mut debug_mode = renderer.debug().mode();
+                egui::ComboBox::from_label("Debug View")
+                    .selected_text(format!("{:?}", debug_mode))
+                    .show_ui(ui, |ui| {
+                        for mode in DebugMode::ALL {
+                            ui.selectable_value(&mut debug_mode, mode, format!("{:?}", mode));
+                        }
+                    });
+                renderer.set_debug_mode(debug_mode);
+                ui.checkbox(&mut renderer.debug_mut().show_axes, "Axes");
+                ui.checkbox(&mut self.graphics_open, "Graphics");
+                ui.checkbox(&mut self.camera_path.open, "Camera Path");
+                ui.checkbox(&mut capture.open, "Capture");
+                ui.checkbox(&mut renderer.profiler.open, "Profiler");
+                ui.checkbox(&mut self.log_console.open, "Log Console");
             });
 
+        if self.graphics_open {
+            graphics_window(&self.platform.context(), renderer, &mut self.graphics_open);
+        }
+
+        // The playback continues with the window closed.
+        if self.camera_path.update(&mut renderer.camera) {
+            renderer.upload_camera();
+        }
+        if self.camera_path.open {
+            camera_path_window(&self.platform.context(), renderer, &mut self.camera_path);
+            self.camera_path.draw_preview();
+        }
+
+        if capture.open {
+            capture_window(&self.platform.context(), capture);
+        }
+
+        if renderer.profiler.open {
+            profiler_window(&self.platform.context(), &mut renderer.profiler);
+        }
+        if self.log_console.open {
+            log_console_window(&self.platform.context(), &mut self.log_console);
+        }
+
+        // Shaders that failed to reload keep their previous version until fixed.
+        let shader_errors = renderer.shaders.errors();
+        if !shader_errors.is_empty() {
+            egui::Window::new("Shader Errors")
+                .anchor(egui::Align2::RIGHT_TOP, [-10.0, 10.0])
+                .resizable(true)
+                .show(&self.platform.context(), |ui| {
+                    egui::ScrollArea::vertical().show(ui, |ui| {
+                        for error in shader_errors.values() {
+                            ui.label(
+                                egui::RichText::new(error.to_string())
+                                    .monospace()
+                                    .color(egui::Color32::RED),
+                            );
+                        }
+                    });
+                });
+        }
+
         let full_output = self.platform.end_frame(None);
 
         let paint_jobs = self.platform.context().tessellate(full_output.shapes);
 
-        // Upload all resources for the GPU.
         let screen_descriptor = ScreenDescriptor {
             physical_width: renderer.surface_config.width,
             physical_height: renderer.surface_config.height,
             scale_factor,
         };
 
-        let tdelta: egui::TexturesDelta = full_output.textures_delta;
+        drop(_guard);
+        UiFrame {
+            paint_jobs,
+            screen_descriptor,
+            textures_delta: full_output.textures_delta,
+        }
+    }
+}
+
+/// Backend, adapter, present mode and frame cap of the [Renderer].
+fn graphics_window(ctx: &egui::Context, renderer: &mut Renderer, open: &mut bool) {
+    egui::Window::new("Graphics")
+        .open(open)
+        .default_size([320.0, 200.0])
+        .show(ctx, |ui| {
+            let mut graphics = renderer.graphics().clone();
+            let info = renderer.adapter_info();
+            ui.label(format!("Using {} ({:?})", info.name, info.backend));
+
+            egui::ComboBox::from_label("Backend")
+                .selected_text(format!("{:?}", graphics.backend))
+                .show_ui(ui, |ui| {
+                    for backend in renderer.available_backends() {
+                        let text = format!("{:?}", backend);
+                        ui.selectable_value(&mut graphics.backend, backend, text);
+                    }
+                });
+            // Indices are only meaningful for the backend the adapters were listed with.
+            if graphics.backend != renderer.graphics().backend {
+                graphics.adapter = None;
+            }
+
+            let adapters = renderer.adapters(graphics.backend);
+            let selected = graphics.adapter.as_ref().map_or_else(
+                || "Default".to_string(),
+                |adapter| match adapter.find(&adapters) {
+                    Some(index) => adapters[index].name.clone(),
+                    None => adapter.to_string(),
+                },
+            );
+            egui::ComboBox::from_label("Adapter")
+                .selected_text(selected)
+                .show_ui(ui, |ui| {
+                    ui.selectable_value(&mut graphics.adapter, None, "Default");
+                    for (index, info) in adapters.iter().enumerate() {
+                        let text = format!("{} ({:?})", info.name, info.backend);
+                        let adapter = Some(AdapterSelection::Index(index));
+                        ui.selectable_value(&mut graphics.adapter, adapter, text);
+                    }
+                });
+
+            ui.add_enabled_ui(graphics.adapter.is_none(), |ui| {
+                egui::ComboBox::from_label("Power Preference")
+                    .selected_text(format!("{:?}", graphics.power_preference))
+                    .show_ui(ui, |ui| {
+                        for preference in PowerPreference::ALL {
+                            let text = format!("{:?}", preference);
+                            ui.selectable_value(&mut graphics.power_preference, preference, text);
+                        }
+                    });
+            });
+
+            egui::ComboBox::from_label("Present Mode")
+                .selected_text(format!("{:?}", graphics.present_mode))
+                .show_ui(ui, |ui| {
+                    for mode in PresentMode::ALL {
+                        let text = format!("{:?}", mode);
+                        ui.selectable_value(&mut graphics.present_mode, mode, text);
+                    }
+                });
 
+            let mut capped = graphics.frame_cap.is_some_and(|cap| cap > 0);
+            ui.horizontal(|ui| {
+                ui.checkbox(&mut capped, "Frame Cap");
+                let mut cap = graphics.frame_cap.filter(|&cap| cap > 0).unwrap_or(60);
+                ui.add_enabled(capped, egui::Slider::new(&mut cap, 10..=360).suffix(" fps"));
+                graphics.frame_cap = capped.then_some(cap);
+            });
+
+            // The backend, adapter and power preference recreate the renderer after the frame.
+            renderer.set_graphics(graphics);
+        });
+}
+
+/// Keyframes of the [PathEditor], recorded from the camera, and their playback.
+fn camera_path_window(ctx: &egui::Context, renderer: &mut Renderer, editor: &mut PathEditor) {
+    let mut open = editor.open;
+    egui::Window::new("Camera Path")
+        .open(&mut open)
+        .default_size([420.0, 360.0])
+        .show(ctx, |ui| {
+            let mut time = editor.time;
+            ui.horizontal(|ui| {
+                let has_keyframes = !editor.path.keyframes.is_empty();
+                let play = if editor.is_playing() { "Pause" } else { "Play" };
+                if ui
+                    .add_enabled(has_keyframes, egui::Button::new(play))
+                    .clicked()
+                {
+                    if editor.is_playing() {
+                        editor.pause();
+                    } else {
+                        editor.play();
+                    }
+                }
+                if ui.button("Add Keyframe").clicked() {
+                    editor.add_keyframe(&renderer.camera);
+                }
+                ui.add(
+                    egui::DragValue::new(&mut time)
+                        .speed(0.05)
+                        .clamp_range(0.0..=f32::MAX)
+                        .suffix(" s"),
+                );
+                ui.label(format!("/ {:.2} s", editor.duration()));
+            });
+            timeline(ui, &editor.path, &mut time);
+            if time != editor.time {
+                editor.seek(time, &mut renderer.camera);
+                renderer.upload_camera();
+            }
+
+            ui.separator();
+            let mut remove = None;
+            let mut go_to = None;
+            egui::ScrollArea::vertical()
+                .max_height(180.0)
+                .show(ui, |ui| {
+                    egui::Grid::new("Keyframes")
+                        .striped(true)
+                        .num_columns(5)
+                        .show(ui, |ui| {
+                            ui.strong("Time (s)");
+                            ui.strong("Eye");
+                            ui.strong("FOV");
+                            ui.end_row();
+                            for (index, keyframe) in editor.path.keyframes.iter().enumerate() {
+                                let [x, y, z] = keyframe.eye;
+                                ui.label(format!("{:.2}", keyframe.time));
+                                ui.label(format!("{:.1}, {:.1}, {:.1}", x, y, z));
+                                ui.label(format!("{:.0}", keyframe.fov));
+                                if ui.button("Go To").clicked() {
+                                    go_to = Some(keyframe.time);
+                                }
+                                if ui.button("Remove").clicked() {
+                                    remove = Some(index);
+                                }
+                                ui.end_row();
+                            }
+                        });
+                });
+            if let Some(time) = go_to {
+                editor.pause();
+                editor.seek(time, &mut renderer.camera);
+                renderer.upload_camera();
+            }
+            if let Some(index) = remove {
+                editor.remove_keyframe(index);
+            }
+
+            ui.separator();
+            ui.horizontal(|ui| {
+                ui.label("File");
+                ui.text_edit_singleline(&mut editor.file);
+                if ui.button("Load").clicked() {
+                    if let Err(error) = editor.load() {
+                        warn!(
+                            error = error::report(&error),
+                            "Failed to load the camera path"
+                        );
+                    }
+                }
+                if ui.button("Save").clicked() {
+                    editor.save();
+                }
+            });
+        });
+    editor.open = open;
+}
+
+/// Screenshots and frame sequences of the [Capture].
+fn capture_window(ctx: &egui::Context, capture: &mut Capture) {
+    let mut open = capture.open;
+    egui::Window::new("Capture")
+        .open(&mut open)
+        .default_size([280.0, 120.0])
+        .show(ctx, |ui| {
+            ui.horizontal(|ui| {
+                if ui.button("Screenshot (F2)").clicked() {
+                    capture.request_screenshot();
+                }
+                ui.add(
+                    egui::Slider::new(&mut capture.scale, 1..=capture::MAX_SCALE)
+                        .text("x window size"),
+                );
+            });
+            ui.horizontal(|ui| {
+                if capture.is_recording() {
+                    if ui.button("Stop Recording").clicked() {
+                        capture.stop_recording();
+                    }
+                    ui.label(format!("{} frames", capture.recorded_frames()));
+                } else if ui.button("Record Frames").clicked() {
+                    capture.start_recording();
+                }
+            });
+            ui.label(format!(
+                "Saved to {}/, without the UI",
+                capture::CAPTURE_DIR
+            ));
+            if capture.pending() > 0 {
+                ui.label(format!("Reading back {} frames", capture.pending()));
+            }
+        });
+    capture.open = open;
+}
+
+/// A bar with a mark per keyframe and the playhead at `time`, clicked or dragged to scrub.
+fn timeline(ui: &mut egui::Ui, path: &CameraPath, time: &mut f32) -> egui::Response {
+    let end = path
+        .keyframes
+        .last()
+        .map_or(0.0, |keyframe| keyframe.time)
+        .max(*time)
+        .max(1.0);
+    let size = egui::vec2(ui.available_width(), 24.0);
+    let (rect, mut response) = ui.allocate_exact_size(size, egui::Sense::click_and_drag());
+    if let Some(pointer) = response.interact_pointer_pos() {
+        *time = egui::remap_clamp(pointer.x, rect.x_range(), 0.0..=end);
+        response.mark_changed();
+    }
+    let x = |time: f32| egui::remap_clamp(time, 0.0..=end, rect.x_range());
+    let painter = ui.painter_at(rect);
+    painter.rect_filled(rect, 2.0, ui.visuals().extreme_bg_color);
+    let marks = ui.visuals().widgets.inactive.fg_stroke.color;
+    for keyframe in &path.keyframes {
+        let x = x(keyframe.time);
+        painter.line_segment(
+            [
+                egui::pos2(x, rect.top() + 4.0),
+                egui::pos2(x, rect.bottom() - 4.0),
+            ],
+            egui::Stroke::new(2.0, marks),
+        );
+    }
+    painter.line_segment(
+        [
+            egui::pos2(x(*time), rect.top()),
+            egui::pos2(x(*time), rect.bottom()),
+        ],
+        egui::Stroke::new(2.0, egui::Color32::RED),
+    );
+    response
+}
+
+/// Frame time graphs and the per-pass breakdown of the [Profiler].
+fn profiler_window(ctx: &egui::Context, profiler: &mut Profiler) {
+    let milliseconds = |duration: Duration| duration.as_secs_f64() * 1000.0;
+    let mut open = profiler.open;
+    egui::Window::new("Profiler")
+        .open(&mut open)
+        .default_size([420.0, 400.0])
+        .show(ctx, |ui| {
+            ui.horizontal(|ui| {
+                ui.checkbox(&mut profiler.paused, "Pause");
+                if ui.button("Export Chrome Trace").clicked() {
+                    let timestamp = SystemTime::now()
+                        .duration_since(UNIX_EPOCH)
+                        .unwrap_or_default()
+                        .as_secs();
+                    let path = format!("trace_{}.json", timestamp);
+                    match profiler.export_chrome_trace(path.as_ref()) {
+                        Ok(()) => info!(path, "Exported the profiler history"),
+                        Err(error) => warn!(
+                            path,
+                            error = error::report(&error),
+                            "Failed to export the profiler history"
+                        ),
+                    }
+                }
+            });
+            if !profiler.gpu_supported() {
+                ui.label("GPU timings require Features::TIMESTAMP_QUERY.");
+            }
+
+            let frames = profiler.frames();
+            if let Some(last) = frames.back() {
+                ui.label(format!(
+                    "Frame {:.2} ms, CPU {:.2} ms, GPU {}, {} draw calls",
+                    milliseconds(last.duration),
+                    milliseconds(last.cpu_time),
+                    frames
+                        .iter()
+                        .rev()
+                        .find_map(|frame| frame.gpu_time())
+                        .map_or("-".to_string(), |gpu| format!(
+                            "{:.2} ms",
+                            milliseconds(gpu)
+                        )),
+                    last.draw_calls
+                ));
+            }
+            let cpu = frames
+                .iter()
+                .map(|frame| [frame.index as f64, milliseconds(frame.duration)])
+                .collect::<egui::plot::PlotPoints>();
+            let gpu = frames
+                .iter()
+                .filter_map(|frame| Some([frame.index as f64, milliseconds(frame.gpu_time()?)]))
+                .collect::<egui::plot::PlotPoints>();
+            egui::plot::Plot::new("Frame Times")
+                .height(140.0)
+                .include_y(0.0)
+                .allow_drag(false)
+                .allow_zoom(false)
+                .legend(egui::plot::Legend::default())
+                .show(ui, |plot| {
+                    plot.line(egui::plot::Line::new(cpu).name("Frame (ms)"));
+                    plot.line(egui::plot::Line::new(gpu).name("GPU (ms)"));
+                });
+
+            ui.separator();
+            egui::Grid::new("Pass Breakdown")
+                .striped(true)
+                .num_columns(3)
+                .show(ui, |ui| {
+                    ui.strong("Pass");
+                    ui.strong("CPU (ms)");
+                    ui.strong("GPU (ms)");
+                    ui.end_row();
+                    let format = |duration: Option<Duration>| {
+                        duration.map_or("-".to_string(), |duration| {
+                            format!("{:.3}", milliseconds(duration))
+                        })
+                    };
+                    for (name, cpu, gpu) in profiler.pass_breakdown() {
+                        ui.label(name);
+                        ui.label(format(cpu));
+                        ui.label(format(gpu));
+                        ui.end_row();
+                    }
+                });
+        });
+    profiler.open = open;
+}
+
+/// The recent tracing events, filtered by level and text.
+fn log_console_window(ctx: &egui::Context, console: &mut LogConsole) {
+    let mut open = console.open;
+    egui::Window::new("Log Console")
+        .open(&mut open)
+        .default_size([600.0, 300.0])
+        .resizable(true)
+        .show(ctx, |ui| {
+            ui.horizontal(|ui| {
+                egui::ComboBox::from_label("Level")
+                    .selected_text(console.level.as_str())
+                    .show_ui(ui, |ui| {
+                        for level in LogConsole::levels() {
+                            ui.selectable_value(&mut console.level, level, level.as_str());
+                        }
+                    });
+                ui.label("Search");
+                ui.text_edit_singleline(&mut console.search);
+                if ui.button("Clear").clicked() {
+                    console.clear();
+                }
+            });
+            ui.separator();
+            egui::ScrollArea::vertical()
+                .auto_shrink([false, false])
+                .stick_to_bottom(true)
+                .show(ui, |ui| {
+                    for event in console.events() {
+                        let color = match event.level {
+                            Level::ERROR => egui::Color32::RED,
+                            Level::WARN => egui::Color32::YELLOW,
+                            Level::INFO => egui::Color32::LIGHT_GREEN,
+                            _ => egui::Color32::GRAY,
+                        };
+                        ui.horizontal_wrapped(|ui| {
+                            ui.monospace(format!("{:>9.3}s", event.time.as_secs_f32()));
+                            ui.label(
+                                egui::RichText::new(event.level.as_str())
+                                    .monospace()
+                                    .color(color),
+                            );
+                            ui.label(egui::RichText::new(event.target).monospace().weak());
+                            ui.label(egui::RichText::new(event.message).monospace());
+                        });
+                    }
+                });
+        });
+    console.open = open;
+}
+
+/// The tessellated user interface of a frame, ready to be painted by the [UiPass].
+pub struct UiFrame {
+    paint_jobs: Vec<egui::ClippedPrimitive>,
+    screen_descriptor: ScreenDescriptor,
+    textures_delta: egui::TexturesDelta,
+}
+
+/// Paints the user interface on top of the surface.
+pub struct UiPass {
+    render_pass: egui_wgpu_backend::RenderPass,
+}
+
+impl UiPass {
+    pub fn new(render_pass: egui_wgpu_backend::RenderPass) -> Self {
+        Self { render_pass }
+    }
+}
+
+impl Pass for UiPass {
+    fn name(&self) -> &'static str {
+        "UI Pass"
+    }
+
+    fn writes(&self) -> &[&'static str] {
+        &[SURFACE]
+    }
+
+    fn execute(&mut self, ctx: &mut PassContext) {
+        let Some(frame) = ctx.ui.take() else {
+            return;
+        };
+        let renderer = ctx.renderer;
+
+        // Upload all resources for the GPU.
         self.render_pass
-            .add_textures(&renderer.device, &renderer.queue, &tdelta)
+            .add_textures(&renderer.device, &renderer.queue, &frame.textures_delta)
             .unwrap();
 
         self.render_pass.update_buffers(
             &renderer.device,
             &renderer.queue,
-            &paint_jobs,
-            &screen_descriptor,
+            &frame.paint_jobs,
+            &frame.screen_descriptor,
         );
 
-        let view = texture
-            .texture
-            .create_view(&wgpu::TextureViewDescriptor::default());
         // Record all render passes
         self.render_pass
-            .execute(encoder, &view, &paint_jobs, &screen_descriptor, None)
+            .execute(
+                ctx.encoder,
+                ctx.resources.view(SURFACE),
+                &frame.paint_jobs,
+                &frame.screen_descriptor,
+                None,
+            )
             .unwrap();
 
         self.render_pass
-            .remove_textures(tdelta)
+            .remove_textures(frame.textures_delta)
             .expect("Failed to remove texture");
-
-        drop(_guard);
     }
 }
@@ -1,9 +1,52 @@
+use std::{fmt, io, path::PathBuf};
+
 use wgpu::{RequestDeviceError, SurfaceError};
 
 /// Represents any error that may be triggered by the VoxelEngine.
 #[derive(Debug)]
 pub enum Error {
     Render(RendererError),
+    /// The window couldn't be created.
+    Window(winit::error::OsError),
+    /// A file of the assets directory couldn't be read.
+    Asset {
+        path: PathBuf,
+        source: io::Error,
+    },
+    /// A shader permutation failed to preprocess, validate or create its pipelines.
+    Shader {
+        name: String,
+        message: String,
+    },
+    Io(io::Error),
+    /// The command-line arguments couldn't be parsed.
+    InvalidArguments(String),
+    /// An input recording couldn't be written or replayed.
+    InputRecording {
+        path: PathBuf,
+        message: String,
+    },
+    /// A camera path couldn't be loaded or saved.
+    CameraPath {
+        path: PathBuf,
+        message: String,
+    },
+    /// A screenshot or a frame of a sequence couldn't be written.
+    Capture {
+        path: PathBuf,
+        message: String,
+    },
+    /// A benchmark report couldn't be written.
+    BenchmarkReport {
+        path: PathBuf,
+        source: io::Error,
+    },
+    /// A world couldn't be saved or loaded.
+    #[allow(dead_code)] // Worlds aren't saved to disk yet.
+    WorldSerialization {
+        path: PathBuf,
+        message: String,
+    },
 }
 
 #[derive(Debug)]
@@ -11,6 +54,135 @@ pub enum RendererError {
     AdapterNotFound,
     RequestDeviceError(wgpu::RequestDeviceError),
     SurfaceError(wgpu::SurfaceError),
+    /// The device stopped working, e.g. after a driver reset, the renderer must be recreated.
+    DeviceLost,
+    /// The passes of the render graph depend on each other, lists the unscheduled passes.
+    RenderGraphCycle(String),
+    /// A pass reads a resource that no pass writes.
+    RenderGraphMissingInput {
+        pass: &'static str,
+        resource: &'static str,
+    },
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Render(error) => write!(f, "{}", error),
+            Self::Window(_) => write!(f, "failed to create the window"),
+            Self::Asset { path, .. } => write!(f, "failed to load the asset {}", path.display()),
+            Self::Shader { name, message } => {
+                write!(f, "failed to compile the shader {}:\n{}", name, message)
+            }
+            Self::Io(_) => write!(f, "input/output error"),
+            Self::InvalidArguments(message) => {
+                write!(f, "invalid command-line arguments: {}", message)
+            }
+            Self::InputRecording { path, message } => {
+                write!(
+                    f,
+                    "failed to record or replay the input {}: {}",
+                    path.display(),
+                    message
+                )
+            }
+            Self::CameraPath { path, message } => {
+                write!(
+                    f,
+                    "failed to load or save the camera path {}: {}",
+                    path.display(),
+                    message
+                )
+            }
+            Self::Capture { path, message } => {
+                write!(
+                    f,
+                    "failed to write the capture {}: {}",
+                    path.display(),
+                    message
+                )
+            }
+            Self::BenchmarkReport { path, .. } => {
+                write!(f, "failed to write the benchmark report {}", path.display())
+            }
+            Self::WorldSerialization { path, message } => {
+                write!(
+                    f,
+                    "failed to save or load the world {}: {}",
+                    path.display(),
+                    message
+                )
+            }
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Render(error) => error.source(),
+            Self::Window(error) => Some(error),
+            Self::Asset { source, .. } | Self::BenchmarkReport { source, .. } => Some(source),
+            Self::Io(error) => Some(error),
+            Self::Shader { .. }
+            | Self::InvalidArguments(_)
+            | Self::InputRecording { .. }
+            | Self::CameraPath { .. }
+            | Self::Capture { .. }
+            | Self::WorldSerialization { .. } => None,
+        }
+    }
+}
+
+impl fmt::Display for RendererError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::AdapterNotFound => write!(
+                f,
+                "no compatible graphics adapter was found, \
+                 make sure the GPU drivers support Vulkan, Metal, DirectX 12 or OpenGL"
+            ),
+            Self::RequestDeviceError(_) => write!(f, "failed to open the graphics device"),
+            Self::SurfaceError(_) => write!(f, "failed to acquire the window surface"),
+            Self::DeviceLost => write!(f, "the graphics device was lost"),
+            Self::RenderGraphCycle(passes) => {
+                write!(
+                    f,
+                    "the render graph passes depend on each other: {}",
+                    passes
+                )
+            }
+            Self::RenderGraphMissingInput { pass, resource } => write!(
+                f,
+                "the render graph pass {} reads {}, which no pass writes",
+                pass, resource
+            ),
+        }
+    }
+}
+
+impl std::error::Error for RendererError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::RequestDeviceError(error) => Some(error),
+            Self::SurfaceError(error) => Some(error),
+            Self::AdapterNotFound
+            | Self::DeviceLost
+            | Self::RenderGraphCycle(_)
+            | Self::RenderGraphMissingInput { .. } => None,
+        }
+    }
+}
+
+/// Formats an error followed by its sources, one per line.
+pub fn report(error: &dyn std::error::Error) -> String {
+    let mut report = error.to_string();
+    let mut source = error.source();
+    while let Some(error) = source {
+        report.push_str(&format!("\n  caused by: {}", error));
+        source = error.source();
+    }
+    report
 }
 
 /// Cast RendererError back to base Error
@@ -19,6 +191,19 @@ impl From<RendererError> for Error {
         Self::Render(error)
     }
 }
+
+impl From<winit::error::OsError> for Error {
+    fn from(error: winit::error::OsError) -> Self {
+        Self::Window(error)
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(error: io::Error) -> Self {
+        Self::Io(error)
+    }
+}
+
 /// Cast WGPU builtin [RequestDeviceError] to [RendererError]
 impl From<RequestDeviceError> for RendererError {
     fn from(error: RequestDeviceError) -> Self {
@@ -0,0 +1,372 @@
+//! Gamepad input through gilrs, mapped onto the camera movement and look of the keyboard
+//! and mouse, see [GamepadSettings] for the dead zones and the response curve.
+
+use std::collections::{BTreeMap, VecDeque};
+
+use serde::{Deserialize, Serialize};
+use tracing::info;
+use vek::Vec2;
+
+/// A source of gamepad events, implemented by [GilrsSource] and by mocks in tests.
+pub trait GamepadSource {
+    /// Returns the next pending event, `None` once there are no more.
+    fn next_event(&mut self) -> Option<GamepadEvent>;
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum GamepadEvent {
+    Connected {
+        id: usize,
+        name: String,
+    },
+    Disconnected {
+        id: usize,
+    },
+    /// A stick axis or trigger moved, sticks are in `-1..=1` and triggers in `0..=1`.
+    Axis {
+        id: usize,
+        axis: Axis,
+        value: f32,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Axis {
+    LeftStickX,
+    /// Up is positive.
+    LeftStickY,
+    RightStickX,
+    /// Up is positive.
+    RightStickY,
+    LeftTrigger,
+    RightTrigger,
+}
+
+/// Discrete actions triggered by the gamepad.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    /// Left trigger.
+    Break,
+    /// Right trigger.
+    Place,
+}
+
+/// Saved with the [crate::settings::Settings].
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct GamepadSettings {
+    /// Stick deflection ignored around the center, from 0 to 1.
+    pub dead_zone: f32,
+    /// Exponent of the response curve after the dead zone, 1 is linear and higher values give
+    /// finer control near the center.
+    pub curve: f32,
+    /// Camera movement steps per second at full deflection, a step being a key press.
+    pub move_speed: f32,
+    /// Mouse motion units per second at full deflection.
+    pub look_sensitivity: f32,
+    /// Inverts the vertical look axis.
+    pub invert_y: bool,
+    /// Trigger value above which its action is triggered.
+    pub trigger_threshold: f32,
+}
+
+impl Default for GamepadSettings {
+    fn default() -> Self {
+        Self {
+            dead_zone: 0.15,
+            curve: 2.0,
+            move_speed: 30.0,
+            look_sensitivity: 600.0,
+            invert_y: false,
+            trigger_threshold: 0.5,
+        }
+    }
+}
+
+impl GamepadSettings {
+    /// Applies the radial dead zone and the response curve to a stick, keeping its direction.
+    pub fn shape_stick(&self, stick: Vec2<f32>) -> Vec2<f32> {
+        let magnitude = stick.magnitude().min(1.0);
+        if magnitude <= self.dead_zone {
+            return Vec2::zero();
+        }
+        let scaled = (magnitude - self.dead_zone) / (1.0 - self.dead_zone);
+        stick.normalized() * scaled.powf(self.curve)
+    }
+}
+
+/// The gamepad input of a frame.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct GamepadFrame {
+    /// Right and forward movement in camera steps.
+    pub movement: Vec2<f32>,
+    /// Look motion in mouse units.
+    pub look: Vec2<f64>,
+    /// Actions whose trigger was pressed since the last frame.
+    pub actions: Vec<Action>,
+}
+
+/// Tracks the connected gamepads, only the last one used controls the camera.
+pub struct Gamepads<S> {
+    source: S,
+    pads: BTreeMap<usize, PadState>,
+    active: Option<usize>,
+}
+
+#[derive(Default)]
+struct PadState {
+    axes: BTreeMap<Axis, f32>,
+    /// Triggers held above the threshold.
+    held: Vec<Action>,
+}
+
+impl PadState {
+    fn axis(&self, axis: Axis) -> f32 {
+        self.axes.get(&axis).copied().unwrap_or(0.0)
+    }
+}
+
+impl<S: GamepadSource> Gamepads<S> {
+    pub fn new(source: S) -> Self {
+        Self {
+            source,
+            pads: BTreeMap::new(),
+            active: None,
+        }
+    }
+
+    /// Processes the pending events and returns the input of a frame lasting `dt` seconds.
+    pub fn update(&mut self, settings: &GamepadSettings, dt: f32) -> GamepadFrame {
+        let mut frame = GamepadFrame::default();
+        while let Some(event) = self.source.next_event() {
+            match event {
+                GamepadEvent::Connected { id, name } => {
+                    info!(id, name, "Gamepad connected");
+                    self.pads.insert(id, PadState::default());
+                    self.active.get_or_insert(id);
+                }
+                GamepadEvent::Disconnected { id } => {
+                    info!(id, "Gamepad disconnected");
+                    self.pads.remove(&id);
+                    if self.active == Some(id) {
+                        self.active = self.pads.keys().next().copied();
+                    }
+                }
+                GamepadEvent::Axis { id, axis, value } => {
+                    let pad = self.pads.entry(id).or_default();
+                    pad.axes.insert(axis, value);
+                    self.active = Some(id);
+                    let action = match axis {
+                        Axis::LeftTrigger => Action::Break,
+                        Axis::RightTrigger => Action::Place,
+                        _ => continue,
+                    };
+                    let held = pad.held.contains(&action);
+                    if !held && value > settings.trigger_threshold {
+                        pad.held.push(action);
+                        frame.actions.push(action);
+                    } else if held && value <= settings.trigger_threshold {
+                        pad.held.retain(|held| *held != action);
+                    }
+                }
+            }
+        }
+
+        let Some(pad) = self.active.and_then(|id| self.pads.get(&id)) else {
+            return frame;
+        };
+        let left = Vec2::new(pad.axis(Axis::LeftStickX), pad.axis(Axis::LeftStickY));
+        let right = Vec2::new(pad.axis(Axis::RightStickX), pad.axis(Axis::RightStickY));
+        frame.movement = settings.shape_stick(left) * settings.move_speed * dt;
+        let look = settings.shape_stick(right) * settings.look_sensitivity * dt;
+        // Mouse motion is positive downwards.
+        let y = if settings.invert_y { look.y } else { -look.y };
+        frame.look = Vec2::new(look.x as f64, y as f64);
+        frame
+    }
+}
+
+/// Reads the gamepads of the system.
+pub struct GilrsSource {
+    gilrs: gilrs::Gilrs,
+    /// The pads connected before the sandbox started, reported first.
+    connected: VecDeque<GamepadEvent>,
+}
+
+impl GilrsSource {
+    pub fn new() -> Result<Self, String> {
+        let gilrs = gilrs::Gilrs::new().map_err(|error| error.to_string())?;
+        let connected = gilrs
+            .gamepads()
+            .map(|(id, gamepad)| GamepadEvent::Connected {
+                id: id.into(),
+                name: gamepad.name().to_string(),
+            })
+            .collect();
+        Ok(Self { gilrs, connected })
+    }
+}
+
+impl GamepadSource for GilrsSource {
+    fn next_event(&mut self) -> Option<GamepadEvent> {
+        if let Some(event) = self.connected.pop_front() {
+            return Some(event);
+        }
+        while let Some(gilrs::Event { id, event, .. }) = self.gilrs.next_event() {
+            let axis = |axis, value| {
+                Some(GamepadEvent::Axis {
+                    id: id.into(),
+                    axis,
+                    value,
+                })
+            };
+            let event = match event {
+                gilrs::EventType::Connected => Some(GamepadEvent::Connected {
+                    id: id.into(),
+                    name: self.gilrs.gamepad(id).name().to_string(),
+                }),
+                gilrs::EventType::Disconnected => {
+                    Some(GamepadEvent::Disconnected { id: id.into() })
+                }
+                gilrs::EventType::AxisChanged(gilrs::Axis::LeftStickX, value, _) => {
+                    axis(Axis::LeftStickX, value)
+                }
+                gilrs::EventType::AxisChanged(gilrs::Axis::LeftStickY, value, _) => {
+                    axis(Axis::LeftStickY, value)
+                }
+                gilrs::EventType::AxisChanged(gilrs::Axis::RightStickX, value, _) => {
+                    axis(Axis::RightStickX, value)
+                }
+                gilrs::EventType::AxisChanged(gilrs::Axis::RightStickY, value, _) => {
+                    axis(Axis::RightStickY, value)
+                }
+                gilrs::EventType::ButtonChanged(gilrs::Button::LeftTrigger2, value, _) => {
+                    axis(Axis::LeftTrigger, value)
+                }
+                gilrs::EventType::ButtonChanged(gilrs::Button::RightTrigger2, value, _) => {
+                    axis(Axis::RightTrigger, value)
+                }
+                _ => None,
+            };
+            if event.is_some() {
+                return event;
+            }
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Returns the events pushed since the last update.
+    #[derive(Default)]
+    struct MockSource {
+        events: VecDeque<GamepadEvent>,
+    }
+
+    impl GamepadSource for MockSource {
+        fn next_event(&mut self) -> Option<GamepadEvent> {
+            self.events.pop_front()
+        }
+    }
+
+    impl Gamepads<MockSource> {
+        fn push(&mut self, event: GamepadEvent) {
+            self.source.events.push_back(event);
+        }
+
+        fn axis(&mut self, id: usize, axis: Axis, value: f32) {
+            self.push(GamepadEvent::Axis { id, axis, value });
+        }
+    }
+
+    fn connected(ids: &[usize]) -> Gamepads<MockSource> {
+        let mut gamepads = Gamepads::new(MockSource::default());
+        for &id in ids {
+            gamepads.push(GamepadEvent::Connected {
+                id,
+                name: format!("Pad {}", id),
+            });
+        }
+        gamepads
+    }
+
+    #[test]
+    fn ignores_the_dead_zone() {
+        let settings = GamepadSettings::default();
+        assert_eq!(settings.shape_stick(Vec2::new(0.1, 0.1)), Vec2::zero());
+        assert!(settings.shape_stick(Vec2::new(0.2, 0.0)).x > 0.0);
+    }
+
+    #[test]
+    fn shapes_the_sticks_with_the_curve() {
+        let settings = GamepadSettings {
+            dead_zone: 0.0,
+            curve: 2.0,
+            ..GamepadSettings::default()
+        };
+        let half = settings.shape_stick(Vec2::new(0.0, -0.5));
+        assert!((half.y + 0.25).abs() < 1e-6);
+        assert_eq!(half.x, 0.0);
+        let full = settings.shape_stick(Vec2::new(1.0, 1.0));
+        assert!((full.magnitude() - 1.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn moves_and_looks_with_the_sticks() {
+        let settings = GamepadSettings {
+            dead_zone: 0.0,
+            curve: 1.0,
+            ..GamepadSettings::default()
+        };
+        let mut gamepads = connected(&[0]);
+        gamepads.axis(0, Axis::LeftStickY, 1.0);
+        gamepads.axis(0, Axis::RightStickX, -1.0);
+        let frame = gamepads.update(&settings, 0.5);
+        assert_eq!(frame.movement, Vec2::new(0.0, settings.move_speed * 0.5));
+        assert_eq!(frame.look.x, -(settings.look_sensitivity as f64) * 0.5);
+        // The sticks keep their value until they move again.
+        assert_eq!(gamepads.update(&settings, 0.5), frame);
+    }
+
+    #[test]
+    fn triggers_actions_once_per_press() {
+        let settings = GamepadSettings::default();
+        let mut gamepads = connected(&[0]);
+        gamepads.axis(0, Axis::RightTrigger, 0.6);
+        gamepads.axis(0, Axis::RightTrigger, 0.9);
+        assert_eq!(gamepads.update(&settings, 0.1).actions, [Action::Place]);
+        assert!(gamepads.update(&settings, 0.1).actions.is_empty());
+        gamepads.axis(0, Axis::RightTrigger, 0.1);
+        gamepads.axis(0, Axis::LeftTrigger, 1.0);
+        gamepads.axis(0, Axis::RightTrigger, 1.0);
+        assert_eq!(
+            gamepads.update(&settings, 0.1).actions,
+            [Action::Break, Action::Place]
+        );
+    }
+
+    #[test]
+    fn switches_pads_on_hot_plug() {
+        let settings = GamepadSettings {
+            dead_zone: 0.0,
+            curve: 1.0,
+            ..GamepadSettings::default()
+        };
+        let mut gamepads = connected(&[0, 1]);
+        gamepads.axis(1, Axis::LeftStickX, 1.0);
+        assert_eq!(
+            gamepads.update(&settings, 1.0).movement.x,
+            settings.move_speed
+        );
+        // The last pad used is disconnected, the remaining one takes over.
+        gamepads.push(GamepadEvent::Disconnected { id: 1 });
+        assert_eq!(gamepads.update(&settings, 1.0).movement, Vec2::zero());
+        assert_eq!(gamepads.pads.len(), 1);
+        gamepads.push(GamepadEvent::Disconnected { id: 0 });
+        gamepads.update(&settings, 1.0);
+        assert!(gamepads.pads.is_empty());
+    }
+}
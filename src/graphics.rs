@@ -0,0 +1,204 @@
+//! Graphics settings: backend, adapter, power preference, present mode and frame cap,
+//! saved with the [crate::settings::Settings] and overridden by the [crate::cli::Cli] options.
+
+use std::{
+    fmt,
+    time::{Duration, Instant},
+};
+
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+/// The `graphics` table of the settings file, e.g.
+///
+/// ```toml
+/// [graphics]
+/// backend = "vulkan"
+/// adapter = "NVIDIA"
+/// power_preference = "high"
+/// present_mode = "mailbox"
+/// frame_cap = 144
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct GraphicsSettings {
+    pub backend: Backend,
+    /// Adapter to use instead of the one picked from the power preference.
+    pub adapter: Option<AdapterSelection>,
+    pub power_preference: PowerPreference,
+    pub present_mode: PresentMode,
+    /// Maximum frames per second, unlimited when `None` or 0.
+    pub frame_cap: Option<u32>,
+}
+
+impl Default for GraphicsSettings {
+    fn default() -> Self {
+        Self {
+            backend: Backend::Auto,
+            adapter: None,
+            power_preference: PowerPreference::Low,
+            present_mode: PresentMode::Vsync,
+            frame_cap: None,
+        }
+    }
+}
+
+impl GraphicsSettings {
+    /// Whether going from `self` to `other` requires a new device, as opposed to
+    /// reconfiguring the surface.
+    pub fn needs_new_device(&self, other: &Self) -> bool {
+        self.backend != other.backend
+            || self.adapter != other.adapter
+            || self.power_preference != other.power_preference
+    }
+}
+
+/// Parses the lowercase name of an enum variant, the same way serde reads it from the file.
+pub fn parse_choice<T: DeserializeOwned>(value: &str) -> Result<T, String> {
+    T::deserialize(serde::de::value::StrDeserializer::<serde::de::value::Error>::new(value))
+        .map_err(|error| error.to_string())
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Backend {
+    /// Vulkan, Metal or DirectX 12, then DirectX 11 or OpenGL.
+    Auto,
+    Vulkan,
+    Metal,
+    Dx12,
+    Dx11,
+    Gl,
+}
+
+impl Backend {
+    pub const ALL: [Self; 6] = [
+        Self::Auto,
+        Self::Vulkan,
+        Self::Metal,
+        Self::Dx12,
+        Self::Dx11,
+        Self::Gl,
+    ];
+
+    pub fn backends(self) -> wgpu::Backends {
+        match self {
+            Self::Auto => wgpu::Backends::all(),
+            Self::Vulkan => wgpu::Backends::VULKAN,
+            Self::Metal => wgpu::Backends::METAL,
+            Self::Dx12 => wgpu::Backends::DX12,
+            Self::Dx11 => wgpu::Backends::DX11,
+            Self::Gl => wgpu::Backends::GL,
+        }
+    }
+}
+
+/// An adapter by index in the list of adapters of the backend, or by a part of its name.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(untagged)]
+pub enum AdapterSelection {
+    Index(usize),
+    Name(String),
+}
+
+impl AdapterSelection {
+    /// Index of the selected adapter in `adapters`.
+    pub fn find(&self, adapters: &[wgpu::AdapterInfo]) -> Option<usize> {
+        match self {
+            Self::Index(index) => (*index < adapters.len()).then_some(*index),
+            Self::Name(name) => {
+                let name = name.to_lowercase();
+                adapters
+                    .iter()
+                    .position(|info| info.name.to_lowercase().contains(&name))
+            }
+        }
+    }
+}
+
+impl fmt::Display for AdapterSelection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Index(index) => write!(f, "#{}", index),
+            Self::Name(name) => write!(f, "{}", name),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PowerPreference {
+    /// Usually an integrated GPU.
+    Low,
+    /// Usually a discrete GPU.
+    High,
+}
+
+impl PowerPreference {
+    pub const ALL: [Self; 2] = [Self::Low, Self::High];
+
+    pub fn wgpu(self) -> wgpu::PowerPreference {
+        match self {
+            Self::Low => wgpu::PowerPreference::LowPower,
+            Self::High => wgpu::PowerPreference::HighPerformance,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PresentMode {
+    /// Waits for the vertical blank, supported everywhere.
+    Vsync,
+    /// Waits for the vertical blank unless the frame is late, which then tears.
+    Relaxed,
+    /// Doesn't wait, the latest frame is shown at the vertical blank.
+    Mailbox,
+    /// Doesn't wait and tears.
+    Immediate,
+}
+
+impl PresentMode {
+    pub const ALL: [Self; 4] = [Self::Vsync, Self::Relaxed, Self::Mailbox, Self::Immediate];
+
+    /// The wgpu present mode if `supported`, otherwise the closest one that is.
+    pub fn select(self, supported: &[wgpu::PresentMode]) -> wgpu::PresentMode {
+        let preferred = match self {
+            Self::Vsync => wgpu::PresentMode::Fifo,
+            Self::Relaxed => wgpu::PresentMode::FifoRelaxed,
+            Self::Mailbox => wgpu::PresentMode::Mailbox,
+            Self::Immediate => wgpu::PresentMode::Immediate,
+        };
+        if supported.contains(&preferred) {
+            return preferred;
+        }
+        match self {
+            Self::Vsync | Self::Relaxed => wgpu::PresentMode::AutoVsync,
+            Self::Mailbox | Self::Immediate => wgpu::PresentMode::AutoNoVsync,
+        }
+    }
+}
+
+/// Sleeps between frames to respect [GraphicsSettings::frame_cap].
+pub struct FrameLimiter {
+    last_frame: Instant,
+}
+
+impl FrameLimiter {
+    pub fn new() -> Self {
+        Self {
+            last_frame: Instant::now(),
+        }
+    }
+
+    /// Waits until a frame can start at `frame_cap` frames per second, a cap of 0 doesn't wait.
+    pub fn wait(&mut self, frame_cap: Option<u32>) {
+        if let Some(cap) = frame_cap.filter(|&cap| cap > 0) {
+            let deadline = self.last_frame + Duration::from_secs_f64(1.0 / cap as f64);
+            let now = Instant::now();
+            if deadline > now {
+                std::thread::sleep(deadline - now);
+            }
+        }
+        self.last_frame = Instant::now();
+    }
+}
@@ -0,0 +1,395 @@
+//! Recording of the input processed by the event loop, and its replay.
+//!
+//! A recording is a JSON Lines file starting with a [Header], followed by one [RecordedEvent]
+//! per line. It's replayed with a fixed timestep instead of the recorded frame times, so a
+//! replay gives the same result on any machine. Gamepads aren't recorded, and are disabled
+//! while replaying.
+
+use std::{
+    collections::VecDeque,
+    fs::{self, File},
+    io::{BufWriter, Write},
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
+};
+
+use serde::{Deserialize, Serialize};
+use tracing::{info, warn};
+use vek::Vec2;
+use winit::{
+    dpi::PhysicalPosition,
+    event::{
+        DeviceEvent, DeviceId, ElementState, Event, KeyboardInput, ModifiersState, MouseButton,
+        MouseScrollDelta, TouchPhase, WindowEvent,
+    },
+    window::WindowId,
+};
+
+use crate::{benchmark::Benchmark, error::Error};
+
+/// Version written in the [Header].
+pub const VERSION: u32 = 1;
+/// Time advanced by each replayed frame.
+pub const REPLAY_TIMESTEP: Duration = Duration::from_micros(16_667);
+
+/// What the event loop does with the input.
+pub enum InputMode {
+    Live,
+    /// Handles the live input and records it.
+    Record(Recorder),
+    /// Ignores the live input and handles the recorded one.
+    Replay(Replay),
+    /// Ignores the live input, the camera flies along the path of the benchmark.
+    Benchmark(Benchmark),
+}
+
+/// The first line of a recording.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct Header {
+    pub version: u32,
+    /// Inner size of the window, the cursor positions are only meaningful at this size.
+    pub size: [u32; 2],
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct RecordedEvent {
+    /// Seconds since the recording started.
+    pub time: f64,
+    pub event: InputEvent,
+}
+
+/// The input events of [WindowEvent] and [DeviceEvent] that are recorded. Focus and resizes
+/// aren't, they depend on the window of the replay. A replay is never paused, so the mouse
+/// motion the camera ignored while recording isn't recorded either.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum InputEvent {
+    Keyboard {
+        input: KeyboardInput,
+    },
+    Character {
+        character: char,
+    },
+    Modifiers {
+        modifiers: ModifiersState,
+    },
+    CursorMoved {
+        position: [f64; 2],
+    },
+    CursorEntered,
+    CursorLeft,
+    MouseButton {
+        button: MouseButton,
+        state: ElementState,
+    },
+    MouseWheel {
+        delta: MouseScrollDelta,
+    },
+    /// Raw mouse motion, used for the camera.
+    MouseMotion {
+        delta: [f64; 2],
+    },
+}
+
+impl InputEvent {
+    pub fn from_event(event: &Event<()>) -> Option<Self> {
+        let event = match event {
+            Event::WindowEvent { event, .. } => event,
+            Event::DeviceEvent {
+                event: DeviceEvent::MouseMotion { delta },
+                ..
+            } => {
+                return Some(Self::MouseMotion {
+                    delta: [delta.0, delta.1],
+                })
+            }
+            _ => return None,
+        };
+        Some(match event {
+            WindowEvent::KeyboardInput { input, .. } => Self::Keyboard { input: *input },
+            WindowEvent::ReceivedCharacter(character) => Self::Character {
+                character: *character,
+            },
+            WindowEvent::ModifiersChanged(modifiers) => Self::Modifiers {
+                modifiers: *modifiers,
+            },
+            WindowEvent::CursorMoved { position, .. } => Self::CursorMoved {
+                position: [position.x, position.y],
+            },
+            WindowEvent::CursorEntered { .. } => Self::CursorEntered,
+            WindowEvent::CursorLeft { .. } => Self::CursorLeft,
+            WindowEvent::MouseInput { button, state, .. } => Self::MouseButton {
+                button: *button,
+                state: *state,
+            },
+            WindowEvent::MouseWheel { delta, .. } => Self::MouseWheel { delta: *delta },
+            _ => return None,
+        })
+    }
+
+    /// The event as received from winit by the window `window_id`.
+    pub fn to_event(&self, window_id: WindowId) -> Event<'static, ()> {
+        // SAFETY: the id is only compared by the handlers, it's never passed to winit.
+        let device_id = unsafe { DeviceId::dummy() };
+        #[allow(deprecated)] // The modifiers fields are required to build the events.
+        let event = match *self {
+            Self::Keyboard { input } => WindowEvent::KeyboardInput {
+                device_id,
+                input,
+                is_synthetic: false,
+            },
+            Self::Character { character } => WindowEvent::ReceivedCharacter(character),
+            Self::Modifiers { modifiers } => WindowEvent::ModifiersChanged(modifiers),
+            Self::CursorMoved { position: [x, y] } => WindowEvent::CursorMoved {
+                device_id,
+                position: PhysicalPosition::new(x, y),
+                modifiers: ModifiersState::empty(),
+            },
+            Self::CursorEntered => WindowEvent::CursorEntered { device_id },
+            Self::CursorLeft => WindowEvent::CursorLeft { device_id },
+            Self::MouseButton { button, state } => WindowEvent::MouseInput {
+                device_id,
+                state,
+                button,
+                modifiers: ModifiersState::empty(),
+            },
+            Self::MouseWheel { delta } => WindowEvent::MouseWheel {
+                device_id,
+                delta,
+                phase: TouchPhase::Moved,
+                modifiers: ModifiersState::empty(),
+            },
+            Self::MouseMotion { delta: [x, y] } => {
+                return Event::DeviceEvent {
+                    device_id,
+                    event: DeviceEvent::MouseMotion { delta: (x, y) },
+                }
+            }
+        };
+        Event::WindowEvent { window_id, event }
+    }
+}
+
+/// Whether `event` is live input that a replay replaces, including the focus changes that
+/// would pause it.
+pub fn is_live_input(event: &Event<()>) -> bool {
+    matches!(
+        event,
+        Event::WindowEvent {
+            event: WindowEvent::Focused(_),
+            ..
+        } | Event::DeviceEvent { .. }
+    ) || InputEvent::from_event(event).is_some()
+}
+
+/// Writes the input events to a recording.
+pub struct Recorder {
+    path: PathBuf,
+    /// `None` after a write failed.
+    writer: Option<BufWriter<File>>,
+    start: Instant,
+}
+
+impl Recorder {
+    pub fn create(path: &Path, size: Vec2<u32>) -> Result<Self, Error> {
+        let error = |message: String| Error::InputRecording {
+            path: path.to_path_buf(),
+            message,
+        };
+        let mut writer = BufWriter::new(File::create(path).map_err(|e| error(e.to_string()))?);
+        let header = Header {
+            version: VERSION,
+            size: size.into_array(),
+        };
+        write_line(&mut writer, &header).map_err(|e| error(e.to_string()))?;
+        info!(?path, "Recording the input");
+        Ok(Self {
+            path: path.to_path_buf(),
+            writer: Some(writer),
+            start: Instant::now(),
+        })
+    }
+
+    /// Records `event` if it's an [InputEvent].
+    pub fn record(&mut self, event: &Event<()>) {
+        let (Some(writer), Some(event)) = (&mut self.writer, InputEvent::from_event(event)) else {
+            return;
+        };
+        let recorded = RecordedEvent {
+            time: self.start.elapsed().as_secs_f64(),
+            event,
+        };
+        if let Err(error) = write_line(writer, &recorded) {
+            warn!(path = ?self.path, %error, "Failed to record the input, stopping");
+            self.writer = None;
+        }
+    }
+
+    /// Flushes the recording, called when the event loop exits.
+    pub fn finish(&mut self) {
+        let Some(mut writer) = self.writer.take() else {
+            return;
+        };
+        match writer.flush() {
+            Ok(()) => info!(path = ?self.path, "Saved the input recording"),
+            Err(error) => warn!(path = ?self.path, %error, "Failed to save the input recording"),
+        }
+    }
+}
+
+fn write_line(writer: &mut impl Write, value: &impl Serialize) -> std::io::Result<()> {
+    serde_json::to_writer(&mut *writer, value)?;
+    writer.write_all(b"\n")
+}
+
+/// Plays back a recording, [REPLAY_TIMESTEP] at a time.
+pub struct Replay {
+    pub header: Header,
+    events: VecDeque<RecordedEvent>,
+    /// Seconds of the recording replayed so far.
+    time: f64,
+}
+
+impl Replay {
+    pub fn load(path: &Path) -> Result<Self, Error> {
+        let error = |message: String| Error::InputRecording {
+            path: path.to_path_buf(),
+            message,
+        };
+        let source = fs::read_to_string(path).map_err(|e| error(e.to_string()))?;
+        // The positions of the errors are in the whole file.
+        let mut deserializer = serde_json::Deserializer::from_str(&source);
+        let header = Header::deserialize(&mut deserializer).map_err(|e| error(e.to_string()))?;
+        if header.version != VERSION {
+            return Err(error(format!(
+                "unsupported recording version {}",
+                header.version
+            )));
+        }
+        let events = deserializer
+            .into_iter()
+            .collect::<Result<VecDeque<RecordedEvent>, _>>()
+            .map_err(|e| error(e.to_string()))?;
+        info!(?path, events = events.len(), "Replaying the input");
+        Ok(Self {
+            header,
+            events,
+            time: 0.0,
+        })
+    }
+
+    /// Advances the replay by a frame, returning the events recorded during that frame.
+    pub fn step(&mut self) -> Vec<InputEvent> {
+        self.time += REPLAY_TIMESTEP.as_secs_f64();
+        let count = self
+            .events
+            .iter()
+            .take_while(|recorded| recorded.time < self.time)
+            .count();
+        self.events
+            .drain(..count)
+            .map(|recorded| recorded.event)
+            .collect()
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.events.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use winit::event::VirtualKeyCode;
+
+    use super::*;
+
+    fn key(key: VirtualKeyCode, state: ElementState) -> InputEvent {
+        #[allow(deprecated)]
+        InputEvent::Keyboard {
+            input: KeyboardInput {
+                scancode: 0,
+                state,
+                virtual_keycode: Some(key),
+                modifiers: ModifiersState::empty(),
+            },
+        }
+    }
+
+    #[test]
+    fn converts_events_back_and_forth() {
+        let window_id = unsafe { WindowId::dummy() };
+        for event in [
+            key(VirtualKeyCode::W, ElementState::Pressed),
+            InputEvent::Character { character: 'w' },
+            InputEvent::CursorMoved {
+                position: [12.5, 40.0],
+            },
+            InputEvent::MouseButton {
+                button: MouseButton::Left,
+                state: ElementState::Released,
+            },
+            InputEvent::MouseWheel {
+                delta: MouseScrollDelta::LineDelta(0.0, -1.0),
+            },
+            InputEvent::MouseMotion { delta: [3.0, -2.0] },
+        ] {
+            let converted = event.to_event(window_id);
+            assert!(is_live_input(&converted));
+            assert_eq!(InputEvent::from_event(&converted), Some(event));
+        }
+    }
+
+    #[test]
+    fn replays_a_recording_with_fixed_timesteps() {
+        // Unique to the process, the tests of several checkouts may run at the same time.
+        let path = std::env::temp_dir().join(format!(
+            "rusty_sandbox_replay_test_{}.jsonl",
+            std::process::id()
+        ));
+        let mut recorder = Recorder::create(&path, Vec2::new(800, 600)).unwrap();
+        let window_id = unsafe { WindowId::dummy() };
+        for event in [
+            key(VirtualKeyCode::W, ElementState::Pressed),
+            key(VirtualKeyCode::W, ElementState::Released),
+        ] {
+            recorder.record(&event.to_event(window_id));
+        }
+        recorder.record(&Event::WindowEvent {
+            window_id,
+            event: WindowEvent::Focused(false),
+        });
+        recorder.finish();
+
+        let mut replay = Replay::load(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(replay.header.size, [800, 600]);
+        // Recorded within a few microseconds, so replayed in the first frame.
+        assert_eq!(replay.step().len(), 2);
+        assert!(replay.is_finished());
+        assert!(replay.step().is_empty());
+    }
+
+    #[test]
+    fn groups_the_events_by_frame() {
+        let event = InputEvent::MouseMotion { delta: [1.0, 0.0] };
+        let timestep = REPLAY_TIMESTEP.as_secs_f64();
+        let mut replay = Replay {
+            header: Header {
+                version: VERSION,
+                size: [800, 600],
+            },
+            events: [0.0, 0.5, 2.5]
+                .into_iter()
+                .map(|frames| RecordedEvent {
+                    time: frames * timestep,
+                    event: event.clone(),
+                })
+                .collect(),
+            time: 0.0,
+        };
+        assert_eq!(replay.step().len(), 2);
+        assert!(replay.step().is_empty());
+        assert_eq!(replay.step().len(), 1);
+        assert!(replay.is_finished());
+    }
+}
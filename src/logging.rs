@@ -0,0 +1,363 @@
+//! Tracing setup: levels from `RUST_LOG` and the [CONFIG_FILE], output to stdout and
+//! optionally to rotating log files, and the recent events shown by the [LogConsole].
+
+use std::{
+    collections::{BTreeMap, VecDeque},
+    fmt::Write,
+    fs, io,
+    path::{Path, PathBuf},
+    sync::{LazyLock, Mutex, OnceLock},
+    time::{Duration, Instant},
+};
+
+use serde::Deserialize;
+use tracing::{
+    field::{Field, Visit},
+    warn, Event, Level, Subscriber,
+};
+use tracing_appender::rolling::{RollingFileAppender, Rotation};
+use tracing_subscriber::{filter::LevelFilter, layer::Context, prelude::*, EnvFilter, Layer};
+
+use crate::profiler::ProfilerLayer;
+
+/// Optional logging configuration, read from the working directory.
+pub const CONFIG_FILE: &str = "logging.toml";
+/// Number of events kept for the log console.
+const CONSOLE_CAPACITY: usize = 1000;
+
+static START: LazyLock<Instant> = LazyLock::new(Instant::now);
+static EVENTS: Mutex<VecDeque<LogEvent>> = Mutex::new(VecDeque::new());
+/// Most verbose level the filter of the [ConsoleLayer] may let through.
+static CONSOLE_MAX_LEVEL: OnceLock<LevelFilter> = OnceLock::new();
+
+/// Contents of the [CONFIG_FILE], e.g.
+///
+/// ```toml
+/// level = "info"
+///
+/// [modules]
+/// wgpu_core = "warn"
+/// "rusty_sandbox::shader_manager" = "debug"
+///
+/// [file]
+/// directory = "logs"
+/// rotation = "daily"
+/// max_files = 7
+/// ```
+///
+/// Directives of `RUST_LOG` take precedence over the file.
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LogConfig {
+    /// Level of the modules without their own level.
+    pub level: String,
+    /// Level per module path.
+    pub modules: BTreeMap<String, String>,
+    /// Also writes the logs to files when present.
+    pub file: Option<LogFileConfig>,
+}
+
+impl Default for LogConfig {
+    fn default() -> Self {
+        Self {
+            level: "info".to_string(),
+            modules: BTreeMap::new(),
+            file: None,
+        }
+    }
+}
+
+impl LogConfig {
+    /// Returns the default configuration if the file doesn't exist.
+    pub fn load(path: &Path) -> Result<Self, String> {
+        match fs::read_to_string(path) {
+            Ok(source) => toml::from_str(&source).map_err(|error| error.to_string()),
+            Err(error) if error.kind() == io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(error) => Err(error.to_string()),
+        }
+    }
+
+    /// The levels as an [EnvFilter] directive list.
+    fn directives(&self) -> String {
+        let mut directives = self.level.clone();
+        for (module, level) in &self.modules {
+            write!(directives, ",{}={}", module, level).unwrap();
+        }
+        directives
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LogFileConfig {
+    pub directory: PathBuf,
+    pub rotation: LogRotation,
+    /// Older files are deleted, 0 keeps every file.
+    pub max_files: usize,
+}
+
+impl Default for LogFileConfig {
+    fn default() -> Self {
+        Self {
+            directory: PathBuf::from("logs"),
+            rotation: LogRotation::Daily,
+            max_files: 7,
+        }
+    }
+}
+
+impl LogFileConfig {
+    fn appender(&self) -> Result<RollingFileAppender, String> {
+        let rotation = match self.rotation {
+            LogRotation::Minutely => Rotation::MINUTELY,
+            LogRotation::Hourly => Rotation::HOURLY,
+            LogRotation::Daily => Rotation::DAILY,
+            LogRotation::Never => Rotation::NEVER,
+        };
+        let mut builder = RollingFileAppender::builder()
+            .rotation(rotation)
+            .filename_prefix(env!("CARGO_PKG_NAME"))
+            .filename_suffix("log");
+        if self.max_files > 0 {
+            builder = builder.max_log_files(self.max_files);
+        }
+        builder
+            .build(&self.directory)
+            .map_err(|error| error.to_string())
+    }
+}
+
+/// How often a new log file is started.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogRotation {
+    Minutely,
+    Hourly,
+    Daily,
+    Never,
+}
+
+/// Installs the global subscriber, `directives` from the command line take precedence over
+/// `RUST_LOG`.
+///
+/// An invalid [CONFIG_FILE] is reported once logging works, the defaults are used instead.
+pub fn init(directives_override: Option<&str>) {
+    let (config, config_error) = match LogConfig::load(Path::new(CONFIG_FILE)) {
+        Ok(config) => (config, None),
+        Err(error) => (LogConfig::default(), Some(error)),
+    };
+    let env = std::env::var(EnvFilter::DEFAULT_ENV).ok();
+    let directives = merge_directives(&config, env.as_deref(), directives_override);
+    let filter = EnvFilter::builder().parse_lossy(directives);
+    CONSOLE_MAX_LEVEL.get_or_init(|| filter.max_level_hint().unwrap_or(LevelFilter::TRACE));
+
+    let (appender, file_error) = match config.file.as_ref().map(LogFileConfig::appender) {
+        Some(Ok(appender)) => (Some(appender), None),
+        Some(Err(error)) => (None, Some(error)),
+        None => (None, None),
+    };
+    let file_layer = appender.map(|appender| {
+        tracing_subscriber::fmt::layer()
+            .with_ansi(false)
+            .with_writer(appender)
+    });
+
+    // The profiler sees every span, including the trace level ones of the render graph passes.
+    tracing_subscriber::registry()
+        .with(
+            tracing_subscriber::fmt::layer()
+                .and_then(file_layer)
+                .and_then(ConsoleLayer)
+                .with_filter(filter),
+        )
+        .with(ProfilerLayer)
+        .init();
+
+    if let Some(error) = config_error {
+        warn!(file = CONFIG_FILE, %error, "Invalid logging config, using the defaults");
+    }
+    if let Some(error) = file_error {
+        warn!(%error, "Failed to open the log file");
+    }
+}
+
+/// Joins the directives of the config, `RUST_LOG` and the command line, the later ones take
+/// precedence.
+fn merge_directives(config: &LogConfig, env: Option<&str>, command_line: Option<&str>) -> String {
+    let mut directives = config.directives();
+    for extra in [env, command_line].into_iter().flatten() {
+        directives.push(',');
+        directives.push_str(extra);
+    }
+    directives
+}
+
+/// A tracing event kept for the [LogConsole].
+#[derive(Debug, Clone)]
+pub struct LogEvent {
+    /// Time since the logging was initialized.
+    pub time: Duration,
+    pub level: Level,
+    pub target: &'static str,
+    /// The message followed by the other fields.
+    pub message: String,
+}
+
+/// Keeps the last [CONSOLE_CAPACITY] events.
+struct ConsoleLayer;
+
+#[derive(Default)]
+struct MessageVisitor {
+    message: String,
+    fields: String,
+}
+
+impl Visit for MessageVisitor {
+    fn record_debug(&mut self, field: &Field, value: &dyn std::fmt::Debug) {
+        if field.name() == "message" {
+            write!(self.message, "{:?}", value).unwrap();
+        } else {
+            write!(self.fields, " {}={:?}", field.name(), value).unwrap();
+        }
+    }
+}
+
+impl<S: Subscriber> Layer<S> for ConsoleLayer {
+    fn on_event(&self, event: &Event<'_>, _ctx: Context<'_, S>) {
+        let mut visitor = MessageVisitor::default();
+        event.record(&mut visitor);
+        let log_event = LogEvent {
+            time: START.elapsed(),
+            level: *event.metadata().level(),
+            target: event.metadata().target(),
+            message: visitor.message + &visitor.fields,
+        };
+        let mut events = EVENTS.lock().unwrap();
+        if events.len() == CONSOLE_CAPACITY {
+            events.pop_front();
+        }
+        events.push_back(log_event);
+    }
+}
+
+/// State of the in-game log console window.
+pub struct LogConsole {
+    pub open: bool,
+    /// Most verbose level shown.
+    pub level: Level,
+    /// Only shows the events containing this text.
+    pub search: String,
+}
+
+impl LogConsole {
+    pub const LEVELS: [Level; 5] = [
+        Level::ERROR,
+        Level::WARN,
+        Level::INFO,
+        Level::DEBUG,
+        Level::TRACE,
+    ];
+
+    pub fn new() -> Self {
+        Self {
+            open: false,
+            level: Self::levels()
+                .last()
+                .unwrap_or(Level::ERROR)
+                .min(Level::INFO),
+            search: String::new(),
+        }
+    }
+
+    /// The [Self::LEVELS] that the log filter lets through, the others are never recorded.
+    pub fn levels() -> impl Iterator<Item = Level> {
+        let max = CONSOLE_MAX_LEVEL
+            .get()
+            .copied()
+            .unwrap_or(LevelFilter::TRACE);
+        Self::LEVELS.into_iter().filter(move |level| *level <= max)
+    }
+
+    /// The recorded events matching the level and search text, oldest first.
+    pub fn events(&self) -> Vec<LogEvent> {
+        EVENTS
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|event| event.level <= self.level)
+            .filter(|event| {
+                self.search.is_empty()
+                    || event.message.contains(&self.search)
+                    || event.target.contains(&self.search)
+            })
+            .cloned()
+            .collect()
+    }
+
+    pub fn clear(&self) {
+        EVENTS.lock().unwrap().clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The most verbose level enabled for this module by the directives.
+    fn most_verbose(directives: &str) -> Option<Level> {
+        let subscriber = tracing_subscriber::registry()
+            .with(tracing_subscriber::fmt::layer().with_filter(EnvFilter::new(directives)));
+        tracing::subscriber::with_default(subscriber, || {
+            [
+                (Level::TRACE, tracing::enabled!(Level::TRACE)),
+                (Level::DEBUG, tracing::enabled!(Level::DEBUG)),
+                (Level::INFO, tracing::enabled!(Level::INFO)),
+                (Level::WARN, tracing::enabled!(Level::WARN)),
+                (Level::ERROR, tracing::enabled!(Level::ERROR)),
+            ]
+            .into_iter()
+            .find_map(|(level, enabled)| enabled.then_some(level))
+        })
+    }
+
+    #[test]
+    fn merges_the_directives_in_order_of_precedence() {
+        let config: LogConfig = toml::from_str(
+            r#"
+            level = "warn"
+            [modules]
+            "rusty_sandbox::logging" = "error"
+            "#,
+        )
+        .unwrap();
+        let merged = |env, command_line| merge_directives(&config, env, command_line);
+        assert_eq!(merged(None, None), "warn,rusty_sandbox::logging=error");
+        assert_eq!(
+            merged(Some("debug"), Some("info")),
+            "warn,rusty_sandbox::logging=error,debug,info"
+        );
+        assert_eq!(most_verbose(&merged(None, None)), Some(Level::ERROR));
+        // `RUST_LOG` overrides the file and the command line overrides both.
+        let module = "rusty_sandbox::logging=debug";
+        assert_eq!(
+            most_verbose(&merged(Some(module), None)),
+            Some(Level::DEBUG)
+        );
+        let command_line = "rusty_sandbox::logging=trace";
+        assert_eq!(
+            most_verbose(&merged(Some(module), Some(command_line))),
+            Some(Level::TRACE)
+        );
+        assert_eq!(
+            most_verbose(&merged(Some(command_line), Some(module))),
+            Some(Level::DEBUG)
+        );
+    }
+
+    #[test]
+    fn defaults_to_info() {
+        let directives = merge_directives(&LogConfig::default(), None, None);
+        assert_eq!(directives, "info");
+        assert_eq!(most_verbose(&directives), Some(Level::INFO));
+    }
+}
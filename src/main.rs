@@ -1,79 +1,344 @@
+use benchmark::Benchmark;
+use camera_path::CameraPath;
+use clap::Parser;
+use cli::Cli;
 use client::Client;
-use error::RendererError;
+use error::{Error, RendererError};
+use input_recording::{InputMode, Recorder, Replay};
+use settings::{Settings, SettingsFile};
 
-use tracing::{span, Level};
-use tracing_subscriber::util::SubscriberInitExt;
+use tracing::{error, info, span, warn, Level};
 use winit::{
     event::{self, DeviceEvent},
-    event_loop::EventLoop,
+    event_loop::{ControlFlow, EventLoop},
 };
 
+mod benchmark;
 mod buffer;
 mod camera;
+mod camera_path;
+mod capture;
+mod cli;
 mod client;
+#[allow(dead_code)]
 mod cube;
+mod debug_draw;
+mod debug_view;
 mod egui_instance;
 mod error;
+mod gamepad;
+mod graphics;
+mod input_recording;
+mod logging;
+mod mesh;
+mod passes;
+mod pipeline;
+mod post;
+mod profiler;
+mod render_graph;
 mod renderer;
+mod settings;
+mod shader_manager;
+mod shader_preprocessor;
+mod shadow;
+mod surface;
+mod texture;
 mod vertex;
 mod window;
 
 fn main() {
-    std::env::set_var("RUST_LOG", "info");
-    std::env::set_var("RUST_BACKTRACE", "1");
-    tracing_subscriber::FmtSubscriber::new().init();
+    let cli = Cli::parse();
+    // Validated first so that an invalid log level is reported instead of partially applied.
+    let validated = cli.validate();
+    logging::init(cli.log_level.as_deref().filter(|_| validated.is_ok()));
 
-    let span = span!(Level::INFO, "Initialize");
+    if let Err(error) = validated.and_then(|()| start(&cli)) {
+        let report = error::report(&error);
+        error!(error = report, "Failed to start");
+        eprintln!("Rusty Sandbox failed to start: {}", report);
+        std::process::exit(1);
+    }
+}
 
-    let _guard = span.enter();
-    let (window, event_loop, renderer) = match crate::window::Window::new() {
-        Ok(instance) => instance,
-        Err(error) => panic!("Failed to create window!: {:?}", error),
+/// Runs the headless benchmark, or opens the window and runs the event loop until it exits.
+fn start(cli: &Cli) -> Result<(), Error> {
+    if cli.headless {
+        let benchmark = new_benchmark(cli)?.ok_or_else(|| {
+            Error::InvalidArguments("--headless requires --benchmark".to_string())
+        })?;
+        let settings = load_settings(cli);
+        return benchmark::run_headless(benchmark, &settings.launch);
+    }
+    let (event_loop, client, input) = initialize(cli)?;
+    run(event_loop, client, input);
+    Ok(())
+}
+
+fn new_benchmark(cli: &Cli) -> Result<Option<Benchmark>, Error> {
+    let (true, Some(camera_path)) = (cli.benchmark, &cli.camera_path) else {
+        return Ok(None);
     };
-    let client = client::Client::init(window, renderer);
-    drop(_guard);
-    run(event_loop, client);
+    let seed = cli.seed.unwrap_or(benchmark::DEFAULT_SEED);
+    Benchmark::new(camera_path, &cli.report, seed).map(Some)
 }
 
-pub fn run(runnable: EventLoop<()>, mut client: Client) {
-    runnable.run(move |event, _, control_flow| {
-        client.gui.handle_event(&event);
-        match event {
-            event::Event::WindowEvent { window_id, event } => {
-                let span = tracing::span!(Level::INFO, "Window Events");
+fn initialize(cli: &Cli) -> Result<(EventLoop<()>, Client, InputMode), Error> {
+    let span = span!(Level::INFO, "Initialize");
+    let _guard = span.enter();
+    // Loaded first, so that an invalid recording or camera path doesn't open a window.
+    let replay = cli.replay.as_deref().map(Replay::load).transpose()?;
+    let benchmark = new_benchmark(cli)?;
+    // Played back on launch without a benchmark.
+    let camera_path = match (cli.benchmark, &cli.camera_path) {
+        (false, Some(path)) => Some(CameraPath::load(path)?),
+        _ => None,
+    };
+    let settings = load_settings(cli);
+    let (window, event_loop, renderer) = crate::window::Window::new(
+        cli.window_options(&settings.launch),
+        settings.launch.graphics.clone(),
+    )?;
+    let mut client = client::Client::init(window, renderer, settings)?;
+    if let (Some(file), Some(camera_path)) = (&cli.camera_path, camera_path) {
+        client.gui.camera_path.set_path(file, camera_path);
+        client.gui.camera_path.play();
+    }
+    let resolution = *client.window.resolution();
+    let input = if let Some(benchmark) = benchmark {
+        client.disable_gamepads();
+        InputMode::Benchmark(benchmark)
+    } else if let Some(replay) = replay {
+        if replay.header.size != resolution.into_array() {
+            warn!(
+                recorded = ?replay.header.size,
+                window = ?resolution,
+                "The window size differs from the recording, the cursor won't match the UI"
+            );
+        }
+        client.disable_gamepads();
+        InputMode::Replay(replay)
+    } else if let Some(path) = &cli.record {
+        InputMode::Record(Recorder::create(path, resolution)?)
+    } else {
+        InputMode::Live
+    };
+    Ok((event_loop, client, input))
+}
 
-                let _guard = span.enter();
+/// Loads the settings file, the defaults are used if it's invalid and it won't be overwritten.
+fn load_settings(cli: &Cli) -> SettingsFile {
+    let path = cli.settings.clone().or_else(Settings::default_path);
+    let (path, saved) = match path.as_deref().map(Settings::load) {
+        Some(Ok(settings)) => (path, settings),
+        Some(Err(error)) => {
+            warn!(?path, %error, "Invalid settings, using the defaults without saving them");
+            (None, Settings::default())
+        }
+        None => {
+            warn!("No config directory, the settings won't be saved");
+            (None, Settings::default())
+        }
+    };
+    let mut launch = saved.clone();
+    cli.apply(&mut launch);
+    SettingsFile {
+        path,
+        saved,
+        launch,
+    }
+}
 
-                if window_id == client.window_id() {
-                    client
-                        .window
-                        .handle_window_events(&event, control_flow, &mut client.renderer);
+/// Exits the event loop with a non-zero status, it can't be changed once set.
+const FAILURE: ControlFlow = ControlFlow::ExitWithCode(1);
 
-                    client.update(&event);
+pub fn run(runnable: EventLoop<()>, client: Client, mut input: InputMode) {
+    // Only empty while the renderer is recreated, or after failing to, until the loop exits.
+    let mut client = Some(client);
+    runnable.run(move |event, _, control_flow| {
+        let Some(current) = client.as_mut() else {
+            *control_flow = ControlFlow::Exit;
+            return;
+        };
+        let recreate = match &mut input {
+            InputMode::Live => handle_event(current, event, control_flow),
+            InputMode::Record(recorder) => {
+                let ignored_motion = matches!(
+                    event,
+                    event::Event::DeviceEvent {
+                        event: DeviceEvent::MouseMotion { .. },
+                        ..
+                    }
+                ) && !current.camera_follows_mouse();
+                if !ignored_motion {
+                    recorder.record(&event);
                 }
+                if let event::Event::LoopDestroyed = event {
+                    recorder.finish();
+                }
+                handle_event(current, event, control_flow)
             }
-            event::Event::DeviceEvent {
-                device_id,
-                event: DeviceEvent::MouseMotion { delta },
-            } => {
-                client.renderer.camera.on_mouse_input(delta.0, delta.1);
-                client.update_camera();
-            }
-            event::Event::MainEventsCleared => {
-                client.window.winit().request_redraw();
+            InputMode::Replay(replay) => replay_event(current, replay, event, control_flow),
+            InputMode::Benchmark(benchmark) => {
+                benchmark_event(current, benchmark, event, control_flow)
             }
-            event::Event::RedrawRequested(..) => {
-                on_redraw_requested(&mut client)
-                    .expect("Unrecoverable Error when preparing for next frame");
+        };
+        if recreate {
+            let current = client.take().expect("The client was just borrowed");
+            match current.recreate_renderer() {
+                Ok(recreated) => client = Some(recreated),
+                Err(error) => {
+                    error!(
+                        error = error::report(&error),
+                        "Failed to recreate the renderer, exiting"
+                    );
+                    *control_flow = FAILURE;
+                }
             }
-            _ => (),
         }
     });
 }
 
+/// Handles an event of the loop while replaying, the recorded input of a frame is handled
+/// before it starts and the live input is ignored. Exits once the replay is finished.
+fn replay_event(
+    client: &mut Client,
+    replay: &mut Replay,
+    event: event::Event<()>,
+    control_flow: &mut ControlFlow,
+) -> bool {
+    if input_recording::is_live_input(&event) {
+        return false;
+    }
+    let mut recreate = false;
+    // Not advanced while paused, which only happens when minimized since focus is ignored.
+    if let event::Event::MainEventsCleared = event {
+        if !client.window.is_paused() {
+            for input in replay.step() {
+                let event = input.to_event(client.window_id());
+                recreate |= handle_event(client, event, control_flow);
+            }
+            if replay.is_finished() {
+                info!("Replay finished, exiting");
+                *control_flow = ControlFlow::Exit;
+            }
+        }
+    }
+    handle_event(client, event, control_flow) || recreate
+}
+
+/// Handles an event of the loop during a benchmark, the camera follows the path and the live
+/// input is ignored. It isn't paused while the window is unfocused or minimized. Writes the
+/// report and exits once the path is finished.
+fn benchmark_event(
+    client: &mut Client,
+    benchmark: &mut Benchmark,
+    event: event::Event<()>,
+    control_flow: &mut ControlFlow,
+) -> bool {
+    if input_recording::is_live_input(&event)
+        || matches!(*control_flow, ControlFlow::ExitWithCode(_))
+    {
+        return false;
+    }
+    match event {
+        event::Event::MainEventsCleared => {
+            if !benchmark.update_camera(&mut client.renderer.camera) {
+                // The timestamps of the last frames are still being read back.
+                let renderer = &mut client.renderer;
+                let finished = renderer::catch_device_loss(|| {
+                    renderer.profiler.wait_for_gpu(&renderer.device)
+                })
+                .map_err(Error::from)
+                .and_then(|()| benchmark.finish(renderer));
+                *control_flow = match finished {
+                    Ok(()) => ControlFlow::Exit,
+                    Err(error) => {
+                        error!(
+                            error = error::report(&error),
+                            "Failed to finish the benchmark"
+                        );
+                        FAILURE
+                    }
+                };
+                return false;
+            }
+            client.renderer.upload_camera();
+            *control_flow = ControlFlow::Poll;
+            client.window.winit().request_redraw();
+            false
+        }
+        event::Event::RedrawRequested(_) => {
+            let recreate = handle_event(client, event, control_flow);
+            benchmark.record_frame(&client.renderer.profiler);
+            recreate
+        }
+        _ => handle_event(client, event, control_flow),
+    }
+}
+
+/// Returns whether the renderer must be recreated.
+fn handle_event(
+    client: &mut Client,
+    event: event::Event<()>,
+    control_flow: &mut ControlFlow,
+) -> bool {
+    client.handle_gui_event(&event);
+    match event {
+        event::Event::WindowEvent { window_id, event } => {
+            let span = tracing::span!(Level::INFO, "Window Events");
+
+            let _guard = span.enter();
+
+            if window_id == client.window_id() {
+                client
+                    .window
+                    .handle_window_events(&event, control_flow, &mut client.renderer);
+
+                client.update(&event);
+            }
+            false
+        }
+        event::Event::DeviceEvent {
+            event: DeviceEvent::MouseMotion { delta },
+            ..
+        } => {
+            client.on_mouse_motion(delta);
+            false
+        }
+        // Nothing is updated or rendered while paused, the loop waits for the next event.
+        event::Event::MainEventsCleared => {
+            if client.window.is_paused() {
+                *control_flow = ControlFlow::Wait;
+            } else {
+                *control_flow = ControlFlow::Poll;
+                client.update_gamepads();
+                client.window.winit().request_redraw();
+            }
+            false
+        }
+        event::Event::LoopDestroyed => {
+            client.save_settings();
+            client.capture.finish(&client.renderer.device);
+            false
+        }
+        event::Event::RedrawRequested(..) => match on_redraw_requested(client) {
+            Ok(()) => client.renderer.needs_recreation(),
+            Err(RendererError::DeviceLost) => true,
+            Err(error) => {
+                error!(
+                    error = error::report(&error),
+                    "Unrecoverable error while rendering, exiting"
+                );
+                *control_flow = FAILURE;
+                false
+            }
+        },
+        _ => false,
+    }
+}
+
 fn on_redraw_requested(client: &mut Client) -> Result<(), RendererError> {
     let span = span!(Level::INFO, "Render");
     let _guard = span.enter();
-    client.render().unwrap();
-    Ok(())
+    client.render()
 }
@@ -0,0 +1,106 @@
+use vek::Vec3;
+use wgpu::BufferUsages;
+
+use crate::{
+    buffer::Buffer,
+    debug_view, profiler,
+    vertex::{BarycentricVertex, Vertex},
+};
+
+/// Describes how the geometry of a [Mesh] is blended into the scene.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RenderLayer {
+    /// Fully opaque geometry, drawn first with depth writes.
+    Opaque,
+    /// Alpha tested geometry (e.g. foliage), fragments below the cutoff are discarded.
+    Cutout,
+    /// Alpha blended geometry (e.g. water, glass), drawn last and sorted back to front.
+    Translucent,
+}
+
+/// A chunk of indexed geometry uploaded to the GPU.
+pub struct Mesh {
+    pub layer: RenderLayer,
+    /// Center of the mesh bounds, used to sort translucent meshes.
+    pub center: Vec3<f32>,
+    /// Corners of the axis aligned bounding box.
+    pub min: Vec3<f32>,
+    pub max: Vec3<f32>,
+    vertex_buffer: Buffer<Vertex>,
+    index_buffer: Buffer<u16>,
+    /// Unrolled triangles for the wireframe shader, when the device can't draw lines.
+    wireframe_buffer: Option<Buffer<BarycentricVertex>>,
+}
+
+impl Mesh {
+    pub fn new(
+        device: &wgpu::Device,
+        vertices: &[Vertex],
+        indices: &[u16],
+        layer: RenderLayer,
+    ) -> Self {
+        let (min, max) = vertices.iter().fold(
+            (Vec3::broadcast(f32::MAX), Vec3::broadcast(f32::MIN)),
+            |(min, max), vertex| {
+                let position = Vec3::from(vertex.position);
+                (
+                    Vec3::partial_min(min, position),
+                    Vec3::partial_max(max, position),
+                )
+            },
+        );
+        let wireframe_buffer = (!debug_view::line_polygon_mode_supported(device)).then(|| {
+            Buffer::new(
+                device,
+                &BarycentricVertex::triangles(vertices, indices),
+                BufferUsages::VERTEX,
+            )
+        });
+        Self {
+            layer,
+            center: (min + max) * 0.5,
+            min,
+            max,
+            vertex_buffer: Buffer::new(
+                device,
+                vertices,
+                BufferUsages::VERTEX | BufferUsages::COPY_DST,
+            ),
+            index_buffer: Buffer::new(device, indices, BufferUsages::INDEX),
+            wireframe_buffer,
+        }
+    }
+
+    /// Draws the mesh with the wireframe pipeline, see [crate::debug_view::DebugView].
+    pub fn draw_wireframe<'a>(&'a self, render_pass: &mut wgpu::RenderPass<'a>) {
+        match &self.wireframe_buffer {
+            Some(buffer) => {
+                render_pass.set_vertex_buffer(0, buffer.data().slice(..));
+                render_pass.draw(0..buffer.len() as u32, 0..1);
+                profiler::count_draw_call();
+            }
+            None => self.draw(render_pass),
+        }
+    }
+
+    /// Records the draw call of this mesh, the pipeline and bind groups must already be set.
+    pub fn draw<'a>(&'a self, render_pass: &mut wgpu::RenderPass<'a>) {
+        render_pass.set_vertex_buffer(0, self.vertex_buffer.data().slice(..));
+        render_pass.set_index_buffer(
+            self.index_buffer.data().slice(..),
+            wgpu::IndexFormat::Uint16,
+        );
+        render_pass.draw_indexed(0..self.index_buffer.len() as u32, 0, 0..1);
+        profiler::count_draw_call();
+    }
+}
+
+/// Sorts translucent meshes from the farthest to the closest to `eye`,
+/// so blending composes them in the right order.
+pub fn sort_back_to_front(meshes: &mut [&Mesh], eye: Vec3<f32>) {
+    meshes.sort_by(|a, b| {
+        let da = a.center.distance_squared(eye);
+        let db = b.center.distance_squared(eye);
+        db.total_cmp(&da)
+    });
+}
@@ -0,0 +1,164 @@
+use crate::{
+    mesh::{self, RenderLayer},
+    post::HDR,
+    render_graph::{Pass, PassContext},
+};
+
+/// Depth buffer of the scene, owned by the render graph.
+pub const DEPTH: &str = "depth";
+/// Multisampled scene color, resolved into [HDR] by the last scene pass.
+/// Only exists when MSAA is enabled.
+pub const MULTISAMPLED_HDR: &str = "hdr_multisampled";
+/// Cascaded sun shadow map, owned by [crate::shadow::ShadowMap].
+pub const SHADOW_MAP: &str = "shadow_map";
+
+/// Renders the depth of the shadow casters from the sun's point of view.
+pub struct ShadowPass;
+
+impl Pass for ShadowPass {
+    fn name(&self) -> &'static str {
+        "Shadow Pass"
+    }
+
+    fn writes(&self) -> &[&'static str] {
+        &[SHADOW_MAP]
+    }
+
+    fn execute(&mut self, ctx: &mut PassContext) {
+        // Translucent geometry doesn't cast shadows.
+        let casters = ctx
+            .renderer
+            .meshes()
+            .iter()
+            .filter(|mesh| mesh.layer != RenderLayer::Translucent)
+            .collect::<Vec<_>>();
+        ctx.renderer.shadow.render(ctx.encoder, &casters);
+    }
+}
+
+/// Clears the frame and renders the opaque and cutout geometry.
+pub struct OpaquePass;
+
+impl Pass for OpaquePass {
+    fn name(&self) -> &'static str {
+        "Opaque Pass"
+    }
+
+    fn reads(&self) -> &[&'static str] {
+        &[SHADOW_MAP]
+    }
+
+    fn writes(&self) -> &[&'static str] {
+        &[HDR, MULTISAMPLED_HDR, DEPTH]
+    }
+
+    fn execute(&mut self, ctx: &mut PassContext) {
+        let renderer = ctx.renderer;
+        let (view, _) = scene_color(ctx);
+        let mut render_pass = ctx.encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some(self.name()),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(renderer.clear_color()),
+                    store: true,
+                },
+            })],
+            depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                view: ctx.resources.view(DEPTH),
+                depth_ops: Some(wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(1.0),
+                    store: true,
+                }),
+                stencil_ops: None,
+            }),
+        });
+        let pipelines = renderer.scene_pipelines();
+        render_pass.set_pipeline(&pipelines.opaque);
+        render_pass.set_bind_group(0, renderer.camera_bind_group(), &[]);
+        render_pass.set_bind_group(1, renderer.shadow.bind_group(), &[]);
+        renderer
+            .meshes()
+            .iter()
+            .filter(|mesh| mesh.layer == RenderLayer::Opaque)
+            .for_each(|mesh| mesh.draw(&mut render_pass));
+        render_pass.set_pipeline(&pipelines.cutout);
+        renderer
+            .meshes()
+            .iter()
+            .filter(|mesh| mesh.layer == RenderLayer::Cutout)
+            .for_each(|mesh| mesh.draw(&mut render_pass));
+    }
+}
+
+/// Blends the translucent geometry over the opaque result, from back to front.
+/// With MSAA enabled, it also resolves the scene color into [HDR].
+pub struct TranslucentPass;
+
+impl Pass for TranslucentPass {
+    fn name(&self) -> &'static str {
+        "Translucent Pass"
+    }
+
+    fn reads(&self) -> &[&'static str] {
+        &[SHADOW_MAP, DEPTH]
+    }
+
+    fn writes(&self) -> &[&'static str] {
+        &[HDR, MULTISAMPLED_HDR]
+    }
+
+    fn execute(&mut self, ctx: &mut PassContext) {
+        let renderer = ctx.renderer;
+        let (view, resolve_target) = scene_color(ctx);
+        let mut translucent = renderer
+            .meshes()
+            .iter()
+            .filter(|mesh| mesh.layer == RenderLayer::Translucent)
+            .collect::<Vec<_>>();
+        mesh::sort_back_to_front(&mut translucent, renderer.camera.eye);
+
+        // Depth is tested but not written. It's stored anyway, the passes after this one read it
+        // and a depth that isn't stored is discarded.
+        let mut render_pass = ctx.encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some(self.name()),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view,
+                resolve_target,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Load,
+                    store: true,
+                },
+            })],
+            depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                view: ctx.resources.view(DEPTH),
+                depth_ops: Some(wgpu::Operations {
+                    load: wgpu::LoadOp::Load,
+                    store: true,
+                }),
+                stencil_ops: None,
+            }),
+        });
+        render_pass.set_pipeline(&renderer.scene_pipelines().translucent);
+        render_pass.set_bind_group(0, renderer.camera_bind_group(), &[]);
+        render_pass.set_bind_group(1, renderer.shadow.bind_group(), &[]);
+        translucent
+            .into_iter()
+            .for_each(|mesh| mesh.draw(&mut render_pass));
+    }
+}
+
+/// Returns the color target of the scene passes and the view it resolves into.
+pub fn scene_color<'a>(
+    ctx: &PassContext<'a>,
+) -> (&'a wgpu::TextureView, Option<&'a wgpu::TextureView>) {
+    if ctx.renderer.sample_count() > 1 {
+        (
+            ctx.resources.view(MULTISAMPLED_HDR),
+            Some(ctx.resources.view(HDR)),
+        )
+    } else {
+        (ctx.resources.view(HDR), None)
+    }
+}
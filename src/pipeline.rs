@@ -0,0 +1,300 @@
+use std::{
+    collections::HashMap,
+    hash::{Hash, Hasher},
+    sync::Arc,
+};
+
+use tracing::warn;
+
+use crate::{shader_manager::ShaderManager, shader_preprocessor::ShaderDefines};
+
+/// Depth testing of a pipeline.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DepthState {
+    pub format: wgpu::TextureFormat,
+    pub write_enabled: bool,
+    pub compare: wgpu::CompareFunction,
+    /// Constant depth bias, see [wgpu::DepthBiasState].
+    pub bias_constant: i32,
+    pub bias_slope_scale: f32,
+}
+
+impl DepthState {
+    pub fn new(format: wgpu::TextureFormat, write_enabled: bool) -> Self {
+        Self {
+            format,
+            write_enabled,
+            compare: wgpu::CompareFunction::Less,
+            bias_constant: 0,
+            bias_slope_scale: 0.0,
+        }
+    }
+}
+
+impl Eq for DepthState {}
+
+impl Hash for DepthState {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.format.hash(state);
+        self.write_enabled.hash(state);
+        self.compare.hash(state);
+        self.bias_constant.hash(state);
+        self.bias_slope_scale.to_bits().hash(state);
+    }
+}
+
+/// Describes a render pipeline, it is also the key of the [PipelineCache].
+///
+/// Starts as a triangle list without culling, fragment stage or depth test.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct PipelineDesc {
+    pub label: &'static str,
+    /// Name of a layout added with [PipelineCache::add_layout].
+    pub layout: &'static str,
+    /// Name of the shader in [crate::shader_manager::SHADER_DIR].
+    pub shader: &'static str,
+    pub defines: ShaderDefines,
+    pub vertex_entry: &'static str,
+    pub vertex_buffers: Vec<wgpu::VertexBufferLayout<'static>>,
+    pub fragment_entry: Option<&'static str>,
+    pub color_format: Option<wgpu::TextureFormat>,
+    pub blend: Option<wgpu::BlendState>,
+    pub topology: wgpu::PrimitiveTopology,
+    pub cull_mode: Option<wgpu::Face>,
+    /// `Line` requires [wgpu::Features::POLYGON_MODE_LINE],
+    /// `Point` requires [wgpu::Features::POLYGON_MODE_POINT].
+    pub polygon_mode: wgpu::PolygonMode,
+    pub depth: Option<DepthState>,
+    pub sample_count: u32,
+}
+
+impl PipelineDesc {
+    pub fn new(label: &'static str, layout: &'static str, shader: &'static str) -> Self {
+        Self {
+            label,
+            layout,
+            shader,
+            defines: ShaderDefines::new(),
+            vertex_entry: "vs_main",
+            vertex_buffers: Vec::new(),
+            fragment_entry: None,
+            color_format: None,
+            blend: None,
+            topology: wgpu::PrimitiveTopology::TriangleList,
+            cull_mode: None,
+            polygon_mode: wgpu::PolygonMode::Fill,
+            depth: None,
+            sample_count: 1,
+        }
+    }
+
+    pub fn defines(mut self, defines: ShaderDefines) -> Self {
+        self.defines = defines;
+        self
+    }
+
+    pub fn vertex(mut self, entry_point: &'static str) -> Self {
+        self.vertex_entry = entry_point;
+        self
+    }
+
+    pub fn vertex_buffer(mut self, layout: wgpu::VertexBufferLayout<'static>) -> Self {
+        self.vertex_buffers.push(layout);
+        self
+    }
+
+    /// Renders into a single color target of `format`.
+    pub fn fragment(mut self, entry_point: &'static str, format: wgpu::TextureFormat) -> Self {
+        self.fragment_entry = Some(entry_point);
+        self.color_format = Some(format);
+        self
+    }
+
+    /// A fragment stage without color targets, e.g. to discard fragments of a depth pass.
+    pub fn depth_only_fragment(mut self, entry_point: &'static str) -> Self {
+        self.fragment_entry = Some(entry_point);
+        self.color_format = None;
+        self
+    }
+
+    pub fn blend(mut self, blend: wgpu::BlendState) -> Self {
+        self.blend = Some(blend);
+        self
+    }
+
+    pub fn topology(mut self, topology: wgpu::PrimitiveTopology) -> Self {
+        self.topology = topology;
+        self
+    }
+
+    pub fn cull(mut self, face: wgpu::Face) -> Self {
+        self.cull_mode = Some(face);
+        self
+    }
+
+    pub fn polygon_mode(mut self, polygon_mode: wgpu::PolygonMode) -> Self {
+        self.polygon_mode = polygon_mode;
+        self
+    }
+
+    pub fn depth(mut self, depth: DepthState) -> Self {
+        self.depth = Some(depth);
+        self
+    }
+
+    pub fn sample_count(mut self, sample_count: u32) -> Self {
+        self.sample_count = sample_count;
+        self
+    }
+}
+
+/// Creates render pipelines from a [PipelineDesc] and keeps them for later requests.
+#[derive(Default)]
+pub struct PipelineCache {
+    layouts: HashMap<&'static str, wgpu::PipelineLayout>,
+    pipelines: HashMap<PipelineDesc, Arc<wgpu::RenderPipeline>>,
+}
+
+impl PipelineCache {
+    pub fn add_layout(&mut self, name: &'static str, layout: wgpu::PipelineLayout) {
+        self.layouts.insert(name, layout);
+    }
+
+    /// Returns the pipeline matching `desc`, creating it on first use.
+    pub fn get(
+        &mut self,
+        device: &wgpu::Device,
+        shaders: &mut ShaderManager,
+        desc: &PipelineDesc,
+    ) -> Arc<wgpu::RenderPipeline> {
+        if let Some(pipeline) = self.pipelines.get(desc) {
+            return pipeline.clone();
+        }
+        let shader = shaders.load(device, desc.shader, &desc.defines);
+        // naga validation doesn't catch interface mismatches with the pipeline layouts.
+        device.push_error_scope(wgpu::ErrorFilter::Validation);
+        let mut pipeline = self.create(device, &shader, desc);
+        if let Some(error) = pollster::block_on(device.pop_error_scope()) {
+            warn!(desc.label, %error, "Failed to create a pipeline");
+            shaders.set_error(desc.shader, &desc.defines, error.to_string());
+            let shader = shaders.load_embedded(device, desc.shader, &desc.defines);
+            pipeline = self.create(device, &shader, desc);
+        }
+        let pipeline = Arc::new(pipeline);
+        self.pipelines.insert(desc.clone(), pipeline.clone());
+        pipeline
+    }
+
+    /// Recreates every cached pipeline using `shader` after it was modified.
+    ///
+    /// Returns `false` if the shader doesn't compile or doesn't match the pipelines,
+    /// in which case the previous pipelines are kept and the error is recorded in `shaders`.
+    pub fn reload(
+        &mut self,
+        device: &wgpu::Device,
+        shaders: &mut ShaderManager,
+        shader: &str,
+    ) -> bool {
+        let descs = self
+            .pipelines
+            .keys()
+            .filter(|desc| desc.shader == shader)
+            .cloned()
+            .collect::<Vec<_>>();
+        let mut modules = HashMap::new();
+        for desc in &descs {
+            if modules.contains_key(&desc.defines) {
+                continue;
+            }
+            let Some(module) = shaders.reload(device, shader, &desc.defines) else {
+                return false;
+            };
+            modules.insert(desc.defines.clone(), module);
+        }
+
+        // One error scope per permutation, so errors are recorded for the permutation causing them.
+        let mut pipelines = Vec::with_capacity(descs.len());
+        let mut failed = false;
+        for (defines, module) in &modules {
+            device.push_error_scope(wgpu::ErrorFilter::Validation);
+            for desc in descs.iter().filter(|desc| desc.defines == *defines) {
+                let pipeline = self.create(device, module, desc);
+                pipelines.push((desc.clone(), Arc::new(pipeline)));
+            }
+            if let Some(error) = pollster::block_on(device.pop_error_scope()) {
+                warn!(shader, %defines, %error, "Failed to create the pipelines of a reloaded shader");
+                shaders.set_error(shader, defines, error.to_string());
+                failed = true;
+            }
+        }
+        if failed {
+            return false;
+        }
+        self.pipelines.extend(pipelines);
+        true
+    }
+
+    fn create(
+        &self,
+        device: &wgpu::Device,
+        shader: &wgpu::ShaderModule,
+        desc: &PipelineDesc,
+    ) -> wgpu::RenderPipeline {
+        let layout = self
+            .layouts
+            .get(desc.layout)
+            .unwrap_or_else(|| panic!("Unknown pipeline layout `{}`", desc.layout));
+        let targets = desc
+            .color_format
+            .map(|format| wgpu::ColorTargetState {
+                format,
+                blend: desc.blend,
+                write_mask: wgpu::ColorWrites::ALL,
+            })
+            .into_iter()
+            .map(Some)
+            .collect::<Vec<_>>();
+        device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some(desc.label),
+            layout: Some(layout),
+            vertex: wgpu::VertexState {
+                module: shader,
+                entry_point: desc.vertex_entry,
+                buffers: &desc.vertex_buffers,
+            },
+            fragment: desc.fragment_entry.map(|entry_point| wgpu::FragmentState {
+                module: shader,
+                entry_point,
+                targets: &targets,
+            }),
+            primitive: wgpu::PrimitiveState {
+                topology: desc.topology,
+                strip_index_format: None,
+                front_face: wgpu::FrontFace::Ccw,
+                cull_mode: desc.cull_mode,
+                // Requires Features::DEPTH_CLIP_CONTROL
+                unclipped_depth: false,
+                polygon_mode: desc.polygon_mode,
+                // Requires Features::CONSERVATIVE_RASTERIZATION
+                conservative: false,
+            },
+            depth_stencil: desc.depth.map(|depth| wgpu::DepthStencilState {
+                format: depth.format,
+                depth_write_enabled: depth.write_enabled,
+                depth_compare: depth.compare,
+                stencil: wgpu::StencilState::default(),
+                bias: wgpu::DepthBiasState {
+                    constant: depth.bias_constant,
+                    slope_scale: depth.bias_slope_scale,
+                    clamp: 0.0,
+                },
+            }),
+            multisample: wgpu::MultisampleState {
+                count: desc.sample_count,
+                mask: !0,
+                alpha_to_coverage_enabled: false,
+            },
+            multiview: None,
+        })
+    }
+}
@@ -0,0 +1,437 @@
+use std::sync::Arc;
+
+use serde::{Deserialize, Serialize};
+use wgpu::BufferUsages;
+
+use crate::{
+    buffer::Buffer,
+    pipeline::{PipelineCache, PipelineDesc},
+    profiler,
+    render_graph::{Pass, PassContext, SURFACE},
+    shader_manager::ShaderManager,
+};
+
+/// High dynamic range color of the scene, before tone mapping.
+pub const HDR: &str = "hdr";
+/// Tone mapped color, before anti-aliasing.
+pub const LDR: &str = "ldr";
+/// Blurred bright parts of the scene.
+pub const BLOOM: &str = "bloom";
+/// Intermediate target of the separable bloom blur.
+pub const BLOOM_BLUR: &str = "bloom_blur";
+
+pub const HDR_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba16Float;
+pub const LDR_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba8Unorm;
+
+/// Pipeline layout shared by the post-processing passes.
+const POST_LAYOUT: &str = "post";
+
+/// Curve mapping HDR colors into the displayable range.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ToneMapping {
+    /// Colors are clamped.
+    None,
+    Reinhard,
+    Aces,
+}
+
+impl ToneMapping {
+    pub const ALL: [ToneMapping; 3] = [ToneMapping::None, ToneMapping::Reinhard, ToneMapping::Aces];
+}
+
+/// User tweakable parameters of the post-processing chain.
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize, Serialize)]
+#[serde(default)]
+pub struct PostSettings {
+    pub tone_mapping: ToneMapping,
+    pub exposure: f32,
+    /// Encodes the output with `gamma`, the surface is always linear.
+    pub gamma_correction: bool,
+    pub gamma: f32,
+    pub bloom: bool,
+    pub bloom_threshold: f32,
+    pub bloom_intensity: f32,
+    pub fxaa: bool,
+}
+
+impl Default for PostSettings {
+    fn default() -> Self {
+        Self {
+            tone_mapping: ToneMapping::Aces,
+            exposure: 1.0,
+            gamma_correction: true,
+            gamma: 2.2,
+            bloom: true,
+            bloom_threshold: 1.0,
+            bloom_intensity: 0.3,
+            fxaa: true,
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct PostBufferData {
+    pub exposure: f32,
+    pub bloom_threshold: f32,
+    pub bloom_intensity: f32,
+    pub tone_mapping: u32,
+    pub gamma: f32,
+    pub encode_gamma: u32,
+    pub fxaa: u32,
+    _padding: u32,
+}
+
+impl PostBufferData {
+    fn new(settings: &PostSettings, surface_srgb: bool) -> Self {
+        Self {
+            exposure: settings.exposure,
+            bloom_threshold: settings.bloom_threshold,
+            bloom_intensity: if settings.bloom {
+                settings.bloom_intensity
+            } else {
+                0.0
+            },
+            tone_mapping: settings.tone_mapping as u32,
+            gamma: settings.gamma,
+            // An sRGB surface already encodes on write.
+            encode_gamma: (settings.gamma_correction && !surface_srgb) as u32,
+            fxaa: settings.fxaa as u32,
+            _padding: 0,
+        }
+    }
+}
+
+/// Resources shared by the post-processing passes.
+pub struct PostProcessing {
+    pub settings: PostSettings,
+    /// Skips exposure, bloom and tone mapping, e.g. for the debug views.
+    pub passthrough: bool,
+    surface_srgb: bool,
+    buffer: Buffer<PostBufferData>,
+    sampler: wgpu::Sampler,
+    bind_group_layout: wgpu::BindGroupLayout,
+    surface_format: wgpu::TextureFormat,
+    pipelines: PostPipelines,
+}
+
+/// Fullscreen pipelines of the post-processing passes, one per fragment entry point of `post.wgsl`.
+pub struct PostPipelines {
+    pub bright: Arc<wgpu::RenderPipeline>,
+    pub blur_horizontal: Arc<wgpu::RenderPipeline>,
+    pub blur_vertical: Arc<wgpu::RenderPipeline>,
+    pub tonemap: Arc<wgpu::RenderPipeline>,
+    pub fxaa: Arc<wgpu::RenderPipeline>,
+}
+
+impl PostPipelines {
+    fn new(
+        device: &wgpu::Device,
+        pipelines: &mut PipelineCache,
+        shaders: &mut ShaderManager,
+        surface_format: wgpu::TextureFormat,
+    ) -> Self {
+        let mut pipeline = |entry_point, format| {
+            let desc = PipelineDesc::new(entry_point, POST_LAYOUT, "post.wgsl")
+                .vertex("vs_fullscreen")
+                .fragment(entry_point, format);
+            pipelines.get(device, shaders, &desc)
+        };
+        Self {
+            bright: pipeline("fs_bright", HDR_FORMAT),
+            blur_horizontal: pipeline("fs_blur_horizontal", HDR_FORMAT),
+            blur_vertical: pipeline("fs_blur_vertical", HDR_FORMAT),
+            tonemap: pipeline("fs_tonemap", LDR_FORMAT),
+            fxaa: pipeline("fs_fxaa", surface_format),
+        }
+    }
+}
+
+impl PostProcessing {
+    pub fn new(
+        device: &wgpu::Device,
+        pipelines: &mut PipelineCache,
+        shaders: &mut ShaderManager,
+        surface_format: wgpu::TextureFormat,
+    ) -> Self {
+        let settings = PostSettings::default();
+        let surface_srgb = surface_format.describe().srgb;
+        let buffer = Buffer::new(
+            device,
+            &[PostBufferData::new(&settings, surface_srgb)],
+            BufferUsages::UNIFORM | BufferUsages::COPY_DST,
+        );
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("Post Processing Sampler"),
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            ..Default::default()
+        });
+        let texture_entry = |binding| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Texture {
+                sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                view_dimension: wgpu::TextureViewDimension::D2,
+                multisampled: false,
+            },
+            count: None,
+        };
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[
+                texture_entry(0),
+                texture_entry(1),
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 3,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+            ],
+            label: Some("post_bind_group_layout"),
+        });
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Post Processing Pipeline Layout"),
+            bind_group_layouts: &[&bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        pipelines.add_layout(POST_LAYOUT, pipeline_layout);
+        let pipelines = PostPipelines::new(device, pipelines, shaders, surface_format);
+        Self {
+            settings,
+            passthrough: false,
+            surface_srgb,
+            buffer,
+            sampler,
+            bind_group_layout,
+            surface_format,
+            pipelines,
+        }
+    }
+
+    /// Uploads the current settings.
+    pub fn update(&mut self, queue: &wgpu::Queue) {
+        let data = if self.passthrough {
+            let settings = PostSettings {
+                tone_mapping: ToneMapping::None,
+                exposure: 1.0,
+                bloom: false,
+                ..self.settings
+            };
+            PostBufferData::new(&settings, self.surface_srgb)
+        } else {
+            PostBufferData::new(&self.settings, self.surface_srgb)
+        };
+        self.buffer.update(queue, &[data], 0);
+    }
+
+    /// Fetches the pipelines again after the cache was reloaded.
+    pub fn update_pipelines(
+        &mut self,
+        device: &wgpu::Device,
+        pipelines: &mut PipelineCache,
+        shaders: &mut ShaderManager,
+    ) {
+        self.pipelines = PostPipelines::new(device, pipelines, shaders, self.surface_format);
+    }
+
+    /// Binds the inputs of a post-processing pass.
+    /// Transient views change on resize, so bind groups are created every frame.
+    fn bind_group(
+        &self,
+        device: &wgpu::Device,
+        input_a: &wgpu::TextureView,
+        input_b: &wgpu::TextureView,
+    ) -> wgpu::BindGroup {
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &self.bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(input_a),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::TextureView(input_b),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: wgpu::BindingResource::Sampler(&self.sampler),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: self.buffer.data().as_entire_binding(),
+                },
+            ],
+            label: Some("post_bind_group"),
+        })
+    }
+}
+
+/// Draws a fullscreen triangle into `target`.
+fn fullscreen_pass(
+    encoder: &mut wgpu::CommandEncoder,
+    label: &str,
+    target: &wgpu::TextureView,
+    pipeline: &wgpu::RenderPipeline,
+    bind_group: &wgpu::BindGroup,
+) {
+    let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+        label: Some(label),
+        color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+            view: target,
+            resolve_target: None,
+            ops: wgpu::Operations {
+                load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                store: true,
+            },
+        })],
+        depth_stencil_attachment: None,
+    });
+    render_pass.set_pipeline(pipeline);
+    render_pass.set_bind_group(0, bind_group, &[]);
+    render_pass.draw(0..3, 0..1);
+    profiler::count_draw_call();
+}
+
+/// Extracts the bright parts of the scene and blurs them.
+pub struct BloomPass;
+
+impl Pass for BloomPass {
+    fn name(&self) -> &'static str {
+        "Bloom Pass"
+    }
+
+    fn reads(&self) -> &[&'static str] {
+        &[HDR]
+    }
+
+    fn writes(&self) -> &[&'static str] {
+        &[BLOOM, BLOOM_BLUR]
+    }
+
+    fn execute(&mut self, ctx: &mut PassContext) {
+        let renderer = ctx.renderer;
+        let post = &renderer.post;
+        let hdr = ctx.resources.view(HDR);
+        let bloom = ctx.resources.view(BLOOM);
+        let blur = ctx.resources.view(BLOOM_BLUR);
+        if !post.settings.bloom || post.passthrough {
+            // Tone mapping still samples the bloom texture, keep it black.
+            ctx.encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some(self.name()),
+                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                    view: bloom,
+                    resolve_target: None,
+                    ops: wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                        store: true,
+                    },
+                })],
+                depth_stencil_attachment: None,
+            });
+            return;
+        }
+        let device = &renderer.device;
+        let bind_group = post.bind_group(device, hdr, hdr);
+        fullscreen_pass(
+            ctx.encoder,
+            "Bloom Bright Pass",
+            bloom,
+            &post.pipelines.bright,
+            &bind_group,
+        );
+        let bind_group = post.bind_group(device, bloom, bloom);
+        fullscreen_pass(
+            ctx.encoder,
+            "Bloom Horizontal Blur",
+            blur,
+            &post.pipelines.blur_horizontal,
+            &bind_group,
+        );
+        let bind_group = post.bind_group(device, blur, blur);
+        fullscreen_pass(
+            ctx.encoder,
+            "Bloom Vertical Blur",
+            bloom,
+            &post.pipelines.blur_vertical,
+            &bind_group,
+        );
+    }
+}
+
+/// Applies exposure, bloom, tone mapping and gamma correction.
+pub struct TonemapPass;
+
+impl Pass for TonemapPass {
+    fn name(&self) -> &'static str {
+        "Tonemap Pass"
+    }
+
+    fn reads(&self) -> &[&'static str] {
+        &[HDR, BLOOM]
+    }
+
+    fn writes(&self) -> &[&'static str] {
+        &[LDR]
+    }
+
+    fn execute(&mut self, ctx: &mut PassContext) {
+        let post = &ctx.renderer.post;
+        let bind_group = post.bind_group(
+            &ctx.renderer.device,
+            ctx.resources.view(HDR),
+            ctx.resources.view(BLOOM),
+        );
+        fullscreen_pass(
+            ctx.encoder,
+            self.name(),
+            ctx.resources.view(LDR),
+            &post.pipelines.tonemap,
+            &bind_group,
+        );
+    }
+}
+
+/// Anti-aliases the tone mapped image into the surface, or copies it when FXAA is disabled.
+pub struct FxaaPass;
+
+impl Pass for FxaaPass {
+    fn name(&self) -> &'static str {
+        "FXAA Pass"
+    }
+
+    fn reads(&self) -> &[&'static str] {
+        &[LDR]
+    }
+
+    fn writes(&self) -> &[&'static str] {
+        &[SURFACE]
+    }
+
+    fn execute(&mut self, ctx: &mut PassContext) {
+        let post = &ctx.renderer.post;
+        let ldr = ctx.resources.view(LDR);
+        let bind_group = post.bind_group(&ctx.renderer.device, ldr, ldr);
+        fullscreen_pass(
+            ctx.encoder,
+            self.name(),
+            ctx.resources.view(SURFACE),
+            &post.pipelines.fxaa,
+            &bind_group,
+        );
+    }
+}
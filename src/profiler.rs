@@ -0,0 +1,640 @@
+//! Frame profiler, combining the CPU time of the `tracing` spans recorded by the [ProfilerLayer]
+//! with the GPU time of every render graph pass measured with timestamp queries.
+//!
+//! The frames are shown by the profiler window and can be exported to the Chrome trace format,
+//! which can be opened by `chrome://tracing` or <https://ui.perfetto.dev>.
+
+use std::{
+    borrow::Cow,
+    collections::VecDeque,
+    fs, io,
+    path::Path,
+    sync::{
+        atomic::{AtomicU32, AtomicU64, Ordering},
+        Arc, LazyLock, Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+use serde_json::json;
+use tracing::{
+    field::{Field, Visit},
+    span, Subscriber,
+};
+use tracing_subscriber::{layer::Context, registry::LookupSpan, Layer};
+
+use crate::error::Error;
+
+/// Number of frames kept in the history.
+pub const HISTORY_LEN: usize = 240;
+/// Passes that can be timed, each one uses two timestamp queries.
+const MAX_TIMED_PASSES: u32 = 64;
+/// Frames whose timestamps can be read back at the same time.
+const READBACK_FRAMES: usize = 3;
+/// Spans kept until the next frame, the newer ones are dropped while no frame is rendered,
+/// e.g. while the window is minimized.
+const MAX_FRAME_SPANS: usize = 16 * 1024;
+/// Thread id of the GPU timeline in the Chrome traces.
+const GPU_THREAD: u64 = 0;
+/// Thread id of the frame boundaries in the Chrome traces.
+const FRAME_THREAD: u64 = u64::MAX;
+
+/// Origin of every timing.
+static START: LazyLock<Instant> = LazyLock::new(Instant::now);
+/// Spans closed since the last frame.
+static CPU_SPANS: Mutex<Vec<CpuSpan>> = Mutex::new(Vec::new());
+static NEXT_THREAD: AtomicU64 = AtomicU64::new(GPU_THREAD + 1);
+/// Draw calls recorded since the last frame.
+static DRAW_CALLS: AtomicU32 = AtomicU32::new(0);
+
+thread_local! {
+    static THREAD: u64 = NEXT_THREAD.fetch_add(1, Ordering::Relaxed);
+}
+
+/// Counts a draw call of the current frame, called next to every `draw` of a render pass.
+pub fn count_draw_call() {
+    DRAW_CALLS.fetch_add(1, Ordering::Relaxed);
+}
+
+/// A `tracing` span entered and exited on the CPU.
+#[derive(Debug, Clone)]
+pub struct CpuSpan {
+    /// The `name` field of the span if it has one, e.g. the name of a render graph pass.
+    pub name: Cow<'static, str>,
+    /// Name the span was created with, e.g. "Pass".
+    pub span: &'static str,
+    pub thread: u64,
+    pub start: Duration,
+    pub duration: Duration,
+}
+
+/// A render graph pass executed by the GPU.
+#[derive(Debug, Clone)]
+pub struct GpuPass {
+    pub name: &'static str,
+    /// The GPU clock can't be compared with the CPU one,
+    /// the passes are placed relative to the moment the frame was submitted.
+    pub start: Duration,
+    pub duration: Duration,
+}
+
+/// Timings of everything that happened between two frame submissions.
+#[derive(Debug, Clone)]
+pub struct FrameProfile {
+    pub index: u64,
+    pub start: Duration,
+    /// Time since the previous frame.
+    pub duration: Duration,
+    /// Time spent recording and submitting the frame, from [Profiler::begin_frame] to
+    /// [Profiler::end_frame].
+    pub cpu_time: Duration,
+    pub draw_calls: u32,
+    pub cpu_spans: Vec<CpuSpan>,
+    /// Filled a few frames later, once the timestamps were read back.
+    pub gpu_passes: Vec<GpuPass>,
+}
+
+impl FrameProfile {
+    /// Time between the start of the first pass and the end of the last one.
+    pub fn gpu_time(&self) -> Option<Duration> {
+        let first = self.gpu_passes.first()?;
+        let last = self.gpu_passes.last()?;
+        Some(last.start + last.duration - first.start)
+    }
+}
+
+/// Records the CPU time of every span, the spans of a frame are collected by [Profiler::end_frame].
+pub struct ProfilerLayer;
+
+/// Per span data stored in the registry.
+struct SpanTiming {
+    name: Cow<'static, str>,
+    entered: Option<Instant>,
+}
+
+struct NameVisitor(Option<String>);
+
+impl Visit for NameVisitor {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        if field.name() == "name" {
+            self.0 = Some(value.to_string());
+        }
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn std::fmt::Debug) {
+        if field.name() == "name" {
+            self.0 = Some(format!("{:?}", value));
+        }
+    }
+}
+
+impl<S> Layer<S> for ProfilerLayer
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    fn on_new_span(&self, attrs: &span::Attributes<'_>, id: &span::Id, ctx: Context<'_, S>) {
+        let Some(span) = ctx.span(id) else {
+            return;
+        };
+        let mut visitor = NameVisitor(None);
+        attrs.record(&mut visitor);
+        let name = match visitor.0 {
+            Some(name) => Cow::Owned(name),
+            None => Cow::Borrowed(attrs.metadata().name()),
+        };
+        span.extensions_mut().insert(SpanTiming {
+            name,
+            entered: None,
+        });
+    }
+
+    fn on_enter(&self, id: &span::Id, ctx: Context<'_, S>) {
+        let Some(span) = ctx.span(id) else {
+            return;
+        };
+        let mut extensions = span.extensions_mut();
+        if let Some(timing) = extensions.get_mut::<SpanTiming>() {
+            timing.entered = Some(Instant::now());
+        }
+    }
+
+    fn on_exit(&self, id: &span::Id, ctx: Context<'_, S>) {
+        let Some(span) = ctx.span(id) else {
+            return;
+        };
+        let mut extensions = span.extensions_mut();
+        let Some(timing) = extensions.get_mut::<SpanTiming>() else {
+            return;
+        };
+        let Some(entered) = timing.entered.take() else {
+            return;
+        };
+        let cpu_span = CpuSpan {
+            name: timing.name.clone(),
+            span: span.metadata().name(),
+            thread: THREAD.with(|thread| *thread),
+            start: entered.saturating_duration_since(*START),
+            duration: entered.elapsed(),
+        };
+        let mut cpu_spans = CPU_SPANS.lock().unwrap();
+        if cpu_spans.len() < MAX_FRAME_SPANS {
+            cpu_spans.push(cpu_span);
+        }
+    }
+}
+
+/// History of the last [HISTORY_LEN] frames.
+pub struct Profiler {
+    /// Whether the profiler window is shown.
+    pub open: bool,
+    /// Stops recording new frames, to inspect the history.
+    pub paused: bool,
+    frames: VecDeque<FrameProfile>,
+    frame_index: u64,
+    frame_start: Duration,
+    cpu_start: Instant,
+    gpu: Option<GpuTimer>,
+}
+
+impl Profiler {
+    /// GPU timings are only measured if the device has [wgpu::Features::TIMESTAMP_QUERY].
+    pub fn new(device: &wgpu::Device, queue: &wgpu::Queue) -> Self {
+        let gpu = device
+            .features()
+            .contains(wgpu::Features::TIMESTAMP_QUERY)
+            .then(|| GpuTimer::new(device, queue));
+        Self {
+            open: false,
+            paused: false,
+            frames: VecDeque::with_capacity(HISTORY_LEN),
+            frame_index: 0,
+            frame_start: START.elapsed(),
+            cpu_start: Instant::now(),
+            gpu,
+        }
+    }
+
+    pub fn frames(&self) -> &VecDeque<FrameProfile> {
+        &self.frames
+    }
+
+    pub fn gpu_supported(&self) -> bool {
+        self.gpu.is_some()
+    }
+
+    /// Reserves the timestamp queries of a frame executing `pass_count` passes.
+    pub fn begin_frame(&mut self, pass_count: usize) {
+        self.cpu_start = Instant::now();
+        if let Some(gpu) = &mut self.gpu {
+            gpu.begin_frame(pass_count);
+        }
+    }
+
+    /// Query set the render graph writes the timestamps to, if the passes of this frame are timed.
+    pub fn query_set(&self) -> Option<&wgpu::QuerySet> {
+        self.gpu
+            .as_ref()
+            .filter(|gpu| gpu.current.is_some())
+            .map(|gpu| &gpu.query_set)
+    }
+
+    /// Copies the timestamps of the passes, in execution order, to a readback buffer.
+    pub fn resolve(&mut self, encoder: &mut wgpu::CommandEncoder, passes: Vec<&'static str>) {
+        if let Some(gpu) = &mut self.gpu {
+            gpu.resolve(encoder, self.frame_index, passes);
+        }
+    }
+
+    /// Closes the frame with the spans recorded since the previous one,
+    /// must be called after the frame was submitted.
+    pub fn end_frame(&mut self, device: &wgpu::Device) {
+        let now = self.push_frame();
+        let Some(gpu) = &mut self.gpu else {
+            return;
+        };
+        let passes = gpu.read_back(device, now);
+        self.set_gpu_passes(passes);
+    }
+
+    /// Waits for the GPU and reads back the timings of the frames still in flight, e.g. before
+    /// reporting the last frames.
+    pub fn wait_for_gpu(&mut self, device: &wgpu::Device) {
+        let Some(gpu) = &mut self.gpu else {
+            return;
+        };
+        device.poll(wgpu::Maintain::Wait);
+        let passes = gpu.mapped_passes();
+        self.set_gpu_passes(passes);
+    }
+
+    fn set_gpu_passes(&mut self, passes: Vec<(u64, Vec<GpuPass>)>) {
+        for (index, passes) in passes {
+            if let Some(frame) = self.frames.iter_mut().find(|frame| frame.index == index) {
+                frame.gpu_passes = passes;
+            }
+        }
+    }
+
+    /// Adds the frame to the history unless paused, returns its end.
+    fn push_frame(&mut self) -> Duration {
+        let now = START.elapsed();
+        let cpu_spans = std::mem::take(&mut *CPU_SPANS.lock().unwrap());
+        let draw_calls = DRAW_CALLS.swap(0, Ordering::Relaxed);
+        if !self.paused {
+            if self.frames.len() == HISTORY_LEN {
+                self.frames.pop_front();
+            }
+            self.frames.push_back(FrameProfile {
+                index: self.frame_index,
+                start: self.frame_start,
+                duration: now - self.frame_start,
+                cpu_time: self.cpu_start.elapsed(),
+                draw_calls,
+                cpu_spans,
+                gpu_passes: Vec::new(),
+            });
+        }
+        self.frame_index += 1;
+        self.frame_start = now;
+        now
+    }
+
+    /// Average CPU and GPU time of every pass over the history, in the order of the last frame.
+    pub fn pass_breakdown(&self) -> Vec<(Cow<'static, str>, Option<Duration>, Option<Duration>)> {
+        let Some(last) = self.frames.back() else {
+            return Vec::new();
+        };
+        let average = |durations: Vec<Duration>| {
+            (!durations.is_empty())
+                .then(|| durations.iter().sum::<Duration>() / durations.len() as u32)
+        };
+        last.cpu_spans
+            .iter()
+            .filter(|span| span.span == "Pass")
+            .map(|pass| {
+                let cpu = self
+                    .frames
+                    .iter()
+                    .flat_map(|frame| &frame.cpu_spans)
+                    .filter(|span| span.span == "Pass" && span.name == pass.name)
+                    .map(|span| span.duration)
+                    .collect();
+                let gpu = self
+                    .frames
+                    .iter()
+                    .flat_map(|frame| &frame.gpu_passes)
+                    .filter(|gpu_pass| gpu_pass.name == pass.name)
+                    .map(|gpu_pass| gpu_pass.duration)
+                    .collect();
+                (pass.name.clone(), average(cpu), average(gpu))
+            })
+            .collect()
+    }
+
+    /// Converts the history to the Chrome trace event format.
+    pub fn chrome_trace(&self) -> serde_json::Value {
+        let micros = |duration: Duration| duration.as_secs_f64() * 1e6;
+        let mut events = [(GPU_THREAD, "GPU"), (FRAME_THREAD, "Frames")]
+            .into_iter()
+            .map(|(thread, name)| {
+                json!({
+                    "name": "thread_name",
+                    "ph": "M",
+                    "pid": 1,
+                    "tid": thread,
+                    "args": { "name": name },
+                })
+            })
+            .collect::<Vec<_>>();
+        for frame in &self.frames {
+            events.push(json!({
+                "name": format!("Frame {}", frame.index),
+                "cat": "frame",
+                "ph": "X",
+                "ts": micros(frame.start),
+                "dur": micros(frame.duration),
+                "pid": 1,
+                "tid": FRAME_THREAD,
+            }));
+            for span in &frame.cpu_spans {
+                events.push(json!({
+                    "name": span.name,
+                    "cat": span.span,
+                    "ph": "X",
+                    "ts": micros(span.start),
+                    "dur": micros(span.duration),
+                    "pid": 1,
+                    "tid": span.thread,
+                }));
+            }
+            for pass in &frame.gpu_passes {
+                events.push(json!({
+                    "name": pass.name,
+                    "cat": "GPU",
+                    "ph": "X",
+                    "ts": micros(pass.start),
+                    "dur": micros(pass.duration),
+                    "pid": 1,
+                    "tid": GPU_THREAD,
+                }));
+            }
+        }
+        json!({ "traceEvents": events, "displayTimeUnit": "ms" })
+    }
+
+    pub fn export_chrome_trace(&self, path: &Path) -> Result<(), Error> {
+        let json = serde_json::to_vec(&self.chrome_trace()).map_err(io::Error::from)?;
+        fs::write(path, json)?;
+        Ok(())
+    }
+}
+
+/// Timestamps of the passes of a frame, read back once the GPU is done with it.
+struct Readback {
+    buffer: wgpu::Buffer,
+    /// Frame index and passes of the resolved timestamps, `None` while the buffer is free.
+    frame: Option<(u64, Vec<&'static str>)>,
+    submitted: Duration,
+    /// Set by the `map_async` callback.
+    mapped: Arc<Mutex<Option<Result<(), wgpu::BufferAsyncError>>>>,
+}
+
+struct GpuTimer {
+    query_set: wgpu::QuerySet,
+    resolve_buffer: wgpu::Buffer,
+    readbacks: Vec<Readback>,
+    /// Readback used by the frame being recorded.
+    current: Option<usize>,
+    /// Nanoseconds per timestamp tick.
+    period: f32,
+}
+
+impl GpuTimer {
+    fn new(device: &wgpu::Device, queue: &wgpu::Queue) -> Self {
+        let size = MAX_TIMED_PASSES as u64 * 2 * std::mem::size_of::<u64>() as u64;
+        let buffer = |label, usage| {
+            device.create_buffer(&wgpu::BufferDescriptor {
+                label: Some(label),
+                size,
+                usage,
+                mapped_at_creation: false,
+            })
+        };
+        Self {
+            query_set: device.create_query_set(&wgpu::QuerySetDescriptor {
+                label: Some("Pass Timestamps"),
+                ty: wgpu::QueryType::Timestamp,
+                count: MAX_TIMED_PASSES * 2,
+            }),
+            resolve_buffer: buffer(
+                "Timestamp Resolve Buffer",
+                wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::COPY_SRC,
+            ),
+            readbacks: (0..READBACK_FRAMES)
+                .map(|_| Readback {
+                    buffer: buffer(
+                        "Timestamp Readback Buffer",
+                        wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+                    ),
+                    frame: None,
+                    submitted: Duration::ZERO,
+                    mapped: Arc::default(),
+                })
+                .collect(),
+            current: None,
+            period: queue.get_timestamp_period(),
+        }
+    }
+
+    /// Frames are skipped while every readback buffer is still in use.
+    fn begin_frame(&mut self, pass_count: usize) {
+        self.current = if pass_count as u32 <= MAX_TIMED_PASSES {
+            self.readbacks
+                .iter()
+                .position(|readback| readback.frame.is_none())
+        } else {
+            None
+        };
+    }
+
+    fn resolve(
+        &mut self,
+        encoder: &mut wgpu::CommandEncoder,
+        frame: u64,
+        passes: Vec<&'static str>,
+    ) {
+        let Some(current) = self.current else {
+            return;
+        };
+        let count = passes.len() as u32 * 2;
+        if count == 0 {
+            self.current = None;
+            return;
+        }
+        let readback = &mut self.readbacks[current];
+        encoder.resolve_query_set(&self.query_set, 0..count, &self.resolve_buffer, 0);
+        encoder.copy_buffer_to_buffer(
+            &self.resolve_buffer,
+            0,
+            &readback.buffer,
+            0,
+            count as u64 * std::mem::size_of::<u64>() as u64,
+        );
+        readback.frame = Some((frame, passes));
+    }
+
+    /// Maps the readback of the submitted frame and returns the passes of the frames that were read.
+    fn read_back(&mut self, device: &wgpu::Device, now: Duration) -> Vec<(u64, Vec<GpuPass>)> {
+        if let Some(current) = self.current.take() {
+            let readback = &mut self.readbacks[current];
+            if readback.frame.is_some() {
+                let mapped = readback.mapped.clone();
+                readback
+                    .buffer
+                    .slice(..)
+                    .map_async(wgpu::MapMode::Read, move |result| {
+                        *mapped.lock().unwrap() = Some(result);
+                    });
+                readback.submitted = now;
+            }
+        }
+        device.poll(wgpu::Maintain::Poll);
+        self.mapped_passes()
+    }
+
+    /// Returns the passes of the readbacks mapped since the last call, and releases them.
+    fn mapped_passes(&mut self) -> Vec<(u64, Vec<GpuPass>)> {
+        let mut frames = Vec::new();
+        for readback in &mut self.readbacks {
+            let Some(result) = readback.mapped.lock().unwrap().take() else {
+                continue;
+            };
+            let (index, names) = readback.frame.take().unwrap();
+            if result.is_ok() {
+                let data = readback.buffer.slice(..).get_mapped_range();
+                let timestamps: &[u64] = bytemuck::cast_slice(&data);
+                let passes = gpu_passes(&names, timestamps, self.period, readback.submitted);
+                frames.push((index, passes));
+                drop(data);
+                readback.buffer.unmap();
+            }
+        }
+        frames
+    }
+}
+
+/// Converts the timestamps written by the render graph, the pass at position `i` wrote its start
+/// and end to `2i` and `2i + 1`, to passes placed relative to the moment the frame was submitted.
+fn gpu_passes(
+    names: &[&'static str],
+    timestamps: &[u64],
+    period: f32,
+    submitted: Duration,
+) -> Vec<GpuPass> {
+    let Some(&first) = timestamps.first() else {
+        return Vec::new();
+    };
+    let to_duration = |ticks: u64| {
+        Duration::from_nanos((ticks.saturating_sub(first) as f64 * period as f64) as u64)
+    };
+    names
+        .iter()
+        .zip(timestamps.chunks_exact(2))
+        .map(|(&name, timestamps)| {
+            let start = to_duration(timestamps[0]);
+            GpuPass {
+                name,
+                start: submitted + start,
+                duration: to_duration(timestamps[1]).saturating_sub(start),
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use tracing_subscriber::layer::SubscriberExt;
+
+    use super::*;
+
+    fn profiler() -> Profiler {
+        Profiler {
+            open: false,
+            paused: false,
+            frames: VecDeque::new(),
+            frame_index: 0,
+            frame_start: START.elapsed(),
+            cpu_start: Instant::now(),
+            gpu: None,
+        }
+    }
+
+    #[test]
+    fn aggregates_the_spans_of_every_frame() {
+        let subscriber = tracing_subscriber::registry().with(ProfilerLayer);
+        let mut profiler = profiler();
+        tracing::subscriber::with_default(subscriber, || {
+            for frame in 0..3 {
+                for name in ["shadow", "opaque"] {
+                    let span = tracing::trace_span!("Pass", name);
+                    let _guard = span.enter();
+                    std::thread::sleep(Duration::from_millis(frame + 1));
+                }
+                tracing::trace_span!("Other").in_scope(|| {});
+                profiler.push_frame();
+            }
+            profiler.paused = true;
+            tracing::trace_span!("Pass", name = "paused").in_scope(|| {});
+            profiler.push_frame();
+        });
+
+        let frames = profiler.frames();
+        assert_eq!(frames.len(), 3);
+        assert_eq!(
+            frames.iter().map(|f| f.index).collect::<Vec<_>>(),
+            [0, 1, 2]
+        );
+        // The spans of other tests are recorded by other threads.
+        let thread = THREAD.with(|thread| *thread);
+        let names = frames[2]
+            .cpu_spans
+            .iter()
+            .filter(|span| span.thread == thread)
+            .map(|span| (span.span, span.name.as_ref()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            names,
+            [("Pass", "shadow"), ("Pass", "opaque"), ("Other", "Other")]
+        );
+
+        let breakdown = profiler.pass_breakdown();
+        let names = breakdown
+            .iter()
+            .map(|(name, ..)| name.as_ref())
+            .collect::<Vec<_>>();
+        assert_eq!(names, ["shadow", "opaque"]);
+        // Averaged over the frames slept 1, 2 and 3 ms.
+        let (_, cpu, gpu) = &breakdown[0];
+        assert!(cpu.unwrap() >= Duration::from_millis(2));
+        assert_eq!(*gpu, None);
+    }
+
+    #[test]
+    fn reads_the_timestamps_of_each_pass() {
+        let submitted = Duration::from_secs(1);
+        let passes = gpu_passes(&["shadow", "opaque"], &[100, 150, 160, 260], 2.0, submitted);
+        let timings = passes
+            .iter()
+            .map(|pass| (pass.name, pass.start - submitted, pass.duration))
+            .collect::<Vec<_>>();
+        let nanos = Duration::from_nanos;
+        assert_eq!(
+            timings,
+            [
+                ("shadow", nanos(0), nanos(100)),
+                ("opaque", nanos(120), nanos(200))
+            ]
+        );
+        assert!(gpu_passes(&[], &[], 1.0, submitted).is_empty());
+    }
+}
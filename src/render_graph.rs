@@ -0,0 +1,361 @@
+use std::collections::HashMap;
+
+use crate::{egui_instance::UiFrame, error::RendererError, renderer::Renderer};
+
+/// Name of the swapchain texture acquired for the current frame.
+pub const SURFACE: &str = "surface";
+
+/// A unit of GPU work recorded into the frame's command encoder.
+///
+/// Passes only declare which resources they read and write,
+/// the [RenderGraph] derives the execution order from those declarations.
+///
+/// Every write makes a new version of the resource, the versions are ordered by the order the
+/// writers were added in. A read gets the version of the last writer added before the pass, or
+/// the first writer added after it if there is none.
+pub trait Pass {
+    /// Debug name of the pass.
+    fn name(&self) -> &'static str;
+    /// Resources that must be written before this pass runs, see [Pass] for the version read.
+    fn reads(&self) -> &[&'static str] {
+        &[]
+    }
+    /// Resources this pass renders into. The passes reading the previous version run first.
+    fn writes(&self) -> &[&'static str];
+    fn execute(&mut self, ctx: &mut PassContext);
+}
+
+/// Everything a [Pass] can access while recording.
+pub struct PassContext<'a> {
+    pub renderer: &'a Renderer,
+    pub encoder: &'a mut wgpu::CommandEncoder,
+    pub resources: GraphResources<'a>,
+    /// The user interface to paint this frame, if any.
+    pub ui: Option<UiFrame>,
+}
+
+/// Texture views of the graph resources for the current frame.
+#[derive(Clone, Copy)]
+pub struct GraphResources<'a> {
+    surface: &'a wgpu::TextureView,
+    transients: &'a HashMap<&'static str, TransientTexture>,
+}
+
+impl<'a> GraphResources<'a> {
+    /// Returns the view of a transient texture or of the [SURFACE].
+    pub fn view(&self, name: &str) -> &'a wgpu::TextureView {
+        if name == SURFACE {
+            return self.surface;
+        }
+        match self.transients.get(name) {
+            Some(transient) => &transient.view,
+            None => panic!("Unknown render graph resource `{}`", name),
+        }
+    }
+}
+
+/// Describes a texture owned by the graph, it always has the size of the surface.
+#[derive(Debug, Clone, Copy)]
+pub struct TransientDesc {
+    pub format: wgpu::TextureFormat,
+    pub usage: wgpu::TextureUsages,
+    pub sample_count: u32,
+}
+
+struct TransientTexture {
+    desc: TransientDesc,
+    _texture: wgpu::Texture,
+    view: wgpu::TextureView,
+}
+
+impl TransientTexture {
+    fn new(
+        device: &wgpu::Device,
+        name: &'static str,
+        desc: TransientDesc,
+        surface_size: (u32, u32),
+    ) -> Self {
+        let (width, height) = surface_size;
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some(name),
+            size: wgpu::Extent3d {
+                width: width.max(1),
+                height: height.max(1),
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: desc.sample_count,
+            dimension: wgpu::TextureDimension::D2,
+            format: desc.format,
+            usage: desc.usage,
+        });
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+        Self {
+            desc,
+            _texture: texture,
+            view,
+        }
+    }
+}
+
+/// Schedules the passes of a frame and owns their transient textures.
+#[derive(Default)]
+pub struct RenderGraph {
+    passes: Vec<Box<dyn Pass>>,
+    /// Indices into `passes` in execution order.
+    order: Vec<usize>,
+    transients: HashMap<&'static str, TransientTexture>,
+    surface_size: (u32, u32),
+}
+
+impl RenderGraph {
+    pub fn new(width: u32, height: u32) -> Self {
+        Self {
+            surface_size: (width, height),
+            ..Default::default()
+        }
+    }
+
+    /// Declares a texture owned by the graph, replacing any texture with the same name.
+    pub fn add_transient(
+        &mut self,
+        device: &wgpu::Device,
+        name: &'static str,
+        desc: TransientDesc,
+    ) {
+        let transient = TransientTexture::new(device, name, desc, self.surface_size);
+        self.transients.insert(name, transient);
+    }
+
+    pub fn remove_transient(&mut self, name: &str) {
+        self.transients.remove(name);
+    }
+
+    /// Adds a pass and reschedules the graph, see [Pass] for the ordering rules.
+    pub fn add_pass(&mut self, pass: impl Pass + 'static) -> Result<(), RendererError> {
+        self.passes.push(Box::new(pass));
+        match schedule(&self.passes) {
+            Ok(order) => {
+                self.order = order;
+                Ok(())
+            }
+            Err(error) => {
+                self.passes.pop();
+                Err(error)
+            }
+        }
+    }
+
+    /// Recreates the transient textures with the new surface size.
+    pub fn resize(&mut self, device: &wgpu::Device, width: u32, height: u32) {
+        self.surface_size = (width, height);
+        for (name, transient) in self.transients.iter_mut() {
+            *transient = TransientTexture::new(device, name, transient.desc, self.surface_size);
+        }
+    }
+
+    /// Names of the passes in execution order.
+    pub fn pass_names(&self) -> Vec<&'static str> {
+        self.order
+            .iter()
+            .map(|&index| self.passes[index].name())
+            .collect()
+    }
+
+    /// Records every pass in execution order.
+    ///
+    /// With `timestamps`, the pass at position `i` writes its start and end to queries `2i` and `2i + 1`.
+    pub fn execute(
+        &mut self,
+        renderer: &Renderer,
+        encoder: &mut wgpu::CommandEncoder,
+        surface: &wgpu::TextureView,
+        ui: Option<UiFrame>,
+        timestamps: Option<&wgpu::QuerySet>,
+    ) {
+        let mut ctx = PassContext {
+            renderer,
+            encoder,
+            resources: GraphResources {
+                surface,
+                transients: &self.transients,
+            },
+            ui,
+        };
+        for (position, &index) in self.order.iter().enumerate() {
+            let pass = &mut self.passes[index];
+            let span = tracing::span!(tracing::Level::TRACE, "Pass", name = pass.name());
+            let _guard = span.enter();
+            let query = position as u32 * 2;
+            if let Some(query_set) = timestamps {
+                ctx.encoder.write_timestamp(query_set, query);
+            }
+            pass.execute(&mut ctx);
+            if let Some(query_set) = timestamps {
+                ctx.encoder.write_timestamp(query_set, query + 1);
+            }
+        }
+    }
+}
+
+/// Topologically sorts the passes, ties are broken by insertion order.
+fn schedule(passes: &[Box<dyn Pass>]) -> Result<Vec<usize>, RendererError> {
+    let dependencies = dependencies(passes)?;
+    let mut order = Vec::with_capacity(passes.len());
+    let mut scheduled = vec![false; passes.len()];
+    while order.len() < passes.len() {
+        let next = (0..passes.len()).find(|&index| {
+            !scheduled[index] && dependencies[index].iter().all(|&dep| scheduled[dep])
+        });
+        match next {
+            Some(index) => {
+                scheduled[index] = true;
+                order.push(index);
+            }
+            None => {
+                let pending = (0..passes.len())
+                    .filter(|&index| !scheduled[index])
+                    .map(|index| passes[index].name())
+                    .collect::<Vec<_>>();
+                return Err(RendererError::RenderGraphCycle(pending.join(", ")));
+            }
+        }
+    }
+    Ok(order)
+}
+
+/// The passes each pass depends on: the writer of the versions it reads, the previous writer
+/// of the resources it writes and the readers of their previous version.
+fn dependencies(passes: &[Box<dyn Pass>]) -> Result<Vec<Vec<usize>>, RendererError> {
+    // The writers of every resource, one per version.
+    let mut writers = HashMap::<&str, Vec<usize>>::new();
+    for (index, pass) in passes.iter().enumerate() {
+        for &resource in pass.writes() {
+            writers.entry(resource).or_default().push(index);
+        }
+    }
+
+    let mut dependencies = vec![Vec::new(); passes.len()];
+    // The readers of every version, keyed by resource and writer.
+    let mut readers = HashMap::<(&str, usize), Vec<usize>>::new();
+    for (index, pass) in passes.iter().enumerate() {
+        for &resource in pass.reads() {
+            let versions = writers.get(resource).map_or(&[][..], Vec::as_slice);
+            let writer = versions
+                .iter()
+                .rev()
+                .find(|&&writer| writer < index)
+                .or_else(|| versions.iter().find(|&&writer| writer > index))
+                .ok_or(RendererError::RenderGraphMissingInput {
+                    pass: pass.name(),
+                    resource,
+                })?;
+            dependencies[index].push(*writer);
+            readers.entry((resource, *writer)).or_default().push(index);
+        }
+    }
+    for (resource, versions) in &writers {
+        for pair in versions.windows(2) {
+            let (previous, next) = (pair[0], pair[1]);
+            dependencies[next].push(previous);
+            let previous_readers = readers.get(&(*resource, previous)).into_iter().flatten();
+            dependencies[next].extend(previous_readers.filter(|&&reader| reader != next));
+        }
+    }
+    Ok(dependencies)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct TestPass {
+        name: &'static str,
+        reads: Vec<&'static str>,
+        writes: Vec<&'static str>,
+    }
+
+    impl Pass for TestPass {
+        fn name(&self) -> &'static str {
+            self.name
+        }
+
+        fn reads(&self) -> &[&'static str] {
+            &self.reads
+        }
+
+        fn writes(&self) -> &[&'static str] {
+            &self.writes
+        }
+
+        fn execute(&mut self, _: &mut PassContext) {}
+    }
+
+    /// Passes of `(name, reads, writes)`.
+    fn passes(passes: &[(&'static str, &[&'static str], &[&'static str])]) -> Vec<Box<dyn Pass>> {
+        passes
+            .iter()
+            .map(|&(name, reads, writes)| {
+                Box::new(TestPass {
+                    name,
+                    reads: reads.to_vec(),
+                    writes: writes.to_vec(),
+                }) as Box<dyn Pass>
+            })
+            .collect()
+    }
+
+    fn names(passes: &[Box<dyn Pass>], order: &[usize]) -> Vec<&'static str> {
+        order.iter().map(|&index| passes[index].name()).collect()
+    }
+
+    #[test]
+    fn runs_the_writers_before_their_readers() {
+        let passes = passes(&[
+            ("tonemap", &["hdr", "bloom"], &["surface"]),
+            ("scene", &[], &["hdr"]),
+            ("bloom", &["hdr"], &["bloom"]),
+            ("ui", &[], &["surface"]),
+        ]);
+        let order = schedule(&passes).unwrap();
+        assert_eq!(names(&passes, &order), ["scene", "bloom", "tonemap", "ui"]);
+    }
+
+    #[test]
+    fn runs_the_readers_of_a_version_before_the_next_writer() {
+        let passes = passes(&[
+            ("opaque", &[], &["hdr", "depth"]),
+            ("translucent", &["depth"], &["hdr"]),
+            ("debug", &["depth"], &["hdr", "depth"]),
+            ("lines", &["depth"], &["hdr"]),
+        ]);
+        let dependencies = dependencies(&passes).unwrap();
+        // The debug pass overwrites the depth read by the translucent pass.
+        assert!(dependencies[2].contains(&1));
+        // The lines read the depth written by the debug pass.
+        assert!(dependencies[3].contains(&2));
+        assert!(!dependencies[1].contains(&2));
+        let order = schedule(&passes).unwrap();
+        assert_eq!(
+            names(&passes, &order),
+            ["opaque", "translucent", "debug", "lines"]
+        );
+    }
+
+    #[test]
+    fn rejects_cycles_and_missing_inputs() {
+        let cycle = passes(&[("a", &["x"], &["y"]), ("b", &["y"], &["x"])]);
+        assert!(matches!(
+            schedule(&cycle),
+            Err(RendererError::RenderGraphCycle(passes)) if passes == "a, b"
+        ));
+        let missing = passes(&[("a", &[], &["x"]), ("b", &["y"], &["x"])]);
+        assert!(matches!(
+            schedule(&missing),
+            Err(RendererError::RenderGraphMissingInput {
+                pass: "b",
+                resource: "y"
+            })
+        ));
+    }
+}
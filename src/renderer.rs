@@ -1,83 +1,239 @@
-use tracing::info;
-use vek::{FrustumPlanes, Mat4, Vec2, Vec3};
-use wgpu::{util::DeviceExt, BufferUsages, CommandEncoder, SurfaceTexture, TextureUsages};
-use winit::dpi::PhysicalSize;
+use std::{
+    panic::{self, AssertUnwindSafe},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+};
+
+use tracing::{error, info, warn};
+use vek::{Vec2, Vec3};
+use wgpu::{BufferUsages, CommandEncoder};
+use wgpu_core::{
+    binding_model::CreateBindGroupError,
+    device::DeviceError,
+    error::ContextError,
+    pipeline::CreateRenderPipelineError,
+    resource::{CreateBufferError, CreateTextureError},
+};
 
 use crate::{
     buffer::Buffer,
     camera::{Camera, CameraBufferData},
+    debug_draw::{DebugDraw, DebugDrawPass},
+    debug_view::{DebugMode, DebugPass, DebugView},
+    egui_instance::UiFrame,
     error::RendererError,
-    vertex::{Vertex, INDICES, VERTICES},
+    graphics::{Backend, GraphicsSettings},
+    mesh::{Mesh, RenderLayer},
+    passes::{OpaquePass, ShadowPass, TranslucentPass, DEPTH, MULTISAMPLED_HDR},
+    pipeline::{DepthState, PipelineCache, PipelineDesc},
+    post::{
+        BloomPass, FxaaPass, PostProcessing, TonemapPass, BLOOM, BLOOM_BLUR, HDR, HDR_FORMAT, LDR,
+        LDR_FORMAT,
+    },
+    profiler::Profiler,
+    render_graph::{Pass, RenderGraph, TransientDesc},
+    settings::RendererSettings,
+    shader_manager::ShaderManager,
+    shader_preprocessor::ShaderDefines,
+    shadow::{ShadowMap, ShadowSettings, MAX_SHADOW_CASCADES},
+    surface::{self, Frame, WindowSurface},
+    texture::Texture,
+    vertex::{
+        Vertex, GLASS_VERTICES, INDICES, LEAVES_VERTICES, QUAD_INDICES, VERTICES, WATER_VERTICES,
+    },
     window::Window,
 };
 /// The `Renderer` is the SandBox's rendering system.
 /// It can interact with the GPU.  
 pub struct Renderer {
-    pub surface: wgpu::Surface,
+    /// `None` when headless.
+    pub surface: Option<wgpu::Surface>,
+    /// Texture the frames are rendered to when headless.
+    offscreen: Option<Arc<wgpu::Texture>>,
     pub surface_config: wgpu::SurfaceConfiguration,
     pub device: wgpu::Device,
     pub queue: wgpu::Queue,
-    pub vertex_buffer: Buffer<Vertex>,
     pub camera_buffer: Buffer<CameraBufferData>,
     pub resolution: Vec2<u32>,
-    index_buffer: Buffer<u16>,
-    pipeline: wgpu::RenderPipeline,
+    meshes: Vec<Mesh>,
+    graph: RenderGraph,
+    pipelines: PipelineCache,
+    scene_pipelines: ScenePipelines,
+    sample_count: u32,
+    supported_sample_counts: Vec<u32>,
     clear_color: wgpu::Color,
     pub camera_projection: CameraBufferData,
+    /// Whether [Renderer::camera_projection] is written to the buffer with the next frame.
+    camera_outdated: bool,
     camera_bind_group: wgpu::BindGroup,
     pub camera: Camera,
+    pub shadow: ShadowMap,
+    pub post: PostProcessing,
+    pub shaders: ShaderManager,
+    debug: DebugView,
+    debug_draw: DebugDraw,
+    pub profiler: Profiler,
+    /// Adapters of every backend found when the renderer was created.
+    available_adapters: Vec<wgpu::AdapterInfo>,
+    adapter_info: wgpu::AdapterInfo,
+    present_modes: Vec<wgpu::PresentMode>,
+    graphics: GraphicsSettings,
+    /// Settings needing a new device, the renderer is then recreated by the event loop.
+    graphics_request: Option<GraphicsSettings>,
+    /// Set by the uncaptured error handler.
+    device_lost: Arc<AtomicBool>,
+}
+
+/// The CPU side state kept when the [Renderer] is recreated.
+pub struct RendererState {
+    /// Settings of the new renderer.
+    pub graphics: GraphicsSettings,
+    /// Settings of the renderer that was released, to go back to them if the new ones fail.
+    pub previous_graphics: GraphicsSettings,
+    pub camera: Camera,
+    pub settings: RendererSettings,
+    pub sun_direction: Vec3<f32>,
+    pub show_cascades: bool,
+    pub debug_mode: DebugMode,
+    pub show_axes: bool,
+    pub profiler_open: bool,
 }
 
 impl Renderer {
-    pub fn new(window: &Window) -> Result<Self, RendererError> {
-        let backend = wgpu::Backends::all();
-        let instance = wgpu::Instance::new(backend);
+    /// Creates the renderer with the backend, adapter and present mode of `graphics`.
+    pub fn new(window: &Window, graphics: GraphicsSettings) -> Result<Self, RendererError> {
+        let instance = wgpu::Instance::new(graphics.backend.backends());
 
         // This is unsafe because the window handle must be valid, if you find a way to
         // have an invalid winit::Window then you have bigger issues
         let surface = unsafe { instance.create_surface(&window.winit()) };
+        Self::create(instance, Some(surface), *window.resolution(), graphics)
+    }
+
+    /// Creates a renderer drawing to an offscreen texture of `resolution`, without a window.
+    /// Falls back to the fallback adapter, usually a software rasterizer, if there is no GPU.
+    pub fn headless(
+        resolution: Vec2<u32>,
+        graphics: GraphicsSettings,
+    ) -> Result<Self, RendererError> {
+        let instance = wgpu::Instance::new(graphics.backend.backends());
+        Self::create(instance, None, resolution, graphics)
+    }
+
+    fn create(
+        instance: wgpu::Instance,
+        surface: Option<wgpu::Surface>,
+        dimensions: Vec2<u32>,
+        graphics: GraphicsSettings,
+    ) -> Result<Self, RendererError> {
+        let backend = graphics.backend.backends();
 
         // Collect and Log adapters
-        let adapters = instance
-            .enumerate_adapters(backend)
-            .enumerate()
+        let mut adapters = instance.enumerate_adapters(backend).collect::<Vec<_>>();
+        let adapter_infos = adapters
+            .iter()
+            .map(wgpu::Adapter::get_info)
             .collect::<Vec<_>>();
-
-        adapters.iter().for_each(|(index, entry)| {
-            let info = entry.get_info();
+        adapter_infos.iter().enumerate().for_each(|(index, info)| {
             info!(?info, "graphics device #{}", index);
         });
+        // The other backends are only listed to offer the ones that can be used. The backend in
+        // use isn't instantiated twice, a second GL instance would break the current context.
+        let others = wgpu::Backends::all() - backend;
+        let mut available_adapters = adapter_infos.clone();
+        if !others.is_empty() {
+            available_adapters.extend(
+                wgpu::Instance::new(others)
+                    .enumerate_adapters(others)
+                    .map(|adapter| adapter.get_info()),
+            );
+        }
 
-        let adapter =
-            pollster::block_on(instance.request_adapter(&wgpu::RequestAdapterOptionsBase {
-                power_preference: wgpu::PowerPreference::LowPower,
-                force_fallback_adapter: false,
-                compatible_surface: Some(&surface),
-            }))
-            .ok_or(RendererError::AdapterNotFound)?;
+        let requested = graphics.adapter.as_ref().and_then(|selection| {
+            let index = selection.find(&adapter_infos).filter(|&index| {
+                surface
+                    .as_ref()
+                    .is_none_or(|surface| adapters[index].is_surface_supported(surface))
+            });
+            if index.is_none() {
+                warn!(
+                    adapter = %selection,
+                    "The requested adapter can't be used, using the default one"
+                );
+            }
+            index
+        });
+        let adapter = match requested {
+            Some(index) => adapters.swap_remove(index),
+            None => {
+                let request = |force_fallback_adapter| {
+                    pollster::block_on(instance.request_adapter(&wgpu::RequestAdapterOptionsBase {
+                        power_preference: graphics.power_preference.wgpu(),
+                        force_fallback_adapter,
+                        compatible_surface: surface.as_ref(),
+                    }))
+                };
+                request(false)
+                    .or_else(|| {
+                        surface.is_none().then(|| {
+                            info!("No graphics device found, using the fallback adapter");
+                            request(true)
+                        })?
+                    })
+                    .ok_or(RendererError::AdapterNotFound)?
+            }
+        };
 
         let info = adapter.get_info();
         info!(?info, "Selected graphics device");
 
+        // Needed to use the sample counts reported by the adapter instead of only 1 and 4,
+        // to draw wireframes without the barycentric fallback and to time the passes on the GPU.
+        let features = adapter.features()
+            & (wgpu::Features::TEXTURE_ADAPTER_SPECIFIC_FORMAT_FEATURES
+                | wgpu::Features::POLYGON_MODE_LINE
+                | wgpu::Features::TIMESTAMP_QUERY);
         let (device, queue) = pollster::block_on(adapter.request_device(
             &wgpu::DeviceDescriptor {
                 label: None,
-                features: wgpu::Features::default(),
+                features,
                 limits: wgpu::Limits::default(),
             },
             None,
         ))?;
-        let dimensions = window.resolution();
-        let format = surface.get_supported_formats(&adapter)[0];
+        // Prefer a linear surface so gamma is applied by the post-processing chain
+        // on every platform, instead of depending on the first reported format.
+        let format = match &surface {
+            Some(surface) => {
+                let formats = surface.get_supported_formats(&adapter);
+                formats
+                    .iter()
+                    .copied()
+                    .find(|format| !format.describe().srgb)
+                    .unwrap_or(formats[0])
+            }
+            None => wgpu::TextureFormat::Rgba8Unorm,
+        };
+        let present_modes = surface
+            .as_ref()
+            .map(|surface| surface.get_supported_present_modes(&adapter))
+            .unwrap_or_default();
         let surface_cfg = wgpu::SurfaceConfiguration {
             usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
             format,
             width: dimensions.x,
             height: dimensions.y,
-            present_mode: wgpu::PresentMode::Fifo,
+            present_mode: graphics.present_mode.select(&present_modes),
             alpha_mode: wgpu::CompositeAlphaMode::Auto,
         };
-        surface.configure(&device, &surface_cfg);
+        if let Some(surface) = &surface {
+            surface.configure(&device, &surface_cfg);
+        }
+        let offscreen = surface
+            .is_none()
+            .then(|| Arc::new(create_offscreen(&device, &surface_cfg)));
 
         let camera_pos = Vec3::new(0.0, 0.0, -3.0);
         let target = Vec3::zero(); // Undefined direction
@@ -106,61 +262,74 @@ impl Renderer {
                 label: Some("camera_bind_group_layout"),
             });
 
+        let mut shaders = ShaderManager::new();
+        let mut pipelines = PipelineCache::default();
+        let shadow = ShadowMap::new(
+            &device,
+            &mut pipelines,
+            &mut shaders,
+            ShadowSettings::default(),
+        );
+
         let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
             label: Some("Pipeline Layout Descriptor"),
-            bind_group_layouts: &[&camera_bind_group_layout],
+            bind_group_layouts: &[&camera_bind_group_layout, shadow.bind_group_layout()],
             push_constant_ranges: &[],
         });
 
-        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
-            label: Some("Shader Module"),
-            source: wgpu::ShaderSource::Wgsl(include_str!("../assets/shaders/shader.wgsl").into()),
-        });
+        pipelines.add_layout(SCENE_LAYOUT, pipeline_layout);
 
-        let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
-            label: Some("Render Pipelime"),
-            layout: Some(&pipeline_layout),
-            vertex: wgpu::VertexState {
-                module: &shader,
-                entry_point: "vs_main",
-                buffers: &[Vertex::layout()],
-            },
-            fragment: Some(wgpu::FragmentState {
-                module: &shader,
-                entry_point: "fs_main",
-                targets: &[Some(wgpu::ColorTargetState {
-                    format: surface_cfg.format,
-                    blend: Some(wgpu::BlendState::REPLACE),
-                    write_mask: wgpu::ColorWrites::ALL,
-                })],
-            }),
-            // how to interpret our vertices when converting them into triangles.
-            primitive: wgpu::PrimitiveState {
-                topology: wgpu::PrimitiveTopology::TriangleList,
-                strip_index_format: None,
-                front_face: wgpu::FrontFace::Ccw,
-                cull_mode: Some(wgpu::Face::Back),
-                // Requires Features::DEPTH_CLIP_CONTROL
-                unclipped_depth: false,
-                // Setting this to anything other than Fill requires Features::NON_FILL_POLYGON_MODE
-                polygon_mode: wgpu::PolygonMode::Fill,
-                // Requires Features::CONSERVATIVE_RASTERIZATION
-                conservative: false,
-            },
-            depth_stencil: None,
-            multisample: wgpu::MultisampleState {
-                count: 1,
-                mask: !0,
-                alpha_to_coverage_enabled: false,
-            },
-            multiview: None,
-        });
-        let vertex_buffer = Buffer::new(
-            &device,
-            VERTICES,
-            BufferUsages::VERTEX | BufferUsages::COPY_DST,
-        );
-        let index_buffer = Buffer::new(&device, INDICES, BufferUsages::INDEX);
+        let supported_sample_counts = supported_sample_counts(&adapter);
+        let sample_count = 1;
+        let scene_pipelines =
+            ScenePipelines::new(&device, &mut pipelines, &mut shaders, sample_count);
+        let meshes = vec![
+            Mesh::new(&device, VERTICES, INDICES, RenderLayer::Opaque),
+            Mesh::new(&device, LEAVES_VERTICES, QUAD_INDICES, RenderLayer::Cutout),
+            Mesh::new(
+                &device,
+                WATER_VERTICES,
+                QUAD_INDICES,
+                RenderLayer::Translucent,
+            ),
+            Mesh::new(
+                &device,
+                GLASS_VERTICES,
+                QUAD_INDICES,
+                RenderLayer::Translucent,
+            ),
+        ];
+
+        let mut graph = RenderGraph::new(surface_cfg.width, surface_cfg.height);
+        add_scene_targets(&mut graph, &device, sample_count);
+        graph.add_pass(ShadowPass)?;
+        graph.add_pass(OpaquePass)?;
+        graph.add_pass(TranslucentPass)?;
+        graph.add_pass(DebugPass)?;
+        graph.add_pass(DebugDrawPass)?;
+        let debug_draw = DebugDraw::new(&device, &mut pipelines, &mut shaders, sample_count);
+
+        let post = PostProcessing::new(&device, &mut pipelines, &mut shaders, surface_cfg.format);
+        for (name, format) in [
+            (HDR, HDR_FORMAT),
+            (BLOOM, HDR_FORMAT),
+            (BLOOM_BLUR, HDR_FORMAT),
+            (LDR, LDR_FORMAT),
+        ] {
+            graph.add_transient(
+                &device,
+                name,
+                TransientDesc {
+                    format,
+                    usage: wgpu::TextureUsages::RENDER_ATTACHMENT
+                        | wgpu::TextureUsages::TEXTURE_BINDING,
+                    sample_count: 1,
+                },
+            );
+        }
+        graph.add_pass(BloomPass)?;
+        graph.add_pass(TonemapPass)?;
+        graph.add_pass(FxaaPass)?;
         // let instance_buffer = Buffer::instance(&device, &[instance_data]);
         let camera_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
             layout: &camera_bind_group_layout,
@@ -171,65 +340,395 @@ impl Renderer {
             label: Some("camera_bind_group"),
         });
 
+        let profiler = Profiler::new(&device, &queue);
+
+        // The loss of the device, or of its memory, is recovered from by recreating the renderer.
+        // Other errors are logged instead of panicking like the default handler.
+        let device_lost = Arc::new(AtomicBool::new(false));
+        let lost = device_lost.clone();
+        device.on_uncaptured_error(move |error| {
+            if is_device_lost(&error) {
+                error!(%error, "The graphics device was lost");
+                lost.store(true, Ordering::Relaxed);
+            } else {
+                error!(%error, "Graphics error");
+            }
+        });
+
         let renderer = Self {
             surface,
+            offscreen,
             device,
             queue,
             surface_config: surface_cfg,
-            resolution: *dimensions,
-            pipeline,
-            vertex_buffer,
+            resolution: dimensions,
+            meshes,
+            graph,
+            pipelines,
+            scene_pipelines,
+            sample_count,
+            supported_sample_counts,
             clear_color: wgpu::Color {
                 r: 0.2,
                 g: 0.6,
                 b: 0.5,
                 a: 1.0,
             },
-            index_buffer,
             camera_buffer,
             camera_projection: camera_buffer_data,
+            camera_outdated: false,
             camera,
             camera_bind_group,
+            shadow,
+            post,
+            shaders,
+            debug: DebugView::new(),
+            debug_draw,
+            profiler,
+            available_adapters,
+            adapter_info: info,
+            present_modes,
+            graphics,
+            graphics_request: None,
+            device_lost,
         };
         Ok(renderer)
     }
 
-    pub fn start_frame(&mut self, encoder: &mut CommandEncoder) -> SurfaceTexture {
-        let texture = match self.surface.get_current_texture() {
-            Ok(tex) => tex,
-            Err(e) => {
-                eprintln!("{:#?}", e);
-                panic!()
+    /// Releases the GPU objects, keeping the state needed to create a new renderer.
+    pub fn into_state(self) -> RendererState {
+        let state = RendererState {
+            settings: self.settings(),
+            graphics: self
+                .graphics_request
+                .clone()
+                .unwrap_or_else(|| self.graphics.clone()),
+            previous_graphics: self.graphics.clone(),
+            camera: self.camera.clone(),
+            sun_direction: self.shadow.sun_direction,
+            show_cascades: self.shadow.show_cascades,
+            debug_mode: self.debug.mode(),
+            show_axes: self.debug.show_axes,
+            profiler_open: self.profiler.open,
+        };
+        // Dropping a lost device waits for it, which panics.
+        if catch_device_loss(|| drop(self)).is_err() {
+            warn!("The lost device couldn't be released cleanly");
+        }
+        state
+    }
+
+    /// Applies the state of a previous renderer.
+    pub fn restore(&mut self, state: RendererState) {
+        self.camera = state.camera;
+        self.upload_camera();
+        self.apply_settings(&state.settings);
+        self.shadow.sun_direction = state.sun_direction;
+        self.shadow.show_cascades = state.show_cascades;
+        self.set_debug_mode(state.debug_mode);
+        self.debug.show_axes = state.show_axes;
+        self.profiler.open = state.profiler_open;
+    }
+
+    /// Uploads the matrices of [Renderer::camera] with the next frame, after it moved.
+    ///
+    /// The buffer is written by [Renderer::start_frame], a write to the queue of a lost device
+    /// panics and must happen in [catch_device_loss].
+    pub fn upload_camera(&mut self) {
+        self.camera_projection.set_mvp_from_mat(
+            self.camera
+                .build_mvp(self.resolution.x as f32, self.resolution.y as f32),
+        );
+        self.camera_outdated = true;
+    }
+
+    /// The settings saved with the user settings.
+    pub fn settings(&self) -> RendererSettings {
+        let color = self.clear_color;
+        RendererSettings {
+            clear_color: [color.r, color.g, color.b],
+            sample_count: self.sample_count,
+            shadow: self.shadow.settings,
+            post: self.post.settings,
+        }
+    }
+
+    pub fn apply_settings(&mut self, settings: &RendererSettings) {
+        let [r, g, b] = settings.clear_color;
+        self.clear_color = wgpu::Color { r, g, b, a: 1.0 };
+        if settings.shadow != self.shadow.settings {
+            self.shadow.set_settings(&self.device, settings.shadow);
+        }
+        self.post.settings = settings.post;
+        self.set_sample_count(settings.sample_count);
+    }
+
+    /// Whether the device was lost, the renderer must then be recreated.
+    pub fn device_lost(&self) -> bool {
+        self.device_lost.load(Ordering::Relaxed)
+    }
+
+    /// The backends with at least one adapter, [Backend::Auto] first.
+    pub fn available_backends(&self) -> Vec<Backend> {
+        Backend::ALL
+            .into_iter()
+            .filter(|backend| !self.adapters(*backend).is_empty())
+            .collect()
+    }
+
+    /// Information about every adapter of `backend`, in the order of
+    /// [crate::graphics::AdapterSelection::Index].
+    pub fn adapters(&self, backend: Backend) -> Vec<wgpu::AdapterInfo> {
+        self.available_adapters
+            .iter()
+            .filter(|info| backend.backends().contains(info.backend.into()))
+            .cloned()
+            .collect()
+    }
+
+    /// The settings in use, or the pending ones waiting for the renderer to be recreated.
+    pub fn graphics(&self) -> &GraphicsSettings {
+        self.graphics_request.as_ref().unwrap_or(&self.graphics)
+    }
+
+    /// Applies the present mode and frame cap right away. A different backend, adapter or
+    /// power preference is applied by recreating the renderer, see [Renderer::needs_recreation].
+    pub fn set_graphics(&mut self, graphics: GraphicsSettings) {
+        if graphics == *self.graphics() {
+            return;
+        }
+        if self.graphics.needs_new_device(&graphics) {
+            self.graphics_request = Some(graphics);
+            return;
+        }
+        self.graphics_request = None;
+        let present_mode = graphics.present_mode.select(&self.present_modes);
+        if present_mode != self.surface_config.present_mode {
+            info!(?present_mode, "Changing the present mode");
+            self.surface_config.present_mode = present_mode;
+            if let Some(surface) = &self.surface {
+                surface.configure(&self.device, &self.surface_config);
             }
+        }
+        self.graphics = graphics;
+    }
+
+    /// Whether the renderer must be recreated, after losing the device or to apply settings.
+    pub fn needs_recreation(&self) -> bool {
+        self.device_lost() || self.graphics_request.is_some()
+    }
+
+    /// The adapter in use.
+    pub fn adapter_info(&self) -> &wgpu::AdapterInfo {
+        &self.adapter_info
+    }
+
+    /// Acquires the next surface texture, see [surface::acquire] for the handled errors,
+    /// or the offscreen texture when headless.
+    ///
+    /// Returns `None` when the frame must be skipped.
+    pub fn acquire_frame(&mut self) -> Result<Option<Frame>, RendererError> {
+        let Some(surface) = &self.surface else {
+            return Ok(self.offscreen.clone().map(Frame::Offscreen));
         };
-        let texture_view = texture
-            .texture
+        let texture = surface::acquire(&mut WindowSurface {
+            surface,
+            device: &self.device,
+            config: &self.surface_config,
+        })?;
+        Ok(texture.map(Frame::Window))
+    }
+
+    /// Records every pass of the render graph into the acquired frame.
+    pub fn start_frame(
+        &mut self,
+        encoder: &mut CommandEncoder,
+        frame: &Frame,
+        ui: Option<UiFrame>,
+    ) {
+        // The graph is moved out below so that passes can borrow the renderer.
+        let pass_names = self.graph.pass_names();
+        self.profiler.begin_frame(pass_names.len());
+        self.reload_shaders();
+        let texture_view = frame
+            .texture()
             .create_view(&wgpu::TextureViewDescriptor::default());
 
-        {
-            let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
-                label: Some("Render Pass"),
-                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
-                    view: &texture_view,
-                    resolve_target: None,
-                    ops: wgpu::Operations {
-                        load: wgpu::LoadOp::Clear(self.clear_color),
-                        store: true,
-                    },
-                })],
-                depth_stencil_attachment: None,
-            });
-            render_pass.set_pipeline(&self.pipeline);
-            render_pass.set_bind_group(0, &self.camera_bind_group, &[]);
-            render_pass.set_vertex_buffer(0, self.vertex_buffer.data().slice(..));
-            render_pass.set_index_buffer(
-                self.index_buffer.data().slice(..),
-                wgpu::IndexFormat::Uint16,
+        if std::mem::take(&mut self.camera_outdated) {
+            self.camera_buffer
+                .update(&self.queue, &[self.camera_projection], 0);
+        }
+        self.post.update(&self.queue);
+        self.shadow.update(
+            &self.queue,
+            &self.camera,
+            self.resolution.x as f32,
+            self.resolution.y as f32,
+        );
+        self.debug.draw(&self.meshes);
+        self.debug_draw.upload(&self.device, &self.queue);
+
+        let mut graph = std::mem::take(&mut self.graph);
+        graph.execute(self, encoder, &texture_view, ui, self.profiler.query_set());
+        self.graph = graph;
+        self.profiler.resolve(encoder, pass_names);
+    }
+
+    /// Renders the scene of the last frame again, without the UI, to a new texture of `size`
+    /// that can be copied, e.g. to capture it. The render targets are resized for it when
+    /// `size` isn't the resolution. It isn't profiled.
+    pub fn render_copyable(
+        &mut self,
+        encoder: &mut CommandEncoder,
+        size: Vec2<u32>,
+    ) -> wgpu::Texture {
+        let config = wgpu::SurfaceConfiguration {
+            width: size.x,
+            height: size.y,
+            ..self.surface_config.clone()
+        };
+        let texture = create_offscreen(&self.device, &config);
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+        let resize = size != Vec2::new(self.surface_config.width, self.surface_config.height);
+        if resize {
+            self.graph.resize(&self.device, size.x, size.y);
+        }
+        let mut graph = std::mem::take(&mut self.graph);
+        graph.execute(self, encoder, &view, None, None);
+        self.graph = graph;
+        // The commands keep the targets of `size` alive until they are executed.
+        if resize {
+            self.graph.resize(
+                &self.device,
+                self.surface_config.width,
+                self.surface_config.height,
+            );
+        }
+        texture
+    }
+
+    /// Collects the profiler timings, call it once the frame was submitted.
+    pub fn finish_frame(&mut self) {
+        self.profiler.end_frame(&self.device);
+    }
+
+    /// Rebuilds the pipelines of the shaders modified on disk.
+    /// A shader that fails to compile keeps its previous pipelines, the error is shown by the UI.
+    fn reload_shaders(&mut self) {
+        let mut reloaded = false;
+        for name in self.shaders.changed() {
+            info!(name, "Reloading shader");
+            reloaded |= self
+                .pipelines
+                .reload(&self.device, &mut self.shaders, &name);
+        }
+        if reloaded {
+            self.update_pipelines();
+        }
+    }
+
+    /// Fetches every pipeline from the cache again, after a shader or MSAA change.
+    fn update_pipelines(&mut self) {
+        self.scene_pipelines = ScenePipelines::new(
+            &self.device,
+            &mut self.pipelines,
+            &mut self.shaders,
+            self.sample_count,
+        );
+        self.shadow
+            .update_pipeline(&self.device, &mut self.pipelines, &mut self.shaders);
+        self.post
+            .update_pipelines(&self.device, &mut self.pipelines, &mut self.shaders);
+        self.debug.update_pipeline(
+            &self.device,
+            &mut self.pipelines,
+            &mut self.shaders,
+            self.sample_count,
+        );
+        self.debug_draw.update_pipelines(
+            &self.device,
+            &mut self.pipelines,
+            &mut self.shaders,
+            self.sample_count,
+        );
+    }
+
+    /// Adds a pass to the render graph.
+    pub fn add_pass(&mut self, pass: impl Pass + 'static) -> Result<(), RendererError> {
+        self.graph.add_pass(pass)
+    }
+
+    pub fn scene_pipelines(&self) -> &ScenePipelines {
+        &self.scene_pipelines
+    }
+
+    /// Number of MSAA samples per pixel of the scene targets.
+    pub fn sample_count(&self) -> u32 {
+        self.sample_count
+    }
+
+    pub fn supported_sample_counts(&self) -> &[u32] {
+        &self.supported_sample_counts
+    }
+
+    /// Rebuilds the scene pipelines and targets with a new MSAA sample count.
+    pub fn set_sample_count(&mut self, sample_count: u32) {
+        if sample_count == self.sample_count {
+            return;
+        }
+        if !self.supported_sample_counts.contains(&sample_count) {
+            warn!(
+                sample_count,
+                supported = ?self.supported_sample_counts,
+                "The adapter doesn't support the MSAA sample count"
             );
-            render_pass.draw_indexed(0..self.index_buffer.len() as u32, 0, 0..1);
-            // render_pass.draw(0..self.vertices.len() as u32, 0..1);
+            return;
         }
-        return texture;
+        info!(sample_count, "Changing MSAA sample count");
+        self.sample_count = sample_count;
+        self.update_pipelines();
+        add_scene_targets(&mut self.graph, &self.device, sample_count);
+    }
+
+    pub fn debug(&self) -> &DebugView {
+        &self.debug
+    }
+
+    /// Use [Renderer::set_debug_mode] to change the mode.
+    pub fn debug_mut(&mut self) -> &mut DebugView {
+        &mut self.debug
+    }
+
+    pub fn debug_draw(&self) -> &DebugDraw {
+        &self.debug_draw
+    }
+
+    pub fn set_debug_mode(&mut self, mode: DebugMode) {
+        if mode == self.debug.mode() {
+            return;
+        }
+        info!(?mode, "Changing debug view");
+        self.debug.set_mode(mode);
+        self.debug.update_pipeline(
+            &self.device,
+            &mut self.pipelines,
+            &mut self.shaders,
+            self.sample_count,
+        );
+        // Debug colors are displayed as is.
+        self.post.passthrough = mode.replaces_scene();
+    }
+
+    pub fn meshes(&self) -> &[Mesh] {
+        &self.meshes
+    }
+
+    pub fn camera_bind_group(&self) -> &wgpu::BindGroup {
+        &self.camera_bind_group
+    }
+
+    pub fn clear_color(&self) -> wgpu::Color {
+        self.clear_color
     }
 
     pub fn resize(&mut self, new_size: winit::dpi::PhysicalSize<u32>) {
@@ -239,6 +738,280 @@ impl Renderer {
         // This solves an issue where the app would panic when minimizing on Windows.
         self.surface_config.width = self.resolution.x.max(1);
         self.surface_config.height = self.resolution.y.max(1);
-        self.surface.configure(&self.device, &self.surface_config);
+        match &self.surface {
+            Some(surface) => surface.configure(&self.device, &self.surface_config),
+            None => {
+                self.offscreen = Some(Arc::new(create_offscreen(
+                    &self.device,
+                    &self.surface_config,
+                )))
+            }
+        }
+        self.graph.resize(
+            &self.device,
+            self.surface_config.width,
+            self.surface_config.height,
+        );
+        // The projection depends on the aspect ratio.
+        self.upload_camera();
+    }
+}
+
+/// Layout of the scene pipelines: camera and shadow bind groups.
+pub const SCENE_LAYOUT: &str = "scene";
+/// MSAA sample counts the renderer can use, depending on the adapter.
+pub const MSAA_SAMPLE_COUNTS: [u32; 4] = [1, 2, 4, 8];
+
+/// Pipelines drawing the scene meshes, one per [RenderLayer].
+pub struct ScenePipelines {
+    pub opaque: Arc<wgpu::RenderPipeline>,
+    pub cutout: Arc<wgpu::RenderPipeline>,
+    pub translucent: Arc<wgpu::RenderPipeline>,
+}
+
+impl ScenePipelines {
+    fn new(
+        device: &wgpu::Device,
+        pipelines: &mut PipelineCache,
+        shaders: &mut ShaderManager,
+        sample_count: u32,
+    ) -> Self {
+        let mut get = |material: SceneMaterial| {
+            pipelines.get(device, shaders, &material.pipeline_desc(sample_count))
+        };
+        Self {
+            opaque: get(SceneMaterial::Opaque),
+            cutout: get(SceneMaterial::Cutout),
+            translucent: get(SceneMaterial::Translucent),
+        }
+    }
+}
+
+/// Runs `frame`, turning the panic of wgpu on the loss of the device, or of its memory, into
+/// [RendererError::DeviceLost]. Other panics are resumed.
+///
+/// wgpu doesn't report the errors of [wgpu::Queue::submit], of the writes to the queue, of
+/// [wgpu::Device::poll] and of dropping the device, it panics with them instead. Everything a
+/// frame does with the GPU must run in it for the loss to be recovered from.
+pub fn catch_device_loss<T>(frame: impl FnOnce() -> T) -> Result<T, RendererError> {
+    panic::catch_unwind(AssertUnwindSafe(frame)).map_err(|payload| {
+        let message = payload
+            .downcast_ref::<String>()
+            .map(String::as_str)
+            .or_else(|| payload.downcast_ref::<&str>().copied())
+            .unwrap_or_default();
+        // wgpu panics with "Error in <call>: <cause>".
+        let lost = message.starts_with("Error in ")
+            && [DeviceError::Lost, DeviceError::OutOfMemory]
+                .iter()
+                .any(|cause| message.ends_with(&cause.to_string()));
+        if !lost {
+            panic::resume_unwind(payload);
+        }
+        error!(message, "The graphics device was lost");
+        RendererError::DeviceLost
+    })
+}
+
+/// Whether the error was caused by the loss of the device or running out of memory.
+///
+/// wgpu reports the loss as a validation error caused by a [DeviceError::Lost]. The errors
+/// wrapping it forward their source past it, so the errors of the resources created while
+/// rendering are matched as well.
+fn is_device_lost(error: &wgpu::Error) -> bool {
+    let source = match error {
+        wgpu::Error::OutOfMemory { .. } => return true,
+        wgpu::Error::Validation { source, .. } => source,
+    };
+    let Some(context) = source.downcast_ref::<ContextError>() else {
+        return false;
+    };
+    let cause = context.cause.as_ref();
+    matches!(cause.downcast_ref(), Some(DeviceError::Lost))
+        || matches!(
+            cause.downcast_ref(),
+            Some(CreateBufferError::Device(DeviceError::Lost))
+        )
+        || matches!(
+            cause.downcast_ref(),
+            Some(CreateTextureError::Device(DeviceError::Lost))
+        )
+        || matches!(
+            cause.downcast_ref(),
+            Some(CreateBindGroupError::Device(DeviceError::Lost))
+        )
+        || matches!(
+            cause.downcast_ref(),
+            Some(CreateRenderPipelineError::Device(DeviceError::Lost))
+        )
+}
+
+/// A texture in the surface format that can be copied, the headless frames and the captures.
+fn create_offscreen(device: &wgpu::Device, config: &wgpu::SurfaceConfiguration) -> wgpu::Texture {
+    device.create_texture(&wgpu::TextureDescriptor {
+        label: Some("Offscreen Frame"),
+        size: wgpu::Extent3d {
+            width: config.width,
+            height: config.height,
+            depth_or_array_layers: 1,
+        },
+        mip_level_count: 1,
+        sample_count: 1,
+        dimension: wgpu::TextureDimension::D2,
+        format: config.format,
+        usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::COPY_SRC,
+    })
+}
+
+/// Returns the MSAA sample counts usable by the scene targets.
+fn supported_sample_counts(adapter: &wgpu::Adapter) -> Vec<u32> {
+    // Without adapter specific format features only the WebGPU guarantees can be used.
+    if !adapter
+        .features()
+        .contains(wgpu::Features::TEXTURE_ADAPTER_SPECIFIC_FORMAT_FEATURES)
+    {
+        return vec![1, 4];
+    }
+    let color = adapter.get_texture_format_features(HDR_FORMAT).flags;
+    let depth = adapter
+        .get_texture_format_features(Texture::DEPTH_FORMAT)
+        .flags;
+    let msaa = wgpu::TextureFormatFeatureFlags::MULTISAMPLE;
+    if color.contains(msaa | wgpu::TextureFormatFeatureFlags::MULTISAMPLE_RESOLVE)
+        && depth.contains(msaa)
+    {
+        MSAA_SAMPLE_COUNTS.to_vec()
+    } else {
+        vec![1]
+    }
+}
+
+/// (Re)creates the depth and multisampled color targets of the scene passes.
+fn add_scene_targets(graph: &mut RenderGraph, device: &wgpu::Device, sample_count: u32) {
+    let usage = wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING;
+    graph.add_transient(
+        device,
+        DEPTH,
+        TransientDesc {
+            format: Texture::DEPTH_FORMAT,
+            usage,
+            sample_count,
+        },
+    );
+    if sample_count > 1 {
+        graph.add_transient(
+            device,
+            MULTISAMPLED_HDR,
+            TransientDesc {
+                format: HDR_FORMAT,
+                usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
+                sample_count,
+            },
+        );
+    } else {
+        graph.remove_transient(MULTISAMPLED_HDR);
+    }
+}
+
+/// Blending and depth behaviour of a scene pipeline, one per [RenderLayer].
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+enum SceneMaterial {
+    Opaque,
+    Cutout,
+    Translucent,
+}
+
+impl SceneMaterial {
+    fn pipeline_desc(self, sample_count: u32) -> PipelineDesc {
+        let defines = ShaderDefines::new().define_value("MAX_SHADOW_CASCADES", MAX_SHADOW_CASCADES);
+        let (label, defines, blend, depth_write_enabled) = match self {
+            SceneMaterial::Opaque => ("Render Pipeline", defines, wgpu::BlendState::REPLACE, true),
+            // Foliage is made of two sided quads.
+            SceneMaterial::Cutout => (
+                "Cutout Render Pipeline",
+                defines.define("ALPHA_TEST"),
+                wgpu::BlendState::REPLACE,
+                true,
+            ),
+            // Water and glass are visible from both sides and must not occlude what's behind them.
+            SceneMaterial::Translucent => (
+                "Translucent Render Pipeline",
+                defines,
+                wgpu::BlendState::ALPHA_BLENDING,
+                false,
+            ),
+        };
+        let desc = PipelineDesc::new(label, SCENE_LAYOUT, "shader.wgsl")
+            .defines(defines)
+            .vertex_buffer(Vertex::layout())
+            .fragment("fs_main", HDR_FORMAT)
+            .blend(blend)
+            .depth(DepthState::new(Texture::DEPTH_FORMAT, depth_write_enabled))
+            .sample_count(sample_count);
+        match self {
+            SceneMaterial::Opaque => desc.cull(wgpu::Face::Back),
+            SceneMaterial::Cutout | SceneMaterial::Translucent => desc,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use wgpu_core::device::queue::QueueSubmitError;
+
+    use super::*;
+
+    #[test]
+    fn recovers_from_the_loss_of_the_device() {
+        let size = Vec2::new(64, 64);
+        let mut renderer = match Renderer::headless(size, GraphicsSettings::default()) {
+            Ok(renderer) => renderer,
+            Err(RendererError::AdapterNotFound) => {
+                eprintln!("No graphics adapter, skipping");
+                return;
+            }
+            Err(error) => panic!("{}", error),
+        };
+        let render = |renderer: &mut Renderer| {
+            let frame = renderer.acquire_frame().unwrap().unwrap();
+            let mut encoder = renderer
+                .device
+                .create_command_encoder(&wgpu::CommandEncoderDescriptor::default());
+            renderer.start_frame(&mut encoder, &frame, None);
+            renderer.queue.submit(std::iter::once(encoder.finish()));
+            renderer.finish_frame();
+        };
+        renderer.camera.eye = Vec3::new(1.0, 2.0, 3.0);
+        renderer.upload_camera();
+
+        // What wgpu panics with when the device is lost while submitting.
+        let lost = catch_device_loss(|| {
+            render(&mut renderer);
+            panic!(
+                "Error in Queue::submit: {}",
+                QueueSubmitError::Queue(DeviceError::Lost)
+            );
+        });
+        assert!(matches!(lost, Err(RendererError::DeviceLost)));
+
+        // The other errors wgpu panics with aren't mistaken for it.
+        let buffer = renderer.device.create_buffer(&wgpu::BufferDescriptor {
+            label: None,
+            size: 4,
+            usage: BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        buffer.destroy();
+        let misuse = panic::catch_unwind(AssertUnwindSafe(|| {
+            catch_device_loss(|| renderer.queue.write_buffer(&buffer, 0, &[0; 4]))
+        }));
+        assert!(misuse.is_err());
+        drop(buffer);
+
+        let state = renderer.into_state();
+        let mut renderer = Renderer::headless(size, state.graphics.clone()).unwrap();
+        renderer.restore(state);
+        assert_eq!(renderer.camera.eye, Vec3::new(1.0, 2.0, 3.0));
+        assert!(catch_device_loss(|| render(&mut renderer)).is_ok());
     }
 }
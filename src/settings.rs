@@ -0,0 +1,527 @@
+//! User settings saved in the platform config directory, e.g.
+//! `~/.config/rusty_sandbox/settings.toml` on Linux.
+//!
+//! The file is versioned, older versions are migrated when loaded, see [MIGRATIONS].
+
+use std::{
+    fmt, fs, io,
+    ops::RangeBounds,
+    path::{Path, PathBuf},
+};
+
+use serde::{Deserialize, Serialize};
+use tracing::{info, warn};
+use winit::event::VirtualKeyCode;
+
+use crate::{
+    camera::{Movement, DEFAULT_SPEED, DEFAULT_VERTICAL_FOV},
+    error::Error,
+    gamepad::GamepadSettings,
+    graphics::GraphicsSettings,
+    post::PostSettings,
+    renderer::MSAA_SAMPLE_COUNTS,
+    shadow::{ShadowSettings, MAX_SHADOW_CASCADES},
+    window::{self, FullscreenMode},
+};
+
+/// Version written to the file.
+pub const VERSION: u32 = 2;
+pub const FILE_NAME: &str = "settings.toml";
+/// Graphics config of the working directory, read as the version 1 of the settings when
+/// the settings file doesn't exist yet.
+pub const LEGACY_GRAPHICS_FILE: &str = "graphics.toml";
+
+/// Upgrades the settings from the version at the same index plus one to the next.
+const MIGRATIONS: [fn(&mut toml::Table); 1] = [move_graphics_to_table];
+
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Settings {
+    pub version: u32,
+    pub window: WindowSettings,
+    pub camera: CameraSettings,
+    pub keys: KeyBindings,
+    pub gamepad: GamepadSettings,
+    pub graphics: GraphicsSettings,
+    pub renderer: RendererSettings,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            version: VERSION,
+            window: WindowSettings::default(),
+            camera: CameraSettings::default(),
+            keys: KeyBindings::default(),
+            gamepad: GamepadSettings::default(),
+            graphics: GraphicsSettings::default(),
+            renderer: RendererSettings::default(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct WindowSettings {
+    /// Inner size, the platform default when `None`.
+    pub size: Option<[u32; 2]>,
+    /// Outer position, chosen by the platform when `None`.
+    pub position: Option<[i32; 2]>,
+    pub fullscreen_mode: FullscreenMode,
+    /// Shows the frame rate and the frame time in the title bar.
+    pub title_stats: bool,
+}
+
+impl Default for WindowSettings {
+    fn default() -> Self {
+        Self {
+            size: None,
+            position: None,
+            fullscreen_mode: FullscreenMode::Borderless,
+            title_stats: true,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CameraSettings {
+    /// Vertical field of view in degrees.
+    pub fov: f32,
+    pub speed: f32,
+}
+
+impl Default for CameraSettings {
+    fn default() -> Self {
+        Self {
+            fov: DEFAULT_VERTICAL_FOV,
+            speed: DEFAULT_SPEED,
+        }
+    }
+}
+
+/// Keys moving the camera, any of the keys of a movement triggers it.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct KeyBindings {
+    pub forward: Vec<VirtualKeyCode>,
+    pub backward: Vec<VirtualKeyCode>,
+    pub left: Vec<VirtualKeyCode>,
+    pub right: Vec<VirtualKeyCode>,
+}
+
+impl Default for KeyBindings {
+    fn default() -> Self {
+        Self {
+            forward: vec![VirtualKeyCode::W, VirtualKeyCode::Up],
+            backward: vec![VirtualKeyCode::S, VirtualKeyCode::Down],
+            left: vec![VirtualKeyCode::A, VirtualKeyCode::Left],
+            right: vec![VirtualKeyCode::D, VirtualKeyCode::Right],
+        }
+    }
+}
+
+impl KeyBindings {
+    pub fn movement(&self, key: VirtualKeyCode) -> Option<Movement> {
+        [
+            (&self.forward, Movement::Forward),
+            (&self.backward, Movement::Backward),
+            (&self.left, Movement::Left),
+            (&self.right, Movement::Right),
+        ]
+        .into_iter()
+        .find(|(keys, _)| keys.contains(&key))
+        .map(|(_, movement)| movement)
+    }
+}
+
+/// The settings of the renderer panel.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RendererSettings {
+    /// Linear RGB color of the sky.
+    pub clear_color: [f64; 3],
+    /// MSAA samples, ignored if the adapter doesn't support them.
+    pub sample_count: u32,
+    pub shadow: ShadowSettings,
+    pub post: PostSettings,
+}
+
+impl Default for RendererSettings {
+    fn default() -> Self {
+        Self {
+            clear_color: [0.2, 0.6, 0.5],
+            sample_count: 1,
+            shadow: ShadowSettings::default(),
+            post: PostSettings::default(),
+        }
+    }
+}
+
+impl Settings {
+    /// The settings file in the platform config directory, if there is one.
+    pub fn default_path() -> Option<PathBuf> {
+        dirs::config_dir().map(|dir| dir.join(env!("CARGO_PKG_NAME")).join(FILE_NAME))
+    }
+
+    /// Loads, migrates and repairs the settings. Returns the [LEGACY_GRAPHICS_FILE] settings,
+    /// or the defaults, if the file doesn't exist.
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let source = match fs::read_to_string(path) {
+            Ok(source) => source,
+            Err(error) if error.kind() == io::ErrorKind::NotFound => {
+                match fs::read_to_string(LEGACY_GRAPHICS_FILE) {
+                    Ok(source) => {
+                        info!(
+                            file = LEGACY_GRAPHICS_FILE,
+                            "Importing the graphics settings"
+                        );
+                        source
+                    }
+                    Err(error) if error.kind() == io::ErrorKind::NotFound => {
+                        return Ok(Self::default())
+                    }
+                    Err(error) => return Err(error.to_string()),
+                }
+            }
+            Err(error) => return Err(error.to_string()),
+        };
+        Self::parse(&source)
+    }
+
+    fn parse(source: &str) -> Result<Self, String> {
+        let mut table = toml::from_str::<toml::Table>(source).map_err(|error| error.to_string())?;
+        migrate(&mut table)?;
+        let mut settings: Self = toml::Value::Table(table)
+            .try_into()
+            .map_err(|error| error.to_string())?;
+        settings.repair();
+        Ok(settings)
+    }
+
+    /// Replaces the values no device can use, e.g. edited by hand, with a warning.
+    /// The renderer further limits them to what the device supports.
+    fn repair(&mut self) {
+        let size = self.window.size.filter(|size| {
+            size.iter()
+                .all(|side| (1..=window::MAX_SIZE).contains(side))
+        });
+        repair("window.size", &mut self.window.size, size);
+
+        let camera = &mut self.camera;
+        let fov = valid_or(camera.fov, f32::MIN_POSITIVE..180.0, DEFAULT_VERTICAL_FOV);
+        repair("camera.fov", &mut camera.fov, fov);
+        let speed = valid_or(camera.speed, f32::MIN_POSITIVE..=f32::MAX, DEFAULT_SPEED);
+        repair("camera.speed", &mut camera.speed, speed);
+
+        let gamepad = &mut self.gamepad;
+        let default = GamepadSettings::default();
+        let dead_zone = valid_or(gamepad.dead_zone, 0.0..1.0, default.dead_zone);
+        repair("gamepad.dead_zone", &mut gamepad.dead_zone, dead_zone);
+        let curve = valid_or(gamepad.curve, f32::MIN_POSITIVE..=f32::MAX, default.curve);
+        repair("gamepad.curve", &mut gamepad.curve, curve);
+        let move_speed = valid_or(gamepad.move_speed, 0.0..=f32::MAX, default.move_speed);
+        repair("gamepad.move_speed", &mut gamepad.move_speed, move_speed);
+        let look_sensitivity = valid_or(
+            gamepad.look_sensitivity,
+            0.0..=f32::MAX,
+            default.look_sensitivity,
+        );
+        repair(
+            "gamepad.look_sensitivity",
+            &mut gamepad.look_sensitivity,
+            look_sensitivity,
+        );
+        let trigger_threshold = valid_or(
+            gamepad.trigger_threshold,
+            0.0..1.0,
+            default.trigger_threshold,
+        );
+        repair(
+            "gamepad.trigger_threshold",
+            &mut gamepad.trigger_threshold,
+            trigger_threshold,
+        );
+
+        let renderer = &mut self.renderer;
+        let default = RendererSettings::default();
+        for (channel, default) in renderer.clear_color.iter_mut().zip(default.clear_color) {
+            let valid = valid_or(*channel, 0.0..=1.0, default);
+            repair("renderer.clear_color", channel, valid);
+        }
+        let sample_count = match renderer.sample_count {
+            count if MSAA_SAMPLE_COUNTS.contains(&count) => count,
+            _ => 1,
+        };
+        repair(
+            "renderer.sample_count",
+            &mut renderer.sample_count,
+            sample_count,
+        );
+
+        let shadow = &mut renderer.shadow;
+        // The devices are requested with the default limits.
+        let resolution = match shadow.resolution {
+            0 => ShadowSettings::default().resolution,
+            resolution => resolution.min(wgpu::Limits::default().max_texture_dimension_2d),
+        };
+        repair(
+            "renderer.shadow.resolution",
+            &mut shadow.resolution,
+            resolution,
+        );
+        let cascade_count = shadow.cascade_count.clamp(1, MAX_SHADOW_CASCADES as u32);
+        repair(
+            "renderer.shadow.cascade_count",
+            &mut shadow.cascade_count,
+            cascade_count,
+        );
+        let bias = valid_or(shadow.bias, 0.0..=1.0, default.shadow.bias);
+        repair("renderer.shadow.bias", &mut shadow.bias, bias);
+
+        let post = &mut renderer.post;
+        let positive = f32::MIN_POSITIVE..=f32::MAX;
+        let exposure = valid_or(post.exposure, positive.clone(), default.post.exposure);
+        repair("renderer.post.exposure", &mut post.exposure, exposure);
+        let gamma = valid_or(post.gamma, positive, default.post.gamma);
+        repair("renderer.post.gamma", &mut post.gamma, gamma);
+        let bloom_threshold = valid_or(
+            post.bloom_threshold,
+            0.0..=f32::MAX,
+            default.post.bloom_threshold,
+        );
+        repair(
+            "renderer.post.bloom_threshold",
+            &mut post.bloom_threshold,
+            bloom_threshold,
+        );
+        let bloom_intensity = valid_or(
+            post.bloom_intensity,
+            0.0..=f32::MAX,
+            default.post.bloom_intensity,
+        );
+        repair(
+            "renderer.post.bloom_intensity",
+            &mut post.bloom_intensity,
+            bloom_intensity,
+        );
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), Error> {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let source = toml::to_string_pretty(self).expect("The settings are valid TOML");
+        fs::write(path, source)?;
+        Ok(())
+    }
+}
+
+/// Upgrades `table` to [VERSION], a missing version is the version 1.
+fn migrate(table: &mut toml::Table) -> Result<(), String> {
+    let version = match table.get("version") {
+        None => 1,
+        Some(toml::Value::Integer(version)) if (1..=VERSION as i64).contains(version) => {
+            *version as u32
+        }
+        Some(version) => return Err(format!("unsupported settings version {}", version)),
+    };
+    for (from, migration) in MIGRATIONS.iter().enumerate().skip(version as usize - 1) {
+        info!(from = from + 1, to = from + 2, "Migrating the settings");
+        migration(table);
+    }
+    table.insert("version".to_string(), toml::Value::Integer(VERSION as i64));
+    Ok(())
+}
+
+fn repair<T: PartialEq + fmt::Debug>(setting: &str, value: &mut T, repaired: T) {
+    if *value != repaired {
+        warn!(
+            setting,
+            ?value,
+            ?repaired,
+            "Invalid setting, using a valid value instead"
+        );
+        *value = repaired;
+    }
+}
+
+/// `value`, or `default` if it's out of `range` or NaN.
+fn valid_or<T: PartialOrd>(value: T, range: impl RangeBounds<T>, default: T) -> T {
+    if range.contains(&value) {
+        value
+    } else {
+        default
+    }
+}
+
+/// Version 1 only had the graphics settings, at the root.
+fn move_graphics_to_table(table: &mut toml::Table) {
+    let graphics = std::mem::take(table)
+        .into_iter()
+        .filter(|(key, _)| key != "version")
+        .collect();
+    table.insert("graphics".to_string(), toml::Value::Table(graphics));
+}
+
+/// The settings file and the settings in effect when the sandbox started.
+pub struct SettingsFile {
+    /// Where the settings are saved, `None` if they can't be.
+    pub path: Option<PathBuf>,
+    /// The contents of the file.
+    pub saved: Settings,
+    /// The settings with the command-line overrides applied.
+    pub launch: Settings,
+}
+
+impl SettingsFile {
+    /// Writes back the sections of `current` changed since the launch, so that the
+    /// command-line overrides are only saved if they were changed while running.
+    pub fn save(&mut self, current: &Settings) -> Result<(), Error> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        fn update<T: Clone + PartialEq>(saved: &mut T, launch: &T, current: &T) {
+            if current != launch {
+                *saved = current.clone();
+            }
+        }
+        update(&mut self.saved.window, &self.launch.window, &current.window);
+        update(&mut self.saved.camera, &self.launch.camera, &current.camera);
+        update(&mut self.saved.keys, &self.launch.keys, &current.keys);
+        update(
+            &mut self.saved.gamepad,
+            &self.launch.gamepad,
+            &current.gamepad,
+        );
+        update(
+            &mut self.saved.graphics,
+            &self.launch.graphics,
+            &current.graphics,
+        );
+        update(
+            &mut self.saved.renderer,
+            &self.launch.renderer,
+            &current.renderer,
+        );
+        self.saved.version = VERSION;
+        self.saved.save(path)?;
+        info!(?path, "Saved the settings");
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::graphics::Backend;
+
+    use super::*;
+
+    #[test]
+    fn migrates_the_legacy_graphics_file() {
+        let settings = Settings::parse("backend = \"gl\"\nframe_cap = 60").unwrap();
+        assert_eq!(settings.version, VERSION);
+        assert_eq!(settings.graphics.backend, Backend::Gl);
+        assert_eq!(settings.graphics.frame_cap, Some(60));
+        assert_eq!(settings.renderer, RendererSettings::default());
+
+        let mut table = toml::toml! { version = 1 };
+        migrate(&mut table).unwrap();
+        assert_eq!(table, toml::toml! { version = 2 graphics = {} });
+    }
+
+    #[test]
+    fn rejects_unknown_versions() {
+        let error = Settings::parse(&format!("version = {}", VERSION + 1)).unwrap_err();
+        assert_eq!(
+            error,
+            format!("unsupported settings version {}", VERSION + 1)
+        );
+        assert!(Settings::parse("version = 0").is_err());
+    }
+
+    #[test]
+    fn repairs_invalid_values() {
+        let settings = Settings::parse(
+            r#"
+            version = 2
+            [camera]
+            fov = 0.0
+            [renderer]
+            sample_count = 3
+            [renderer.shadow]
+            resolution = 0
+            cascade_count = 9
+            "#,
+        )
+        .unwrap();
+        assert_eq!(settings.camera.fov, DEFAULT_VERTICAL_FOV);
+        assert_eq!(settings.renderer.sample_count, 1);
+        assert_eq!(settings.renderer.shadow.resolution, 2048);
+        assert_eq!(settings.renderer.shadow.cascade_count, 4);
+
+        let settings = Settings::parse(
+            "version = 2\n[renderer.shadow]\nresolution = 100000\ncascade_count = 0",
+        )
+        .unwrap();
+        let max = wgpu::Limits::default().max_texture_dimension_2d;
+        assert_eq!(settings.renderer.shadow.resolution, max);
+        assert_eq!(settings.renderer.shadow.cascade_count, 1);
+    }
+
+    #[test]
+    fn repairs_every_invalid_number() {
+        let settings = Settings::parse(
+            r#"
+            version = 2
+            [window]
+            size = [0, 720]
+            [camera]
+            fov = nan
+            speed = -1.0
+            [gamepad]
+            dead_zone = 1.5
+            curve = 0.0
+            move_speed = inf
+            look_sensitivity = -600.0
+            trigger_threshold = nan
+            [renderer]
+            clear_color = [nan, 0.1, 2.0]
+            [renderer.shadow]
+            bias = -0.1
+            [renderer.post]
+            exposure = 0.0
+            gamma = nan
+            bloom_threshold = -1.0
+            bloom_intensity = inf
+            "#,
+        )
+        .unwrap();
+        let defaults = Settings::default();
+        assert_eq!(settings.window.size, None);
+        assert_eq!(settings.camera, defaults.camera);
+        assert_eq!(settings.gamepad, defaults.gamepad);
+        assert_eq!(settings.renderer.clear_color, [0.2, 0.1, 0.5]);
+        assert_eq!(settings.renderer.shadow, defaults.renderer.shadow);
+        assert_eq!(settings.renderer.post, defaults.renderer.post);
+
+        let valid = Settings {
+            window: WindowSettings {
+                size: Some([1280, 720]),
+                ..WindowSettings::default()
+            },
+            camera: CameraSettings {
+                fov: 90.0,
+                speed: 0.5,
+            },
+            gamepad: GamepadSettings {
+                dead_zone: 0.0,
+                curve: 1.0,
+                ..GamepadSettings::default()
+            },
+            ..Settings::default()
+        };
+        let source = toml::to_string(&valid).unwrap();
+        assert_eq!(Settings::parse(&source).unwrap(), valid);
+    }
+}
@@ -0,0 +1,221 @@
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    path::{Path, PathBuf},
+    sync::mpsc::{self, Receiver},
+};
+
+use notify::{RecursiveMode, Watcher};
+use tracing::{info, warn};
+
+use crate::{
+    error::{self, Error},
+    shader_preprocessor::{self, ShaderDefines},
+};
+
+/// Directory the shaders are loaded from at runtime.
+pub const SHADER_DIR: &str = "assets/shaders";
+
+/// Shaders compiled into the binary, used when a file is missing or fails to compile on startup.
+const EMBEDDED_SHADERS: [(&str, &str); 5] = [
+    ("camera.wgsl", include_str!("../assets/shaders/camera.wgsl")),
+    ("debug.wgsl", include_str!("../assets/shaders/debug.wgsl")),
+    ("shader.wgsl", include_str!("../assets/shaders/shader.wgsl")),
+    ("shadow.wgsl", include_str!("../assets/shaders/shadow.wgsl")),
+    ("post.wgsl", include_str!("../assets/shaders/post.wgsl")),
+];
+
+/// Loads WGSL shaders from [SHADER_DIR] and watches them for changes.
+///
+/// Shaders are preprocessed, see [shader_preprocessor::preprocess], and validated with naga
+/// before being handed to wgpu. Errors are kept so they can be displayed instead of panicking.
+pub struct ShaderManager {
+    root: PathBuf,
+    /// Kept alive to keep receiving file events.
+    _watcher: Option<notify::RecommendedWatcher>,
+    events: Option<Receiver<notify::Result<notify::Event>>>,
+    errors: BTreeMap<String, Error>,
+    /// Files included by each loaded shader, used to reload it when one of them changes.
+    dependencies: HashMap<String, HashSet<String>>,
+}
+
+impl ShaderManager {
+    pub fn new() -> Self {
+        let root = PathBuf::from(SHADER_DIR);
+        let (sender, events) = mpsc::channel();
+        let watcher = notify::recommended_watcher(sender).and_then(|mut watcher| {
+            watcher.watch(&root, RecursiveMode::NonRecursive)?;
+            Ok(watcher)
+        });
+        match watcher {
+            Ok(watcher) => {
+                info!(?root, "Watching shaders for changes");
+                Self {
+                    root,
+                    _watcher: Some(watcher),
+                    events: Some(events),
+                    errors: BTreeMap::new(),
+                    dependencies: HashMap::new(),
+                }
+            }
+            Err(error) => {
+                warn!(?root, %error, "Shader hot-reloading is disabled");
+                Self {
+                    root,
+                    _watcher: None,
+                    events: None,
+                    errors: BTreeMap::new(),
+                    dependencies: HashMap::new(),
+                }
+            }
+        }
+    }
+
+    /// Loads a permutation of a shader, falling back to the embedded sources if it fails.
+    pub fn load(
+        &mut self,
+        device: &wgpu::Device,
+        name: &str,
+        defines: &ShaderDefines,
+    ) -> wgpu::ShaderModule {
+        match self.reload(device, name, defines) {
+            Some(module) => module,
+            None => self.load_embedded(device, name, defines),
+        }
+    }
+
+    /// Loads a permutation of a shader from the sources compiled into the binary.
+    pub fn load_embedded(
+        &self,
+        device: &wgpu::Device,
+        name: &str,
+        defines: &ShaderDefines,
+    ) -> wgpu::ShaderModule {
+        warn!(name, %defines, "Using the embedded shader");
+        let shader = shader_preprocessor::preprocess(name, defines, &read_embedded)
+            .unwrap_or_else(|error| panic!("Invalid embedded shader: {}", error));
+        create_module(device, &error_key(name, defines), &shader.source)
+    }
+
+    /// Loads, preprocesses and validates a permutation of a shader from disk.
+    /// Returns `None` and records the error if it can't be compiled.
+    pub fn reload(
+        &mut self,
+        device: &wgpu::Device,
+        name: &str,
+        defines: &ShaderDefines,
+    ) -> Option<wgpu::ShaderModule> {
+        let key = error_key(name, defines);
+        let read = |file: &str| {
+            let path = self.root.join(file);
+            std::fs::read_to_string(&path)
+                .map_err(|source| error::report(&Error::Asset { path, source }))
+        };
+        let result = shader_preprocessor::preprocess(name, defines, &read).and_then(|shader| {
+            validate(&shader.source)?;
+            Ok(shader)
+        });
+        match result {
+            Ok(shader) => {
+                self.errors.remove(&key);
+                self.dependencies
+                    .entry(name.to_string())
+                    .or_default()
+                    .extend(shader.files);
+                Some(create_module(device, &key, &shader.source))
+            }
+            Err(error) => {
+                warn!(name, %defines, %error, "Failed to load shader");
+                self.insert_error(key, error);
+                None
+            }
+        }
+    }
+
+    /// Records an error that happened after a permutation was validated,
+    /// e.g. on pipeline creation. It is cleared when the permutation reloads.
+    pub fn set_error(&mut self, name: &str, defines: &ShaderDefines, message: String) {
+        self.insert_error(error_key(name, defines), message);
+    }
+
+    fn insert_error(&mut self, key: String, message: String) {
+        let error = Error::Shader {
+            name: key.clone(),
+            message,
+        };
+        self.errors.insert(key, error);
+    }
+
+    /// Returns the names of the loaded shaders modified since the last call,
+    /// directly or through one of their includes.
+    pub fn changed(&mut self) -> HashSet<String> {
+        let mut files = HashSet::new();
+        let Some(events) = &self.events else {
+            return files;
+        };
+        for event in events.try_iter().flatten() {
+            if !(event.kind.is_modify() || event.kind.is_create()) {
+                continue;
+            }
+            files.extend(
+                event
+                    .paths
+                    .iter()
+                    .filter(|path| path.extension().is_some_and(|ext| ext == "wgsl"))
+                    .filter_map(|path| file_name(path)),
+            );
+        }
+        self.dependencies
+            .iter()
+            .filter(|(_, dependencies)| !dependencies.is_disjoint(&files))
+            .map(|(name, _)| name.clone())
+            .collect()
+    }
+
+    /// Compilation errors of the shaders that failed to (re)load, by shader name and permutation.
+    pub fn errors(&self) -> &BTreeMap<String, Error> {
+        &self.errors
+    }
+}
+
+/// Identifies a shader permutation in the errors and in wgpu labels.
+fn error_key(name: &str, defines: &ShaderDefines) -> String {
+    if *defines == ShaderDefines::new() {
+        name.to_string()
+    } else {
+        format!("{} {}", name, defines)
+    }
+}
+
+fn read_embedded(name: &str) -> Result<String, String> {
+    EMBEDDED_SHADERS
+        .iter()
+        .find(|(embedded, _)| *embedded == name)
+        .map(|(_, source)| source.to_string())
+        .ok_or_else(|| format!("No embedded shader named `{}`", name))
+}
+
+fn file_name(path: &Path) -> Option<String> {
+    path.file_name()
+        .and_then(|name| name.to_str())
+        .map(str::to_string)
+}
+
+/// Parses and validates WGSL with naga, the same way wgpu would but without panicking.
+fn validate(source: &str) -> Result<(), String> {
+    let module =
+        naga::front::wgsl::parse_str(source).map_err(|error| error.emit_to_string(source))?;
+    naga::valid::Validator::new(
+        naga::valid::ValidationFlags::all(),
+        naga::valid::Capabilities::empty(),
+    )
+    .validate(&module)
+    .map_err(|error| error.to_string())?;
+    Ok(())
+}
+
+fn create_module(device: &wgpu::Device, name: &str, source: &str) -> wgpu::ShaderModule {
+    device.create_shader_module(wgpu::ShaderModuleDescriptor {
+        label: Some(name),
+        source: wgpu::ShaderSource::Wgsl(source.into()),
+    })
+}
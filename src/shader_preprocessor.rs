@@ -0,0 +1,293 @@
+use std::{
+    collections::{BTreeMap, HashSet},
+    fmt,
+};
+
+/// Names defined before preprocessing a shader, selecting one of its permutations.
+///
+/// Defines with a value are also substituted in the source,
+/// e.g. `MAX_SHADOW_CASCADES` can be used as an array size.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct ShaderDefines(BTreeMap<String, String>);
+
+impl ShaderDefines {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Defines a flag tested with `#ifdef`.
+    pub fn define(mut self, name: &str) -> Self {
+        self.0.insert(name.to_string(), String::new());
+        self
+    }
+
+    /// Defines a name replaced by `value` in the source.
+    pub fn define_value(mut self, name: &str, value: impl ToString) -> Self {
+        self.0.insert(name.to_string(), value.to_string());
+        self
+    }
+}
+
+impl fmt::Display for ShaderDefines {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let defines = self
+            .0
+            .iter()
+            .map(|(name, value)| {
+                if value.is_empty() {
+                    name.clone()
+                } else {
+                    format!("{}={}", name, value)
+                }
+            })
+            .collect::<Vec<_>>();
+        write!(f, "[{}]", defines.join(", "))
+    }
+}
+
+/// A shader ready to be handed to naga, with the files it was built from.
+pub struct Preprocessed {
+    pub source: String,
+    /// The shader itself and every file it included.
+    pub files: HashSet<String>,
+}
+
+/// Resolves the directives of the shader `name`:
+///
+/// - `#include "file.wgsl"` pastes a file once, later includes of the same file are ignored.
+/// - `#define NAME` and `#define NAME value`, values are substituted in the following lines.
+/// - `#ifdef NAME`, `#ifndef NAME`, `#else` and `#endif`.
+///
+/// Files are read through `read`, so they can come from disk or be embedded.
+pub fn preprocess(
+    name: &str,
+    defines: &ShaderDefines,
+    read: &dyn Fn(&str) -> Result<String, String>,
+) -> Result<Preprocessed, String> {
+    let mut preprocessor = Preprocessor {
+        defines: defines.0.clone(),
+        files: HashSet::new(),
+        read,
+        output: String::new(),
+    };
+    preprocessor.file(name)?;
+    Ok(Preprocessed {
+        source: preprocessor.output,
+        files: preprocessor.files,
+    })
+}
+
+struct Preprocessor<'a> {
+    defines: BTreeMap<String, String>,
+    files: HashSet<String>,
+    read: &'a dyn Fn(&str) -> Result<String, String>,
+    output: String,
+}
+
+/// State of an `#ifdef` block.
+struct Condition {
+    /// Whether the enclosing block is emitted.
+    parent_active: bool,
+    /// Whether the current branch is emitted.
+    active: bool,
+    seen_else: bool,
+}
+
+impl<'a> Preprocessor<'a> {
+    fn file(&mut self, name: &str) -> Result<(), String> {
+        if !self.files.insert(name.to_string()) {
+            return Ok(());
+        }
+        let source = (self.read)(name)?;
+        let mut conditions: Vec<Condition> = Vec::new();
+        for (index, line) in source.lines().enumerate() {
+            let error = |message: &str| format!("{}:{}: {}", name, index + 1, message);
+            let active = conditions.last().is_none_or(|condition| condition.active);
+            let Some(directive) = line.trim_start().strip_prefix('#') else {
+                if active {
+                    self.output.push_str(&self.substitute(line));
+                    self.output.push('\n');
+                }
+                continue;
+            };
+            let mut words = directive.split_whitespace();
+            match (words.next(), words.next()) {
+                (Some(kind @ ("ifdef" | "ifndef")), Some(define)) => {
+                    let defined = self.defines.contains_key(define);
+                    let expected = kind == "ifdef";
+                    conditions.push(Condition {
+                        parent_active: active,
+                        active: active && defined == expected,
+                        seen_else: false,
+                    });
+                }
+                (Some("else"), None) => match conditions.last_mut() {
+                    Some(condition) if !condition.seen_else => {
+                        condition.seen_else = true;
+                        condition.active = condition.parent_active && !condition.active;
+                    }
+                    _ => return Err(error("`#else` without `#ifdef`")),
+                },
+                (Some("endif"), None) => {
+                    if conditions.pop().is_none() {
+                        return Err(error("`#endif` without `#ifdef`"));
+                    }
+                }
+                _ if !active => {}
+                (Some("define"), Some(define)) => {
+                    let value = words.collect::<Vec<_>>().join(" ");
+                    self.defines.insert(define.to_string(), value);
+                }
+                (Some("include"), Some(path)) => {
+                    let path = path
+                        .strip_prefix('"')
+                        .and_then(|path| path.strip_suffix('"'))
+                        .ok_or_else(|| error("expected `#include \"file.wgsl\"`"))?;
+                    self.file(path).map_err(|include| error(&include))?;
+                }
+                _ => return Err(error(&format!("unknown directive `#{}`", directive.trim()))),
+            }
+        }
+        if !conditions.is_empty() {
+            return Err(format!("{}: missing `#endif`", name));
+        }
+        Ok(())
+    }
+
+    /// Replaces the identifiers of the line that are defined with a value.
+    fn substitute(&self, line: &str) -> String {
+        let mut output = String::with_capacity(line.len());
+        let mut identifier = String::new();
+        for c in line.chars().chain(std::iter::once('\n')) {
+            if c.is_ascii_alphanumeric() || c == '_' {
+                identifier.push(c);
+                continue;
+            }
+            match self.defines.get(&identifier) {
+                Some(value) if !value.is_empty() => output.push_str(value),
+                _ => output.push_str(&identifier),
+            }
+            identifier.clear();
+            if c != '\n' {
+                output.push(c);
+            }
+        }
+        output
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+
+    /// Preprocesses `main.wgsl` among `files`.
+    fn run(files: &[(&str, &str)], defines: &ShaderDefines) -> Result<Preprocessed, String> {
+        let files = files
+            .iter()
+            .map(|&(name, source)| (name.to_string(), source.to_string()))
+            .collect::<HashMap<_, _>>();
+        let read = |name: &str| {
+            files
+                .get(name)
+                .cloned()
+                .ok_or_else(|| format!("{} not found", name))
+        };
+        preprocess("main.wgsl", defines, &read)
+    }
+
+    fn source(main: &str, defines: &ShaderDefines) -> String {
+        run(&[("main.wgsl", main)], defines).unwrap().source
+    }
+
+    #[test]
+    fn includes_every_file_once() {
+        let preprocessed = run(
+            &[
+                (
+                    "main.wgsl",
+                    "#include \"a.wgsl\"\n#include \"b.wgsl\"\nmain",
+                ),
+                ("a.wgsl", "#include \"b.wgsl\"\na"),
+                // Includes back the file including it.
+                ("b.wgsl", "#include \"a.wgsl\"\nb"),
+            ],
+            &ShaderDefines::new(),
+        )
+        .unwrap();
+        assert_eq!(preprocessed.source, "b\na\nmain\n");
+        let mut files = preprocessed.files.into_iter().collect::<Vec<_>>();
+        files.sort();
+        assert_eq!(files, ["a.wgsl", "b.wgsl", "main.wgsl"]);
+
+        let missing = run(
+            &[("main.wgsl", "#include \"c.wgsl\"")],
+            &ShaderDefines::new(),
+        );
+        assert_eq!(missing.err().unwrap(), "main.wgsl:1: c.wgsl not found");
+    }
+
+    #[test]
+    fn substitutes_the_defined_values() {
+        let defines = ShaderDefines::new().define_value("COUNT", 4);
+        let main = "#define SIZE 8\narray<f32, COUNT> COUNTER SIZE;";
+        assert_eq!(source(main, &defines), "array<f32, 4> COUNTER 8;\n");
+    }
+
+    #[test]
+    fn selects_the_nested_branches() {
+        let main = "\
+#ifdef A
+a
+#ifndef B
+not b
+#else
+b
+#endif
+#else
+not a
+#ifdef B
+b without a
+#endif
+#endif
+#ifndef A
+still not a
+#endif";
+        let defines = ShaderDefines::new().define("A");
+        assert_eq!(source(main, &defines), "a\nnot b\n");
+        assert_eq!(source(main, &defines.define("B")), "a\nb\n");
+        assert_eq!(source(main, &ShaderDefines::new()), "not a\nstill not a\n");
+        assert_eq!(
+            source(main, &ShaderDefines::new().define("B")),
+            "not a\nb without a\nstill not a\n"
+        );
+        // Defines made in an inactive branch are ignored.
+        let main = "#ifdef A\n#define B\n#endif\n#ifdef B\nb\n#endif";
+        assert_eq!(source(main, &ShaderDefines::new()), "");
+    }
+
+    #[test]
+    fn rejects_invalid_directives() {
+        let error = |main: &str| {
+            run(&[("main.wgsl", main)], &ShaderDefines::new().define("A"))
+                .err()
+                .unwrap()
+        };
+        assert_eq!(error("#ifdef A\na"), "main.wgsl: missing `#endif`");
+        assert_eq!(error("#else"), "main.wgsl:1: `#else` without `#ifdef`");
+        assert_eq!(
+            error("#ifdef A\n#else\n#else\n#endif"),
+            "main.wgsl:3: `#else` without `#ifdef`"
+        );
+        assert_eq!(error("#endif"), "main.wgsl:1: `#endif` without `#ifdef`");
+        assert_eq!(
+            error("#ifdefined A\n#endif"),
+            "main.wgsl:1: unknown directive `#ifdefined A`"
+        );
+        assert_eq!(
+            error("#include c.wgsl"),
+            "main.wgsl:1: expected `#include \"file.wgsl\"`"
+        );
+    }
+}
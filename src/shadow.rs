@@ -0,0 +1,515 @@
+use std::sync::Arc;
+
+use serde::{Deserialize, Serialize};
+use vek::{FrustumPlanes, Mat4, Rgba, Vec3};
+use wgpu::BufferUsages;
+
+use crate::{
+    buffer::Buffer,
+    camera::{Camera, FAR_PLANE, NEAR_PLANE},
+    debug_draw,
+    mesh::{Mesh, RenderLayer},
+    pipeline::{DepthState, PipelineCache, PipelineDesc},
+    shader_manager::ShaderManager,
+    texture::Texture,
+    vertex::Vertex,
+};
+
+/// Upper bound of cascades, `shader.wgsl` receives it as the `MAX_SHADOW_CASCADES` define.
+pub const MAX_SHADOW_CASCADES: usize = 4;
+/// Shadow map resolutions selectable from the settings panel.
+pub const SHADOW_RESOLUTIONS: [u32; 4] = [512, 1024, 2048, 4096];
+/// Pipeline layout of the depth pass, a single cascade bind group.
+const SHADOW_LAYOUT: &str = "shadow";
+/// Blend factor between logarithmic and uniform cascade splits.
+const CASCADE_SPLIT_LAMBDA: f32 = 0.75;
+/// Colors of the cascade bounds drawn by [ShadowMap::show_cascades].
+const CASCADE_COLORS: [Rgba<f32>; MAX_SHADOW_CASCADES] = [
+    Rgba::new(1.0, 0.0, 0.0, 1.0),
+    Rgba::new(0.0, 1.0, 0.0, 1.0),
+    Rgba::new(0.0, 0.0, 1.0, 1.0),
+    Rgba::new(1.0, 0.0, 1.0, 1.0),
+];
+
+/// User tweakable parameters of the sun shadows.
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize, Serialize)]
+#[serde(default)]
+pub struct ShadowSettings {
+    /// Width and height in texels of each cascade.
+    pub resolution: u32,
+    /// Depth bias applied when comparing against the shadow map.
+    pub bias: f32,
+    /// Number of cascades the view distance is split into.
+    pub cascade_count: u32,
+}
+
+impl Default for ShadowSettings {
+    fn default() -> Self {
+        Self {
+            resolution: 2048,
+            bias: 0.002,
+            cascade_count: 3,
+        }
+    }
+}
+
+impl ShadowSettings {
+    /// The settings with a resolution the device supports and a valid cascade count.
+    pub fn clamped(self, limits: &wgpu::Limits) -> Self {
+        Self {
+            resolution: self.resolution.clamp(1, limits.max_texture_dimension_2d),
+            cascade_count: self.cascade_count.clamp(1, MAX_SHADOW_CASCADES as u32),
+            ..self
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct ShadowBufferData {
+    /// Light View Projection Matrix of every cascade
+    pub light_view_proj: [[[f32; 4]; 4]; MAX_SHADOW_CASCADES],
+    /// Far view distance of every cascade
+    pub cascade_splits: [f32; MAX_SHADOW_CASCADES],
+    /// Direction the sun light travels in, w is unused
+    pub sun_direction: [f32; 4],
+    pub bias: f32,
+    pub cascade_count: u32,
+    pub texel_size: f32,
+    _padding: f32,
+}
+
+/// Cascaded shadow map of the sun.
+/// Renders the scene depth from the sun's point of view, once per cascade.
+pub struct ShadowMap {
+    pub settings: ShadowSettings,
+    /// Direction the sun light travels in.
+    pub sun_direction: Vec3<f32>,
+    /// Draws the bounds and light volume of every cascade.
+    pub show_cascades: bool,
+    texture: Texture,
+    cascade_views: Vec<wgpu::TextureView>,
+    sampler: wgpu::Sampler,
+    pipeline: Arc<wgpu::RenderPipeline>,
+    /// Alpha tested depth pipeline of the [RenderLayer::Cutout] casters.
+    cutout_pipeline: Arc<wgpu::RenderPipeline>,
+    data: ShadowBufferData,
+    buffer: Buffer<ShadowBufferData>,
+    cascade_buffers: Vec<Buffer<[[f32; 4]; 4]>>,
+    cascade_bind_groups: Vec<wgpu::BindGroup>,
+    bind_group_layout: wgpu::BindGroupLayout,
+    bind_group: wgpu::BindGroup,
+}
+
+impl ShadowMap {
+    pub fn new(
+        device: &wgpu::Device,
+        pipelines: &mut PipelineCache,
+        shaders: &mut ShaderManager,
+        settings: ShadowSettings,
+    ) -> Self {
+        let settings = settings.clamped(&device.limits());
+        let cascade_bind_group_layout =
+            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                entries: &[wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::VERTEX,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                }],
+                label: Some("shadow_cascade_bind_group_layout"),
+            });
+
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::VERTEX_FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Depth,
+                        view_dimension: wgpu::TextureViewDimension::D2Array,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Comparison),
+                    count: None,
+                },
+            ],
+            label: Some("shadow_bind_group_layout"),
+        });
+
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Shadow Pipeline Layout Descriptor"),
+            bind_group_layouts: &[&cascade_bind_group_layout],
+            push_constant_ranges: &[],
+        });
+
+        pipelines.add_layout(SHADOW_LAYOUT, pipeline_layout);
+        let pipeline = pipelines.get(device, shaders, &pipeline_desc(false));
+        let cutout_pipeline = pipelines.get(device, shaders, &pipeline_desc(true));
+
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("Shadow Sampler"),
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            mipmap_filter: wgpu::FilterMode::Nearest,
+            compare: Some(wgpu::CompareFunction::LessEqual),
+            ..Default::default()
+        });
+
+        let data = ShadowBufferData {
+            light_view_proj: [Mat4::<f32>::identity().into_col_arrays(); MAX_SHADOW_CASCADES],
+            cascade_splits: [FAR_PLANE; MAX_SHADOW_CASCADES],
+            sun_direction: [0.0; 4],
+            bias: settings.bias,
+            cascade_count: settings.cascade_count,
+            texel_size: 1.0 / settings.resolution as f32,
+            _padding: 0.0,
+        };
+        let buffer = Buffer::new(
+            device,
+            &[data],
+            BufferUsages::UNIFORM | BufferUsages::COPY_DST,
+        );
+
+        let cascade_buffers = (0..MAX_SHADOW_CASCADES)
+            .map(|_| {
+                Buffer::new(
+                    device,
+                    &[Mat4::<f32>::identity().into_col_arrays()],
+                    BufferUsages::UNIFORM | BufferUsages::COPY_DST,
+                )
+            })
+            .collect::<Vec<_>>();
+        let cascade_bind_groups = cascade_buffers
+            .iter()
+            .map(|buffer| {
+                device.create_bind_group(&wgpu::BindGroupDescriptor {
+                    layout: &cascade_bind_group_layout,
+                    entries: &[wgpu::BindGroupEntry {
+                        binding: 0,
+                        resource: buffer.data().as_entire_binding(),
+                    }],
+                    label: Some("shadow_cascade_bind_group"),
+                })
+            })
+            .collect();
+
+        let (texture, cascade_views) = Self::create_texture(device, &settings);
+        let bind_group =
+            Self::create_bind_group(device, &bind_group_layout, &buffer, &texture, &sampler);
+
+        Self {
+            settings,
+            sun_direction: Vec3::new(0.4, -1.0, 0.3).normalized(),
+            show_cascades: false,
+            texture,
+            cascade_views,
+            sampler,
+            pipeline,
+            cutout_pipeline,
+            data,
+            buffer,
+            cascade_buffers,
+            cascade_bind_groups,
+            bind_group_layout,
+            bind_group,
+        }
+    }
+
+    /// Fetches the depth pipelines again after the cache was reloaded.
+    pub fn update_pipeline(
+        &mut self,
+        device: &wgpu::Device,
+        pipelines: &mut PipelineCache,
+        shaders: &mut ShaderManager,
+    ) {
+        self.pipeline = pipelines.get(device, shaders, &pipeline_desc(false));
+        self.cutout_pipeline = pipelines.get(device, shaders, &pipeline_desc(true));
+    }
+
+    /// The layout of the bind group sampled by the main pass.
+    pub fn bind_group_layout(&self) -> &wgpu::BindGroupLayout {
+        &self.bind_group_layout
+    }
+
+    /// The bind group sampled by the main pass.
+    pub fn bind_group(&self) -> &wgpu::BindGroup {
+        &self.bind_group
+    }
+
+    /// Applies new settings, recreating the shadow map texture when its size changed.
+    pub fn set_settings(&mut self, device: &wgpu::Device, settings: ShadowSettings) {
+        let settings = settings.clamped(&device.limits());
+        if settings.resolution != self.settings.resolution
+            || settings.cascade_count != self.settings.cascade_count
+        {
+            let (texture, cascade_views) = Self::create_texture(device, &settings);
+            self.texture = texture;
+            self.cascade_views = cascade_views;
+            self.bind_group = Self::create_bind_group(
+                device,
+                &self.bind_group_layout,
+                &self.buffer,
+                &self.texture,
+                &self.sampler,
+            );
+        }
+        self.settings = settings;
+    }
+
+    /// Fits every cascade around its slice of the camera frustum and uploads the matrices.
+    pub fn update(&mut self, queue: &wgpu::Queue, camera: &Camera, width: f32, height: f32) {
+        let count = self.settings.cascade_count as usize;
+        let splits = cascade_splits(count);
+        let mut near = NEAR_PLANE;
+        for (cascade, &far) in splits.iter().enumerate().take(count) {
+            let corners = camera.frustum_corners(width, height, near, far);
+            let (center, radius) = bounding_sphere(&corners);
+            let matrix = self.fit_cascade(center, radius);
+            if self.show_cascades {
+                let color = CASCADE_COLORS[cascade];
+                debug_draw::sphere(center, radius, color);
+                debug_draw::frustum(matrix, color);
+            }
+            self.data.light_view_proj[cascade] = matrix.into_col_arrays();
+            self.cascade_buffers[cascade].update(queue, &[matrix.into_col_arrays()], 0);
+            near = far;
+        }
+        self.data.cascade_splits = splits;
+        self.data.sun_direction = self.sun_direction.with_w(0.0).into_array();
+        self.data.bias = self.settings.bias;
+        self.data.cascade_count = self.settings.cascade_count;
+        self.data.texel_size = 1.0 / self.settings.resolution as f32;
+        self.buffer.update(queue, &[self.data], 0);
+    }
+
+    /// Renders the depth of the shadow casters into every cascade.
+    pub fn render(&self, encoder: &mut wgpu::CommandEncoder, casters: &[&Mesh]) {
+        for (view, bind_group) in self
+            .cascade_views
+            .iter()
+            .zip(&self.cascade_bind_groups)
+            .take(self.settings.cascade_count as usize)
+        {
+            let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("Shadow Pass"),
+                color_attachments: &[],
+                depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                    view,
+                    depth_ops: Some(wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(1.0),
+                        store: true,
+                    }),
+                    stencil_ops: None,
+                }),
+            });
+            render_pass.set_bind_group(0, bind_group, &[]);
+            for (pipeline, cutout) in [(&self.pipeline, false), (&self.cutout_pipeline, true)] {
+                render_pass.set_pipeline(pipeline);
+                casters
+                    .iter()
+                    .filter(|mesh| (mesh.layer == RenderLayer::Cutout) == cutout)
+                    .for_each(|mesh| mesh.draw(&mut render_pass));
+            }
+        }
+    }
+
+    /// Builds an orthographic light matrix enclosing the frustum slice.
+    /// The bounds are a sphere snapped to whole texels so shadows don't shimmer
+    /// when the camera moves or rotates.
+    fn fit_cascade(&self, center: Vec3<f32>, radius: f32) -> Mat4<f32> {
+        let up = if self.sun_direction.y.abs() > 0.99 {
+            Vec3::unit_z()
+        } else {
+            Vec3::unit_y()
+        };
+        let light_view = Mat4::look_at_lh(Vec3::zero(), self.sun_direction, up);
+
+        let texel = 2.0 * radius / self.settings.resolution as f32;
+        let center = light_view.mul_point(center);
+        let x = (center.x / texel).floor() * texel;
+        let y = (center.y / texel).floor() * texel;
+
+        // Extend the near plane so that casters between the sun and the frustum are kept.
+        let projection = Mat4::orthographic_lh_zo(FrustumPlanes {
+            left: x - radius,
+            right: x + radius,
+            bottom: y - radius,
+            top: y + radius,
+            near: center.z - radius - FAR_PLANE,
+            far: center.z + radius,
+        });
+        projection * light_view
+    }
+
+    fn create_texture(
+        device: &wgpu::Device,
+        settings: &ShadowSettings,
+    ) -> (Texture, Vec<wgpu::TextureView>) {
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("Shadow Map"),
+            size: wgpu::Extent3d {
+                width: settings.resolution,
+                height: settings.resolution,
+                depth_or_array_layers: settings.cascade_count,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: Texture::DEPTH_FORMAT,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
+        });
+        let view = texture.create_view(&wgpu::TextureViewDescriptor {
+            label: Some("Shadow Map View"),
+            dimension: Some(wgpu::TextureViewDimension::D2Array),
+            ..Default::default()
+        });
+        let cascade_views = (0..settings.cascade_count)
+            .map(|layer| {
+                texture.create_view(&wgpu::TextureViewDescriptor {
+                    label: Some("Shadow Cascade View"),
+                    dimension: Some(wgpu::TextureViewDimension::D2),
+                    base_array_layer: layer,
+                    array_layer_count: std::num::NonZeroU32::new(1),
+                    ..Default::default()
+                })
+            })
+            .collect();
+        (Texture { texture, view }, cascade_views)
+    }
+
+    fn create_bind_group(
+        device: &wgpu::Device,
+        layout: &wgpu::BindGroupLayout,
+        buffer: &Buffer<ShadowBufferData>,
+        texture: &Texture,
+        sampler: &wgpu::Sampler,
+    ) -> wgpu::BindGroup {
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: buffer.data().as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::TextureView(&texture.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: wgpu::BindingResource::Sampler(sampler),
+                },
+            ],
+            label: Some("shadow_bind_group"),
+        })
+    }
+}
+
+/// Only depth is written. Both faces cast shadows, otherwise single sided geometry would leak light.
+/// The `cutout` pipeline discards the fragments below the alpha cutoff.
+fn pipeline_desc(cutout: bool) -> PipelineDesc {
+    let label = if cutout {
+        "Cutout Shadow Render Pipeline"
+    } else {
+        "Shadow Render Pipeline"
+    };
+    let desc = PipelineDesc::new(label, SHADOW_LAYOUT, "shadow.wgsl")
+        .vertex_buffer(Vertex::layout())
+        .depth(DepthState {
+            compare: wgpu::CompareFunction::LessEqual,
+            bias_constant: 2,
+            bias_slope_scale: 2.0,
+            ..DepthState::new(Texture::DEPTH_FORMAT, true)
+        });
+    if cutout {
+        desc.depth_only_fragment("fs_alpha_test")
+    } else {
+        desc
+    }
+}
+
+/// Returns a sphere enclosing a frustum slice, its radius is rounded
+/// so that it doesn't change, and the shadows don't shimmer, as the camera rotates.
+fn bounding_sphere(corners: &[Vec3<f32>; 8]) -> (Vec3<f32>, f32) {
+    let center = corners.iter().copied().sum::<Vec3<f32>>() / corners.len() as f32;
+    let radius = corners
+        .iter()
+        .map(|corner| corner.distance(center))
+        .fold(0.0f32, f32::max);
+    (center, (radius * 16.0).ceil() / 16.0)
+}
+
+/// Splits the view distance into `count` cascades, returning the far distance of each one.
+/// Unused entries are set to [FAR_PLANE].
+fn cascade_splits(count: usize) -> [f32; MAX_SHADOW_CASCADES] {
+    let mut splits = [FAR_PLANE; MAX_SHADOW_CASCADES];
+    for (i, split) in splits.iter_mut().enumerate().take(count) {
+        let p = (i + 1) as f32 / count as f32;
+        let log = NEAR_PLANE * (FAR_PLANE / NEAR_PLANE).powf(p);
+        let uniform = NEAR_PLANE + (FAR_PLANE - NEAR_PLANE) * p;
+        *split = CASCADE_SPLIT_LAMBDA * log + (1.0 - CASCADE_SPLIT_LAMBDA) * uniform;
+    }
+    splits
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cascade_splits_increase_up_to_the_far_plane() {
+        for count in 1..=MAX_SHADOW_CASCADES {
+            let splits = cascade_splits(count);
+            let mut near = NEAR_PLANE;
+            for &split in &splits[..count] {
+                assert!(split > near, "{:?} with {} cascades", splits, count);
+                near = split;
+            }
+            assert!((splits[count - 1] - FAR_PLANE).abs() < 1e-3, "{:?}", splits);
+            assert!(splits[count..].iter().all(|&split| split == FAR_PLANE));
+        }
+    }
+
+    #[test]
+    fn clamps_the_settings_to_the_device() {
+        let limits = wgpu::Limits::downlevel_defaults();
+        let settings = ShadowSettings {
+            resolution: 0,
+            cascade_count: 9,
+            ..ShadowSettings::default()
+        }
+        .clamped(&limits);
+        assert_eq!((settings.resolution, settings.cascade_count), (1, 4));
+        let settings = ShadowSettings {
+            resolution: u32::MAX,
+            cascade_count: 0,
+            ..ShadowSettings::default()
+        }
+        .clamped(&limits);
+        assert_eq!(settings.resolution, limits.max_texture_dimension_2d);
+        assert_eq!(settings.cascade_count, 1);
+    }
+}
@@ -0,0 +1,180 @@
+use std::sync::Arc;
+
+use tracing::{debug, warn};
+
+use crate::error::RendererError;
+
+/// A surface frames are acquired from, implemented by [WindowSurface] and by mocks in tests.
+pub trait RenderSurface {
+    type Texture;
+
+    fn get_current_texture(&mut self) -> Result<Self::Texture, wgpu::SurfaceError>;
+    /// Recreates the swapchain with the current configuration.
+    fn configure(&mut self);
+}
+
+/// The window surface with the device and configuration used to reconfigure it.
+pub struct WindowSurface<'a> {
+    pub surface: &'a wgpu::Surface,
+    pub device: &'a wgpu::Device,
+    pub config: &'a wgpu::SurfaceConfiguration,
+}
+
+impl RenderSurface for WindowSurface<'_> {
+    type Texture = wgpu::SurfaceTexture;
+
+    fn get_current_texture(&mut self) -> Result<Self::Texture, wgpu::SurfaceError> {
+        self.surface.get_current_texture()
+    }
+
+    fn configure(&mut self) {
+        self.surface.configure(self.device, self.config);
+    }
+}
+
+/// A frame being rendered, to the window or to an offscreen texture when headless.
+pub enum Frame {
+    Window(wgpu::SurfaceTexture),
+    Offscreen(Arc<wgpu::Texture>),
+}
+
+impl Frame {
+    pub fn texture(&self) -> &wgpu::Texture {
+        match self {
+            Self::Window(surface_texture) => &surface_texture.texture,
+            Self::Offscreen(texture) => texture,
+        }
+    }
+
+    /// Shows the frame in the window, offscreen frames are only rendered.
+    pub fn present(self) {
+        if let Self::Window(surface_texture) = self {
+            surface_texture.present();
+        }
+    }
+}
+
+/// Acquires the next texture of `surface`, recovering from the errors that allow it.
+///
+/// - `Lost` and `Outdated` reconfigure the surface and try again once, a surface still lost
+///   after that means the device was lost.
+/// - `Timeout` skips the frame, returning `None`.
+/// - `OutOfMemory` can't be recovered from and is returned.
+pub fn acquire<S: RenderSurface>(surface: &mut S) -> Result<Option<S::Texture>, RendererError> {
+    let mut lost = false;
+    for attempt in 0..2 {
+        let result = surface.get_current_texture();
+        lost = matches!(result, Err(wgpu::SurfaceError::Lost));
+        match result {
+            Ok(texture) => return Ok(Some(texture)),
+            Err(error @ (wgpu::SurfaceError::Lost | wgpu::SurfaceError::Outdated)) => {
+                debug!(?error, attempt, "Reconfiguring the surface");
+                surface.configure();
+            }
+            Err(wgpu::SurfaceError::Timeout) => {
+                warn!("Timed out acquiring the surface texture, skipping the frame");
+                return Ok(None);
+            }
+            Err(error @ wgpu::SurfaceError::OutOfMemory) => return Err(error.into()),
+        }
+    }
+    if lost {
+        return Err(RendererError::DeviceLost);
+    }
+    warn!("The surface is still outdated after reconfiguring it, skipping the frame");
+    Ok(None)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::VecDeque;
+
+    use super::*;
+
+    /// Returns the queued results, then textures.
+    struct MockSurface {
+        results: VecDeque<Result<u32, wgpu::SurfaceError>>,
+        configured: u32,
+    }
+
+    impl MockSurface {
+        fn new(results: impl IntoIterator<Item = Result<u32, wgpu::SurfaceError>>) -> Self {
+            Self {
+                results: results.into_iter().collect(),
+                configured: 0,
+            }
+        }
+    }
+
+    impl RenderSurface for MockSurface {
+        type Texture = u32;
+
+        fn get_current_texture(&mut self) -> Result<u32, wgpu::SurfaceError> {
+            self.results.pop_front().unwrap_or(Ok(0))
+        }
+
+        fn configure(&mut self) {
+            self.configured += 1;
+        }
+    }
+
+    #[test]
+    fn returns_the_texture() {
+        let mut surface = MockSurface::new([Ok(7)]);
+        assert_eq!(acquire(&mut surface).unwrap(), Some(7));
+        assert_eq!(surface.configured, 0);
+    }
+
+    #[test]
+    fn reconfigures_lost_and_outdated_surfaces() {
+        for error in [wgpu::SurfaceError::Lost, wgpu::SurfaceError::Outdated] {
+            let mut surface = MockSurface::new([Err(error), Ok(1)]);
+            assert_eq!(acquire(&mut surface).unwrap(), Some(1));
+            assert_eq!(surface.configured, 1);
+        }
+    }
+
+    #[test]
+    fn skips_the_frame_if_reconfiguring_fails() {
+        let mut surface = MockSurface::new([
+            Err(wgpu::SurfaceError::Outdated),
+            Err(wgpu::SurfaceError::Outdated),
+            Ok(1),
+        ]);
+        assert_eq!(acquire(&mut surface).unwrap(), None);
+        assert_eq!(surface.configured, 2);
+        assert_eq!(acquire(&mut surface).unwrap(), Some(1));
+    }
+
+    #[test]
+    fn reports_the_device_lost_if_reconfiguring_fails() {
+        let mut surface =
+            MockSurface::new([Err(wgpu::SurfaceError::Lost), Err(wgpu::SurfaceError::Lost)]);
+        assert!(matches!(
+            acquire(&mut surface),
+            Err(RendererError::DeviceLost)
+        ));
+    }
+
+    #[test]
+    fn skips_the_frame_on_timeout() {
+        let mut surface = MockSurface::new([Err(wgpu::SurfaceError::Timeout), Ok(1)]);
+        assert_eq!(acquire(&mut surface).unwrap(), None);
+        assert_eq!(surface.configured, 0);
+        assert_eq!(acquire(&mut surface).unwrap(), Some(1));
+    }
+
+    #[test]
+    fn fails_when_out_of_memory() {
+        let mut surface = MockSurface::new([Err(wgpu::SurfaceError::OutOfMemory)]);
+        assert!(matches!(
+            acquire(&mut surface),
+            Err(RendererError::SurfaceError(wgpu::SurfaceError::OutOfMemory))
+        ));
+        let mut surface = MockSurface::new([
+            Err(wgpu::SurfaceError::Lost),
+            Err(wgpu::SurfaceError::OutOfMemory),
+        ]);
+        assert!(acquire(&mut surface).is_err());
+    }
+}
@@ -5,7 +5,8 @@ use wgpu::VertexBufferLayout;
 #[derive(Debug, Zeroable, Clone, Copy, Pod)]
 pub struct Vertex {
     pub position: [f32; 3],
-    pub color: [f32; 3],
+    /// Linear RGBA color, alpha is used by the cutout and translucent layers.
+    pub color: [f32; 4],
 }
 
 impl Vertex {
@@ -22,6 +23,59 @@ impl Vertex {
                 wgpu::VertexAttribute {
                     offset: std::mem::size_of::<[f32; 3]>() as wgpu::BufferAddress,
                     shader_location: 1,
+                    format: wgpu::VertexFormat::Float32x4,
+                },
+            ],
+        }
+    }
+}
+
+/// A [Vertex] of a non-indexed triangle list, with its barycentric coordinates in the triangle.
+/// Used to draw wireframes when [wgpu::PolygonMode::Line] isn't supported.
+#[repr(C)]
+#[derive(Debug, Zeroable, Clone, Copy, Pod)]
+pub struct BarycentricVertex {
+    pub position: [f32; 3],
+    pub color: [f32; 4],
+    pub barycentric: [f32; 3],
+}
+
+impl BarycentricVertex {
+    /// Unrolls indexed triangles.
+    pub fn triangles(vertices: &[Vertex], indices: &[u16]) -> Vec<Self> {
+        const CORNERS: [[f32; 3]; 3] = [[1.0, 0.0, 0.0], [0.0, 1.0, 0.0], [0.0, 0.0, 1.0]];
+        indices
+            .iter()
+            .zip(CORNERS.iter().cycle())
+            .map(|(&index, &barycentric)| {
+                let vertex = vertices[index as usize];
+                Self {
+                    position: vertex.position,
+                    color: vertex.color,
+                    barycentric,
+                }
+            })
+            .collect()
+    }
+
+    pub fn layout() -> VertexBufferLayout<'static> {
+        VertexBufferLayout {
+            array_stride: std::mem::size_of::<BarycentricVertex>() as wgpu::BufferAddress,
+            step_mode: wgpu::VertexStepMode::Vertex,
+            attributes: &[
+                wgpu::VertexAttribute {
+                    offset: 0,
+                    format: wgpu::VertexFormat::Float32x3,
+                    shader_location: 0,
+                },
+                wgpu::VertexAttribute {
+                    offset: std::mem::size_of::<[f32; 3]>() as wgpu::BufferAddress,
+                    shader_location: 1,
+                    format: wgpu::VertexFormat::Float32x4,
+                },
+                wgpu::VertexAttribute {
+                    offset: std::mem::size_of::<[f32; 7]>() as wgpu::BufferAddress,
+                    shader_location: 2,
                     format: wgpu::VertexFormat::Float32x3,
                 },
             ],
@@ -32,24 +86,87 @@ impl Vertex {
 pub const VERTICES: &[Vertex] = &[
     Vertex {
         position: [-0.0868241, 0.49240386, 0.0],
-        color: [1.0, 0.4, 1.0],
+        color: [1.0, 0.4, 1.0, 1.0],
     },
     Vertex {
         position: [-0.49513406, 0.06958647, 0.0],
-        color: [1.0, 0.3, 0.4],
+        color: [1.0, 0.3, 0.4, 1.0],
     },
     Vertex {
         position: [-0.21918549, -0.44939706, 0.0],
-        color: [1.0, 1.0, 1.0],
+        color: [1.0, 1.0, 1.0, 1.0],
     },
     Vertex {
         position: [0.35966998, -0.3473291, 0.0],
-        color: [0.4, 0.0, 1.0],
+        color: [0.4, 0.0, 1.0, 1.0],
     },
     Vertex {
         position: [0.44147372, 0.2347359, 0.0],
-        color: [0.5, 0.5, 0.5],
+        color: [0.5, 0.5, 0.5, 1.0],
     },
 ];
 
 pub const INDICES: &[u16] = &[0, 1, 4, 1, 2, 4, 2, 3, 4];
+
+/// A sheet of water below the scene.
+pub const WATER_VERTICES: &[Vertex] = &[
+    Vertex {
+        position: [-2.0, -0.6, -2.0],
+        color: [0.1, 0.3, 0.8, 0.6],
+    },
+    Vertex {
+        position: [-2.0, -0.6, 2.0],
+        color: [0.1, 0.3, 0.8, 0.6],
+    },
+    Vertex {
+        position: [2.0, -0.6, 2.0],
+        color: [0.1, 0.4, 0.9, 0.6],
+    },
+    Vertex {
+        position: [2.0, -0.6, -2.0],
+        color: [0.1, 0.4, 0.9, 0.6],
+    },
+];
+
+/// A glass pane in front of the scene.
+pub const GLASS_VERTICES: &[Vertex] = &[
+    Vertex {
+        position: [-0.4, -0.4, -1.0],
+        color: [0.8, 0.9, 1.0, 0.25],
+    },
+    Vertex {
+        position: [0.4, -0.4, -1.0],
+        color: [0.8, 0.9, 1.0, 0.25],
+    },
+    Vertex {
+        position: [0.4, 0.4, -1.0],
+        color: [0.8, 0.9, 1.0, 0.25],
+    },
+    Vertex {
+        position: [-0.4, 0.4, -1.0],
+        color: [0.8, 0.9, 1.0, 0.25],
+    },
+];
+
+/// A foliage quad, the alpha gradient is cut off into a hard edge.
+pub const LEAVES_VERTICES: &[Vertex] = &[
+    Vertex {
+        position: [0.6, -0.5, 0.5],
+        color: [0.2, 0.6, 0.1, 1.0],
+    },
+    Vertex {
+        position: [1.4, -0.5, 0.5],
+        color: [0.2, 0.6, 0.1, 0.0],
+    },
+    Vertex {
+        position: [1.4, 0.3, 0.5],
+        color: [0.1, 0.5, 0.1, 1.0],
+    },
+    Vertex {
+        position: [0.6, 0.3, 0.5],
+        color: [0.1, 0.5, 0.1, 0.0],
+    },
+];
+
+/// Indices of a quad made of two triangles.
+pub const QUAD_INDICES: &[u16] = &[0, 1, 2, 0, 2, 3];
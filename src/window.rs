@@ -1,32 +1,120 @@
+use std::{
+    fs::File,
+    io,
+    path::Path,
+    time::{Duration, Instant},
+};
+
+use serde::{Deserialize, Serialize};
+use tracing::{info, warn};
 use vek::Vec2;
 use winit::{
+    dpi::{PhysicalPosition, PhysicalSize},
     event::{self, WindowEvent},
     event_loop::{ControlFlow, EventLoop},
-    window,
+    window::{self, CursorGrabMode},
 };
 
 use crate::{
     error::Error,
+    graphics::GraphicsSettings,
     renderer::{self, Renderer},
+    settings::WindowSettings,
 };
 
+pub const TITLE: &str = "Rusty Sandbox";
+/// Read when the window is created, the window keeps the platform icon if it's missing.
+pub const ICON_PATH: &str = "assets/icon.png";
+/// The window can't be resized below this size, the UI panel wouldn't fit.
+pub const MIN_SIZE: PhysicalSize<u32> = PhysicalSize::new(640, 480);
+/// Largest width and height accepted, the maximum texture size of most GPUs.
+pub const MAX_SIZE: u32 = 16384;
+/// How often the frame statistics of the title are updated.
+const TITLE_STATS_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Fullscreen mode used by F11 and `--fullscreen`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FullscreenMode {
+    /// A borderless window covering the monitor.
+    #[default]
+    Borderless,
+    /// Changes the video mode of the monitor to the largest one with the highest refresh rate.
+    Exclusive,
+}
+
+/// How the window is created, from the settings and the command-line options.
+pub struct WindowOptions {
+    pub settings: WindowSettings,
+    /// Starts in fullscreen, in the mode of the settings.
+    pub fullscreen: bool,
+}
+
 pub struct Window {
     winit: window::Window,
     resolution: Vec2<u32>,
+    fullscreen_mode: FullscreenMode,
+    /// Size and position while windowed, saved with the settings.
+    windowed_size: PhysicalSize<u32>,
+    windowed_position: Option<PhysicalPosition<i32>>,
+    title_stats: Option<TitleStats>,
+    focused: bool,
+    /// Minimized, which is a resize to 0x0 on Windows, or occluded on the other platforms.
+    hidden: bool,
+    cursor_grabbed: bool,
+    /// Whether the cursor was grabbed when the window lost the focus.
+    grab_on_focus: bool,
+}
+
+/// Frames counted since the title was last updated.
+struct TitleStats {
+    frames: u32,
+    since: Instant,
 }
 
 impl Window {
-    pub fn new() -> Result<(Self, EventLoop<()>, Renderer), Error> {
+    pub fn new(
+        options: WindowOptions,
+        graphics: GraphicsSettings,
+    ) -> Result<(Self, EventLoop<()>, Renderer), Error> {
+        let settings = options.settings;
         let event_loop = EventLoop::new();
-        let builder = window::WindowBuilder::new().with_title("Rusty Sandbox");
-        let window = builder.build(&event_loop).unwrap();
+        let mut builder = window::WindowBuilder::new()
+            .with_title(TITLE)
+            .with_min_inner_size(MIN_SIZE);
+        if let Some([width, height]) = settings.size {
+            let size = PhysicalSize::new(width.max(MIN_SIZE.width), height.max(MIN_SIZE.height));
+            builder = builder.with_inner_size(size);
+        }
+        if let Some([x, y]) = settings.position {
+            builder = builder.with_position(PhysicalPosition::new(x, y));
+        }
+        match load_icon(Path::new(ICON_PATH)) {
+            Ok(icon) => builder = builder.with_window_icon(Some(icon)),
+            Err(error) => warn!(error = crate::error::report(&error), "No window icon"),
+        }
+        let window = builder.build(&event_loop)?;
 
         let size = window.inner_size();
-        let this = Self {
+        let mut this = Self {
+            windowed_size: size,
+            windowed_position: window.outer_position().ok(),
             winit: window,
             resolution: Vec2::new(size.width, size.height),
+            fullscreen_mode: settings.fullscreen_mode,
+            title_stats: settings.title_stats.then(|| TitleStats {
+                frames: 0,
+                since: Instant::now(),
+            }),
+            focused: true,
+            hidden: false,
+            cursor_grabbed: false,
+            grab_on_focus: false,
         };
-        let renderer = renderer::Renderer::new(&this)?;
+        if options.fullscreen {
+            this.set_fullscreen(true);
+        }
+        let renderer = renderer::Renderer::new(&this, graphics)?;
 
         Ok((this, event_loop, renderer))
     }
@@ -45,12 +133,20 @@ impl Window {
                 self.on_close();
                 *control_flow = ControlFlow::Exit;
             }
-            WindowEvent::Resized(size) => renderer.resize(*size),
+            WindowEvent::Resized(size) => {
+                self.on_resize(*size);
+                renderer.resize(*size)
+            }
             // Not sure when is this even emitted.
             WindowEvent::ScaleFactorChanged { new_inner_size, .. } => {
+                self.on_resize(**new_inner_size);
                 renderer.resize(**new_inner_size)
             }
-            WindowEvent::KeyboardInput { input, .. } => {}
+            WindowEvent::Moved(position) if !self.is_fullscreen() => {
+                self.windowed_position = Some(*position);
+            }
+            WindowEvent::Focused(focused) => self.on_focus(*focused),
+            WindowEvent::Occluded(occluded) => self.hidden = *occluded,
             _ => (),
         }
     }
@@ -58,4 +154,152 @@ impl Window {
         &self.resolution
     }
     pub fn on_close(&mut self) {}
+
+    fn on_resize(&mut self, size: PhysicalSize<u32>) {
+        self.resolution = Vec2::new(size.width, size.height);
+        self.hidden = size.width == 0 || size.height == 0;
+        // Minimizing on Windows resizes the window to 0x0.
+        if !self.is_fullscreen() && size.width > 0 && size.height > 0 {
+            self.windowed_size = size;
+        }
+    }
+
+    /// The cursor is released while the window is unfocused, and grabbed again on focus if it
+    /// was grabbed before.
+    fn on_focus(&mut self, focused: bool) {
+        self.focused = focused;
+        if focused {
+            if self.grab_on_focus {
+                self.set_cursor_grab(true);
+            }
+        } else {
+            self.grab_on_focus = self.cursor_grabbed;
+            self.set_cursor_grab(false);
+        }
+    }
+
+    /// Whether the sandbox is paused, because the window is unfocused or minimized.
+    pub fn is_paused(&self) -> bool {
+        !self.focused || self.hidden
+    }
+
+    pub fn cursor_grabbed(&self) -> bool {
+        self.cursor_grabbed
+    }
+
+    /// Confines and hides the cursor so that the mouse only moves the camera, or releases it.
+    pub fn set_cursor_grab(&mut self, grab: bool) {
+        if grab == self.cursor_grabbed {
+            return;
+        }
+        let result = if grab {
+            // Locked isn't supported on Windows and X11, Confined isn't on macOS.
+            self.winit
+                .set_cursor_grab(CursorGrabMode::Locked)
+                .or_else(|_| self.winit.set_cursor_grab(CursorGrabMode::Confined))
+        } else {
+            self.winit.set_cursor_grab(CursorGrabMode::None)
+        };
+        if let Err(error) = result {
+            warn!(%error, grab, "Failed to change the cursor grab");
+            return;
+        }
+        self.winit.set_cursor_visible(!grab);
+        self.cursor_grabbed = grab;
+    }
+
+    pub fn is_fullscreen(&self) -> bool {
+        self.winit.fullscreen().is_some()
+    }
+
+    /// Switches to the [FullscreenMode] of the settings on the current monitor, or back to the
+    /// windowed size.
+    pub fn set_fullscreen(&mut self, fullscreen: bool) {
+        if !fullscreen {
+            self.winit.set_fullscreen(None);
+            return;
+        }
+        let monitor = self.winit.current_monitor();
+        let mode = match self.fullscreen_mode {
+            FullscreenMode::Borderless => Some(window::Fullscreen::Borderless(monitor)),
+            FullscreenMode::Exclusive => monitor
+                .and_then(|monitor| {
+                    monitor.video_modes().max_by_key(|mode| {
+                        let size = mode.size();
+                        (size.width * size.height, mode.refresh_rate_millihertz())
+                    })
+                })
+                .map(window::Fullscreen::Exclusive),
+        };
+        match mode {
+            Some(mode) => {
+                info!(?mode, "Entering fullscreen");
+                self.winit.set_fullscreen(Some(mode));
+            }
+            None => warn!(mode = ?self.fullscreen_mode, "No monitor to go fullscreen on"),
+        }
+    }
+
+    /// Counts a presented frame, updating the frame statistics of the title if enabled.
+    pub fn on_frame(&mut self) {
+        let Some(stats) = &mut self.title_stats else {
+            return;
+        };
+        stats.frames += 1;
+        let elapsed = stats.since.elapsed();
+        if elapsed >= TITLE_STATS_INTERVAL {
+            let frame_time = elapsed / stats.frames;
+            self.winit.set_title(&format!(
+                "{} - {:.0} fps ({:.2} ms)",
+                TITLE,
+                1.0 / frame_time.as_secs_f64(),
+                frame_time.as_secs_f64() * 1000.0
+            ));
+            stats.frames = 0;
+            stats.since = Instant::now();
+        }
+    }
+
+    /// The windowed size and position, to restore them on the next run.
+    pub fn settings(&self) -> WindowSettings {
+        WindowSettings {
+            size: Some([self.windowed_size.width, self.windowed_size.height]),
+            position: self
+                .windowed_position
+                .map(|position| [position.x, position.y]),
+            fullscreen_mode: self.fullscreen_mode,
+            title_stats: self.title_stats.is_some(),
+        }
+    }
+}
+
+/// Decodes a PNG icon, with or without transparency.
+fn load_icon(path: &Path) -> Result<window::Icon, Error> {
+    let asset_error = |source: io::Error| Error::Asset {
+        path: path.to_path_buf(),
+        source,
+    };
+    let invalid =
+        |message: String| asset_error(io::Error::new(io::ErrorKind::InvalidData, message));
+
+    let mut decoder = png::Decoder::new(File::open(path).map_err(asset_error)?);
+    decoder.set_transformations(png::Transformations::normalize_to_color8());
+    let mut reader = decoder
+        .read_info()
+        .map_err(|error| asset_error(error.into()))?;
+    let mut pixels = vec![0; reader.output_buffer_size()];
+    let info = reader
+        .next_frame(&mut pixels)
+        .map_err(|error| asset_error(error.into()))?;
+    pixels.truncate(info.buffer_size());
+    let rgba = match info.color_type {
+        png::ColorType::Rgba => pixels,
+        png::ColorType::Rgb => pixels
+            .chunks_exact(3)
+            .flat_map(|rgb| [rgb[0], rgb[1], rgb[2], u8::MAX])
+            .collect(),
+        color_type => return Err(invalid(format!("unsupported color type {:?}", color_type))),
+    };
+    window::Icon::from_rgba(rgba, info.width, info.height)
+        .map_err(|error| invalid(error.to_string()))
 }